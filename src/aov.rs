@@ -0,0 +1,208 @@
+// Arbitrary output variables: buffers describing what the camera rays hit first, saved next
+// to the image for debugging and denoising. The integrators fill a `FirstHit` during the
+// first bounce of every sample.
+//
+// Albedo, normal and emission are the mean over the samples of a pixel. Depth, primitive
+// and material are those of the first sample of the pixel, as their mean has no meaning.
+// None of them is filtered.
+use std::sync::OnceLock;
+
+use crate::{
+    color::{ColorSpace, Xyz},
+    math::{Lanes, Ray, Vec3},
+    scene::Scene,
+    shape::{Intersection, Primitive},
+    spectrum::{
+        illuminant::CieIlluminant,
+        tabulated::TabulatedSpectrum,
+        wavelength::{LAMBDA_MAX_NM, LAMBDA_MIN_NM},
+        SampleableSpectrum,
+        SpectralSample,
+        Wavelength,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    Primitive,
+    Material,
+    Emission,
+}
+
+// Names and descriptions of the AOVs
+pub const AOVS: [(&str, &str); 6] = [
+    ("albedo", "reflectance of the first surface hit, as seen under D65"),
+    ("normal", "world space shading normal of the first hit"),
+    ("depth", "distance from the camera to the first hit, infinite for none"),
+    ("primitive", "index of the first primitive hit, -1 for none"),
+    ("material", "index of the material of the first hit, -1 for none"),
+    ("emission", "radiance emitted by the first hit"),
+];
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "albedo" => Aov::Albedo,
+            "normal" => Aov::Normal,
+            "depth" => Aov::Depth,
+            "primitive" => Aov::Primitive,
+            "material" => Aov::Material,
+            "emission" => Aov::Emission,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Primitive => "primitive",
+            Aov::Material => "material",
+            Aov::Emission => "emission",
+        }
+    }
+
+    // Whether the pixel holds the mean over its samples rather than the first one
+    pub fn is_averaged(self) -> bool {
+        match self {
+            Aov::Albedo | Aov::Normal | Aov::Emission => true,
+            Aov::Depth | Aov::Primitive | Aov::Material => false,
+        }
+    }
+}
+
+// What a camera ray hit first, a miss until recorded
+#[derive(Debug, Clone, Copy)]
+pub struct FirstHit {
+    pub albedo: Xyz,
+    pub normal: Vec3,
+    pub depth: f32,
+    pub primitive: Option<usize>,
+    pub material: Option<usize>,
+    pub emission: Xyz,
+}
+
+impl FirstHit {
+    pub fn miss() -> Self {
+        Self {
+            albedo: Xyz::new(0.0, 0.0, 0.0),
+            normal: Vec3::splat(0.0),
+            depth: f32::INFINITY,
+            primitive: None,
+            material: None,
+            emission: Xyz::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Called with the first intersection of the camera ray
+    pub fn record_surface<L: Lanes>(
+        &mut self,
+        scene: &Scene,
+        ray: &Ray,
+        prim: &Primitive,
+        hit: &Intersection,
+        wavelength: Wavelength<L>,
+    ) {
+        self.normal = hit.normal;
+        self.depth = ray.o().distance(hit.point);
+        self.primitive = Some(scene.primitive_index(prim));
+        self.material = scene.material_index(prim);
+        if let Some(light) = prim.get_light(&scene.lights) {
+            self.emission = spectrum_to_xyz(light.evaluate(wavelength), wavelength);
+        }
+    }
+
+    // Called with the BSDF sampled at the first intersection, `bsdf_values * cos_theta / pdf`
+    // estimates the directional albedo
+    pub fn record_albedo<L: Lanes>(
+        &mut self,
+        bsdf_values: SpectralSample<L>,
+        cos_theta: f32,
+        pdf: f32,
+        wavelength: Wavelength<L>,
+    ) {
+        if pdf > 0.0 {
+            let reflectance = bsdf_values * (cos_theta / pdf);
+            self.albedo = reflectance_to_xyz(reflectance, wavelength);
+        }
+    }
+
+    pub fn value(&self, aov: Aov, color_space: ColorSpace) -> (f32, f32, f32) {
+        let index = |index: Option<usize>| {
+            let value = index.map_or(-1.0, |i| i as f32);
+            (value, value, value)
+        };
+        match aov {
+            Aov::Albedo => self.albedo.to_rgb(color_space),
+            Aov::Normal => (self.normal.x(), self.normal.y(), self.normal.z()),
+            Aov::Depth => (self.depth, self.depth, self.depth),
+            Aov::Primitive => index(self.primitive),
+            Aov::Material => index(self.material),
+            Aov::Emission => self.emission.to_rgb(color_space),
+        }
+    }
+}
+
+// Every wavelength of the bundle contributes, weighted by the balance heuristic over the
+// wavelength pdfs as with HWSS
+fn spectrum_to_xyz<L: Lanes>(value: SpectralSample<L>, wavelength: Wavelength<L>) -> Xyz {
+    let pdfs = wavelength.pdfs();
+    (pdfs / pdfs.sum() * value).to_xyz(wavelength)
+}
+
+// Color of a reflectance lit by D65, normalized so that a perfect white reflector is white
+fn reflectance_to_xyz<L: Lanes>(reflectance: SpectralSample<L>, wavelength: Wavelength<L>) -> Xyz {
+    static D65: OnceLock<(TabulatedSpectrum, f32)> = OnceLock::new();
+    let (d65, luminance) = D65.get_or_init(|| {
+        let d65 = CieIlluminant::from_name("d65").unwrap().spectrum();
+        let luminance = (LAMBDA_MIN_NM as usize..=LAMBDA_MAX_NM as usize)
+            .map(|lambda| Xyz::from_wavelength(lambda as f32, d65.evaluate_single(lambda as f32)).y())
+            .sum::<f32>();
+        (d65, luminance)
+    });
+    spectrum_to_xyz(reflectance * d65.evaluate(wavelength), wavelength) / *luminance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        math::Vec4,
+        sampling::Sampler,
+        spectrum::{wavelength::WavelengthSampling, ConstantSpectrum},
+    };
+
+    #[test]
+    fn test_names() {
+        for &(name, _) in &AOVS {
+            assert_eq!(Aov::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(Aov::from_name("beauty"), None);
+    }
+
+    #[test]
+    fn test_white_albedo() {
+        // a perfect white reflector comes out white, on average over the wavelengths
+        let mut sum = (0.0, 0.0, 0.0);
+        let samples = 4096;
+        for i in 0..samples {
+            let mut sampler = Sampler::new(0, 0, i, 1);
+            let wavelength = Wavelength::<Vec4>::sample(&mut sampler, WavelengthSampling::Uniform);
+            let mut first_hit = FirstHit::miss();
+            first_hit.record_albedo(ConstantSpectrum::new(1.0).evaluate(wavelength), 0.5, 0.5, wavelength);
+            let (r, g, b) = first_hit.value(Aov::Albedo, ColorSpace::Srgb);
+            sum = (sum.0 + r, sum.1 + g, sum.2 + b);
+        }
+        for value in &[sum.0, sum.1, sum.2] {
+            assert!((value / samples as f32 - 1.0).abs() < 0.02, "{:?}", sum);
+        }
+
+        let first_hit = FirstHit::miss();
+        assert_eq!(first_hit.value(Aov::Primitive, ColorSpace::Srgb), (-1.0, -1.0, -1.0));
+        assert_eq!(first_hit.value(Aov::Depth, ColorSpace::Srgb).0, f32::INFINITY);
+    }
+}
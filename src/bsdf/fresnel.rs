@@ -2,7 +2,7 @@
 #![allow(unused)]
 use crate::{
     bsdf::SampleableBsdf,
-    math::{self, PdfSet, Shading, Vec3, Vec4},
+    math::{self, Lanes, PdfSet, Shading, Vec3},
     sampling::{self, Sampler},
     spectrum::{SampleableSpectrum, SpectralSample, Spectrum, Wavelength},
 };
@@ -27,41 +27,36 @@ impl FresnelBsdf {
         }
     }
 
-    fn refractive_index(&self, wavelength: Wavelength) -> Vec4 {
+    fn refractive_index<L: Lanes>(&self, wavelength: Wavelength<L>) -> L {
         //1.5220 + 0.00459 / (wavelength.inner * wavelength.inner * 1e-6)
-        self.base_ior + self.dispersion / (wavelength.inner * wavelength.inner * 1e-6)
+        L::splat(self.dispersion) / (wavelength.inner * wavelength.inner * 1e-6) + self.base_ior
     }
 }
 
 impl SampleableBsdf for FresnelBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         wi: Vec3<Shading>,
         wo: Vec3<Shading>,
-        wavelength: Wavelength,
-    ) -> SpectralSample {
+        wavelength: Wavelength<L>,
+    ) -> SpectralSample<L> {
         SpectralSample::splat(0.0)
     }
 
-    fn pdf(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, wavelength: Wavelength) -> PdfSet {
+    fn pdf<L: Lanes>(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, wavelength: Wavelength<L>) -> PdfSet<L> {
         PdfSet::splat(0.0)
     }
 
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         wo: Vec3<Shading>,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet) {
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>) {
         // TODO: SIMD this
         let eta_a = 1.0;
         let eta_b = self.refractive_index(wavelength);
-        let fresnel = Vec4::new(
-            math::fresnel_dielectric(wo.cos_theta(), eta_a, eta_b.x()),
-            math::fresnel_dielectric(wo.cos_theta(), eta_a, eta_b.y()),
-            math::fresnel_dielectric(wo.cos_theta(), eta_a, eta_b.z()),
-            math::fresnel_dielectric(wo.cos_theta(), eta_a, eta_b.w()),
-        );
+        let fresnel = L::from_fn(|i| math::fresnel_dielectric(wo.cos_theta(), eta_a, eta_b.lane(i)));
 
         if sampler.gen_0_1() < fresnel.hero() {
             let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
@@ -87,8 +82,8 @@ impl SampleableBsdf for FresnelBsdf {
                 let hero_value = ft.hero() / wi.cos_theta().abs();
                 (
                     wi,
-                    SpectralSample::new(hero_value, 0.0, 0.0, 0.0),
-                    PdfSet::new(1.0 - fresnel.hero(), 0.0, 0.0, 0.0),
+                    SpectralSample::hero_only(hero_value),
+                    PdfSet::hero_only(1.0 - fresnel.hero()),
                 )
             } else {
                 // Total internal reflection
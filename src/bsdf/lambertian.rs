@@ -2,7 +2,7 @@
 #![allow(unused)]
 use crate::{
     bsdf::SampleableBsdf,
-    math::{PdfSet, Shading, Vec3},
+    math::{Lanes, PdfSet, Shading, Vec3},
     sampling::{self, Sampler},
     spectrum::{SampleableSpectrum, SpectralSample, Spectrum, Wavelength},
 };
@@ -21,25 +21,25 @@ impl LambertianBsdf {
 }
 
 impl SampleableBsdf for LambertianBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         wi: Vec3<Shading>,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
-    ) -> SpectralSample {
+        hero_wavelength: Wavelength<L>,
+    ) -> SpectralSample<L> {
         self.albedo.evaluate(hero_wavelength) / PI
     }
 
-    fn pdf(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength) -> PdfSet {
+    fn pdf<L: Lanes>(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength<L>) -> PdfSet<L> {
         PdfSet::splat(sampling::pdf_cosine_unit_hemisphere(wi.cos_theta().abs()))
     }
 
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         wo: Vec3<Shading>,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet) {
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>) {
         let wi = sampling::cosine_unit_hemisphere(sampler.gen_0_1(), sampler.gen_0_1());
         let wi = if wo.same_hemisphere(wi) { wi } else { -wi };
         (
@@ -3,7 +3,7 @@
 use crate::{
     bsdf::SampleableBsdf,
     math,
-    math::{PdfSet, Shading, Vec3},
+    math::{Lanes, PdfSet, Shading, Vec3},
     sampling::{self, ggx, Sampler},
     spectrum::{SampleableSpectrum, SpectralSample, Spectrum, Wavelength},
 };
@@ -30,12 +30,12 @@ impl MicrofacetBsdf {
 }
 
 impl SampleableBsdf for MicrofacetBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         wi: Vec3<Shading>,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
-    ) -> SpectralSample {
+        hero_wavelength: Wavelength<L>,
+    ) -> SpectralSample<L> {
         let cos_theta_o = wo.cos_theta().abs();
         let cos_theta_i = wi.cos_theta().abs();
         let wh = wo + wi;
@@ -56,18 +56,18 @@ impl SampleableBsdf for MicrofacetBsdf {
         self.reflectance.evaluate(hero_wavelength) * d * g * f / (4.0 * cos_theta_o * cos_theta_i)
     }
 
-    fn pdf(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength) -> PdfSet {
+    fn pdf<L: Lanes>(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength<L>) -> PdfSet<L> {
         let wh = (wi + wo).normalize();
         let res = ggx::pdf(wo, wh, self.alpha_x, self.alpha_y) / (4.0 * wo.dot(wh));
         PdfSet::splat(res)
     }
 
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
+        hero_wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet) {
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>) {
         let wh = ggx::sample(wo, self.alpha_x, self.alpha_y, sampler);
         let wi = reflect(wo, wh);
 
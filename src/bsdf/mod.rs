@@ -1,5 +1,5 @@
 use crate::{
-    math::{PdfSet, Shading, Vec3},
+    math::{Lanes, PdfSet, Shading, Vec3},
     sampling::Sampler,
     spectrum::{SpectralSample, Wavelength},
 };
@@ -22,22 +22,22 @@ pub use null::NullBsdf;
 
 #[enum_dispatch]
 pub trait SampleableBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         wi: Vec3<Shading>,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
-    ) -> SpectralSample;
+        hero_wavelength: Wavelength<L>,
+    ) -> SpectralSample<L>;
 
-    fn pdf(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength) -> PdfSet;
+    fn pdf<L: Lanes>(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength<L>) -> PdfSet<L>;
 
     // Returns the sampled direction as well as the PDF for each wavelength
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
+        hero_wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet);
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>);
 
     fn is_specular(&self) -> bool {
         false
@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 use crate::{
     bsdf::SampleableBsdf,
-    math::{PdfSet, Shading, Vec3},
+    math::{Lanes, PdfSet, Shading, Vec3},
     sampling::Sampler,
     spectrum::{SpectralSample, Wavelength},
 };
@@ -16,25 +16,25 @@ impl NullBsdf {
 }
 
 impl SampleableBsdf for NullBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         _wi: Vec3<Shading>,
         _wo: Vec3<Shading>,
-        _hero_wavelength: Wavelength,
-    ) -> SpectralSample {
+        _hero_wavelength: Wavelength<L>,
+    ) -> SpectralSample<L> {
         unreachable!()
     }
 
-    fn pdf(&self, _wi: Vec3<Shading>, _wo: Vec3<Shading>, _hero_wavelength: Wavelength) -> PdfSet {
+    fn pdf<L: Lanes>(&self, _wi: Vec3<Shading>, _wo: Vec3<Shading>, _hero_wavelength: Wavelength<L>) -> PdfSet<L> {
         unreachable!()
     }
 
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         _wo: Vec3<Shading>,
-        _hero_wavelength: Wavelength,
+        _hero_wavelength: Wavelength<L>,
         _sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet) {
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>) {
         unreachable!()
     }
 }
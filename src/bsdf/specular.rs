@@ -2,7 +2,7 @@
 #![allow(unused)]
 use crate::{
     bsdf::SampleableBsdf,
-    math::{self, PdfSet, Shading, Vec3},
+    math::{self, Lanes, PdfSet, Shading, Vec3},
     sampling::{self, Sampler},
     spectrum::{SampleableSpectrum, SpectralSample, Spectrum, Wavelength},
 };
@@ -23,27 +23,27 @@ impl SpecularBsdf {
 }
 
 impl SampleableBsdf for SpecularBsdf {
-    fn evaluate(
+    fn evaluate<L: Lanes>(
         &self,
         wi: Vec3<Shading>,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
-    ) -> SpectralSample {
+        hero_wavelength: Wavelength<L>,
+    ) -> SpectralSample<L> {
         // only perfect specular
         SpectralSample::splat(0.0)
     }
 
-    fn pdf(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength) -> PdfSet {
+    fn pdf<L: Lanes>(&self, wi: Vec3<Shading>, wo: Vec3<Shading>, hero_wavelength: Wavelength<L>) -> PdfSet<L> {
         // only perfect specular
         PdfSet::splat(0.0)
     }
 
-    fn sample(
+    fn sample<L: Lanes>(
         &self,
         wo: Vec3<Shading>,
-        hero_wavelength: Wavelength,
+        hero_wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> (Vec3<Shading>, SpectralSample, PdfSet) {
+    ) -> (Vec3<Shading>, SpectralSample<L>, PdfSet<L>) {
         let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
         //let wi = Vec3::new(0.0, 0.0, 1.0);
         let fresnel = 1.0;//TODO
@@ -0,0 +1,607 @@
+// Bounding volume hierarchy over the primitives of a scene.
+//
+// The tree is first built as a binary hierarchy top-down, choosing splits with the surface
+// area heuristic evaluated over a fixed number of buckets along the axis of largest centroid
+// extent (pbrt 4.3.2). It is then collapsed into a 4-wide tree (QBVH, Dammertz et al. 2008)
+// whose nodes store the boxes of their children in SSE registers, so that a ray is tested
+// against all of them at once. Triangles in the leaves are stored in groups of four and
+// intersected together as well, other shapes are intersected one by one.
+//
+// The width matches the four lanes of `__m128`, an 8-wide tree would need AVX.
+use std::mem::MaybeUninit;
+
+use crate::{
+    math::{Bounds3, Bounds4, Point3, Ray, WideRay},
+    shape::{Geometry, Intersection, Primitive, Shape, Triangle, Triangle4},
+};
+
+const WIDTH: usize = 4;
+const BUCKETS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// cost of a box test relative to a primitive intersection
+const TRAVERSAL_COST: f32 = 0.125;
+// beyond this depth splits fall back to the median so that the traversal stack cannot overflow
+const MAX_SAH_DEPTH: usize = 64;
+// every level of the wide tree adds at most WIDTH - 1 entries
+const STACK_SIZE: usize = 320;
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { second_child: usize },
+}
+
+// Node of the binary tree, stored in depth first order so the first child of an interior
+// node directly follows it.
+#[derive(Debug, Clone)]
+struct BinaryNode {
+    bounds: Bounds3,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BuildPrimitive {
+    index: usize,
+    bounds: Bounds3,
+    centroid: Point3,
+}
+
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<BinaryNode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Child {
+    Empty,
+    Node(u32),
+    Leaf(u32),
+}
+
+#[derive(Clone, Copy)]
+struct WideNode {
+    bounds: Bounds4,
+    children: [Child; WIDTH],
+}
+
+// A leaf refers to a range of triangle groups and a range of other primitives
+#[derive(Debug, Clone, Copy)]
+struct Leaf {
+    first_group: usize,
+    groups: usize,
+    first_other: usize,
+    others: usize,
+}
+
+pub struct Bvh {
+    root: Child,
+    nodes: Vec<WideNode>,
+    leaves: Vec<Leaf>,
+    triangles: Vec<Triangle4>,
+    // primitive index for every lane of `triangles`, usize::MAX for unused lanes
+    triangle_indices: Vec<[usize; WIDTH]>,
+    others: Vec<usize>,
+    primitive_count: usize,
+}
+
+impl Default for Bvh {
+    fn default() -> Self {
+        Self {
+            root: Child::Empty,
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+            triangles: Vec::new(),
+            triangle_indices: Vec::new(),
+            others: Vec::new(),
+            primitive_count: 0,
+        }
+    }
+}
+
+impl Bvh {
+    pub fn build(primitives: &[Primitive]) -> Self {
+        let mut prims = primitives
+            .iter()
+            .enumerate()
+            .map(|(index, prim)| {
+                let bounds = prim.bounds();
+                BuildPrimitive {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut builder = Builder::default();
+        if !prims.is_empty() {
+            builder.build_node(&mut prims, 0, 0);
+        }
+        let indices = prims.iter().map(|p| p.index).collect::<Vec<_>>();
+
+        let mut bvh = Self {
+            primitive_count: primitives.len(),
+            ..Self::default()
+        };
+        if !builder.nodes.is_empty() {
+            bvh.root = bvh.collapse(&builder.nodes, 0, &indices, primitives);
+        }
+        bvh
+    }
+
+    // Number of primitives the hierarchy was built over
+    pub fn primitive_count(&self) -> usize {
+        self.primitive_count
+    }
+
+    // Turns the binary subtree at `node` into a wide one. Interior nodes absorb the
+    // children of their largest interior children until they have WIDTH of them.
+    fn collapse(
+        &mut self,
+        binary: &[BinaryNode],
+        node: usize,
+        indices: &[usize],
+        primitives: &[Primitive],
+    ) -> Child {
+        let second_child = match binary[node].kind {
+            NodeKind::Leaf { first, count } => {
+                let leaf = self.add_leaf(&indices[first..(first + count)], primitives);
+                return Child::Leaf(leaf as u32);
+            }
+            NodeKind::Interior { second_child } => second_child,
+        };
+
+        let mut children = vec![node + 1, second_child];
+        while children.len() < WIDTH {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &c)| match binary[c].kind {
+                    NodeKind::Interior { second_child } => Some((i, c, second_child)),
+                    NodeKind::Leaf { .. } => None,
+                })
+                .max_by(|a, b| {
+                    let a = binary[a.1].bounds.surface_area();
+                    let b = binary[b.1].bounds.surface_area();
+                    // NaN bounds must not panic
+                    a.total_cmp(&b)
+                });
+
+            match largest {
+                Some((i, c, second_child)) => {
+                    children[i] = c + 1;
+                    children.push(second_child);
+                }
+                None => break,
+            }
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideNode {
+            bounds: Bounds4::new([Bounds3::default(); WIDTH]),
+            children: [Child::Empty; WIDTH],
+        });
+
+        let mut bounds = [Bounds3::default(); WIDTH];
+        let mut wide_children = [Child::Empty; WIDTH];
+        for (lane, &c) in children.iter().enumerate() {
+            bounds[lane] = binary[c].bounds;
+            wide_children[lane] = self.collapse(binary, c, indices, primitives);
+        }
+        self.nodes[index] = WideNode {
+            bounds: Bounds4::new(bounds),
+            children: wide_children,
+        };
+
+        Child::Node(index as u32)
+    }
+
+    fn add_leaf(&mut self, indices: &[usize], primitives: &[Primitive]) -> usize {
+        let mut triangles: Vec<(usize, &Triangle)> = Vec::new();
+        let first_other = self.others.len();
+        for &i in indices {
+            match &primitives[i].geometry {
+                Geometry::Triangle(triangle) => triangles.push((i, triangle)),
+                _ => self.others.push(i),
+            }
+        }
+
+        let first_group = self.triangles.len();
+        for group in triangles.chunks(WIDTH) {
+            let mut lanes = [usize::MAX; WIDTH];
+            for (lane, &(i, _)) in group.iter().enumerate() {
+                lanes[lane] = i;
+            }
+            let group = group.iter().map(|&(_, t)| t).collect::<Vec<_>>();
+            self.triangles.push(Triangle4::new(&group));
+            self.triangle_indices.push(lanes);
+        }
+
+        self.leaves.push(Leaf {
+            first_group,
+            groups: self.triangles.len() - first_group,
+            first_other,
+            others: self.others.len() - first_other,
+        });
+        self.leaves.len() - 1
+    }
+
+    // Closest intersection along the ray, visiting the nearest children of each node first
+    pub fn intersect<'a>(
+        &self,
+        primitives: &'a [Primitive],
+        ray: &Ray,
+    ) -> Option<(&'a Primitive, Intersection)> {
+        let wide_ray = WideRay::new(ray);
+        let mut closest = None;
+        let mut t_max = f32::INFINITY;
+
+        self.traverse(&wide_ray, &mut t_max, |leaf, t_max| {
+            for group in leaf.first_group..(leaf.first_group + leaf.groups) {
+                let distances = self.triangles[group].intersect(&wide_ray, *t_max);
+                for (lane, &t) in distances.iter().enumerate() {
+                    if t < *t_max {
+                        *t_max = t;
+                        closest = Some(self.triangle_indices[group][lane]);
+                    }
+                }
+            }
+
+            for &i in &self.others[leaf.first_other..(leaf.first_other + leaf.others)] {
+                if let Some((_, t)) = primitives[i].intersect(ray) {
+                    if t > 0.0 && t < *t_max {
+                        *t_max = t;
+                        closest = Some(i);
+                    }
+                }
+            }
+            false
+        });
+
+        // only the closest primitive computes the full surface interaction
+        let prim = &primitives[closest?];
+        prim.intersect(ray).map(|(hit, _)| (prim, hit))
+    }
+
+    // Whether anything not rejected by `ignore` intersects the ray in [t_min, t_max).
+    // Stops at the first such blocker found.
+    pub fn intersect_any(
+        &self,
+        primitives: &[Primitive],
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        ignore: impl Fn(&Primitive) -> bool,
+    ) -> bool {
+        let wide_ray = WideRay::new(ray);
+        let mut t_max = t_max;
+        let blocks = |t: f32, t_max: f32, i: usize| t >= t_min && t < t_max && !ignore(&primitives[i]);
+
+        self.traverse(&wide_ray, &mut t_max, |leaf, t_max| {
+            for group in leaf.first_group..(leaf.first_group + leaf.groups) {
+                let distances = self.triangles[group].intersect(&wide_ray, *t_max);
+                for (lane, &t) in distances.iter().enumerate() {
+                    if blocks(t, *t_max, self.triangle_indices[group][lane]) {
+                        return true;
+                    }
+                }
+            }
+
+            self.others[leaf.first_other..(leaf.first_other + leaf.others)]
+                .iter()
+                .any(|&i| match primitives[i].intersect(ray) {
+                    Some((_, t)) => blocks(t, *t_max, i),
+                    None => false,
+                })
+        })
+    }
+
+    // Calls `visit` for every leaf whose box the ray overlaps in [0, t_max], nearest first.
+    // `visit` may shrink t_max, returning true ends the traversal.
+    fn traverse(
+        &self,
+        ray: &WideRay,
+        t_max: &mut f32,
+        mut visit: impl FnMut(&Leaf, &mut f32) -> bool,
+    ) -> bool {
+        // the stack is only read below `stack_len`, so it does not need to be initialized
+        let mut stack: [MaybeUninit<(Child, f32)>; STACK_SIZE] =
+            unsafe { MaybeUninit::uninit().assume_init() };
+        stack[0] = MaybeUninit::new((self.root, 0.0));
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (child, t_entry) = unsafe { stack[stack_len].assume_init() };
+            if t_entry > *t_max {
+                // entered behind the closest hit found since it was pushed
+                continue;
+            }
+
+            match child {
+                Child::Empty => {}
+                Child::Leaf(i) => {
+                    if visit(&self.leaves[i as usize], t_max) {
+                        return true;
+                    }
+                }
+                Child::Node(i) => {
+                    let node = &self.nodes[i as usize];
+                    let (mask, entry) = node.bounds.intersect(ray, *t_max);
+
+                    // insert the children hit sorted by decreasing entry distance,
+                    // so that the nearest is visited next
+                    let first = stack_len;
+                    for (lane, &lane_child) in node.children.iter().enumerate() {
+                        if mask & (1 << lane) == 0 || lane_child == Child::Empty {
+                            continue;
+                        }
+
+                        let mut j = stack_len;
+                        while j > first && unsafe { stack[j - 1].assume_init().1 } < entry[lane] {
+                            stack[j] = stack[j - 1];
+                            j -= 1;
+                        }
+                        stack[j] = MaybeUninit::new((lane_child, entry[lane]));
+                        stack_len += 1;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Builder {
+    fn build_node(&mut self, prims: &mut [BuildPrimitive], start: usize, depth: usize) -> usize {
+        let bounds = prims.iter().fold(Bounds3::default(), |b, p| b.union(p.bounds));
+        let node_index = self.nodes.len();
+        self.nodes.push(BinaryNode {
+            bounds,
+            kind: NodeKind::Leaf { first: start, count: prims.len() },
+        });
+
+        if prims.len() == 1 {
+            return node_index;
+        }
+
+        let centroid_bounds = prims
+            .iter()
+            .fold(Bounds3::default(), |b, p| b.union_point(p.centroid));
+        let axis = centroid_bounds.max_extent();
+        if centroid_bounds.offset(centroid_bounds.max, axis) == 0.0 {
+            // all centroids coincide, there is no meaningful split
+            return node_index;
+        }
+
+        let mid = if depth < MAX_SAH_DEPTH {
+            match Self::sah_split(prims, bounds, centroid_bounds, axis) {
+                Some(mid) => mid,
+                None => return node_index,
+            }
+        } else {
+            0
+        };
+
+        // too deep, or the buckets did not separate anything: split at the median instead
+        let mid = if mid == 0 || mid == prims.len() {
+            let mid = prims.len() / 2;
+            prims.select_nth_unstable_by(mid, |a, b| {
+                let a = centroid_bounds.offset(a.centroid, axis);
+                let b = centroid_bounds.offset(b.centroid, axis);
+                // a NaN centroid, e.g. of a sphere with a NaN center, must not panic
+                a.total_cmp(&b)
+            });
+            mid
+        } else {
+            mid
+        };
+
+        let (left, right) = prims.split_at_mut(mid);
+        self.build_node(left, start, depth + 1);
+        let second_child = self.build_node(right, start + mid, depth + 1);
+        self.nodes[node_index].kind = NodeKind::Interior { second_child };
+
+        node_index
+    }
+
+    // Partitions the primitives at the cheapest bucket boundary and returns the index of the
+    // first primitive on the right side, or None if a leaf is cheaper than any split.
+    fn sah_split(
+        prims: &mut [BuildPrimitive],
+        bounds: Bounds3,
+        centroid_bounds: Bounds3,
+        axis: usize,
+    ) -> Option<usize> {
+        let bucket_of = |p: &BuildPrimitive| {
+            let b = (BUCKETS as f32 * centroid_bounds.offset(p.centroid, axis)) as usize;
+            b.min(BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; BUCKETS];
+        let mut bucket_bounds = [Bounds3::default(); BUCKETS];
+        for p in prims.iter() {
+            let b = bucket_of(p);
+            counts[b] += 1;
+            bucket_bounds[b] = bucket_bounds[b].union(p.bounds);
+        }
+
+        // sweep from the right to get the cost of everything right of each boundary
+        let mut right_cost = [0.0f32; BUCKETS];
+        let (mut right_bounds, mut right_count) = (Bounds3::default(), 0);
+        for b in (1..BUCKETS).rev() {
+            right_bounds = right_bounds.union(bucket_bounds[b]);
+            right_count += counts[b];
+            right_cost[b] = right_count as f32 * right_bounds.surface_area();
+        }
+
+        let (mut left_bounds, mut left_count) = (Bounds3::default(), 0);
+        let (mut best_cost, mut best_bucket) = (f32::INFINITY, 0);
+        for b in 0..(BUCKETS - 1) {
+            left_bounds = left_bounds.union(bucket_bounds[b]);
+            left_count += counts[b];
+            let cost = left_count as f32 * left_bounds.surface_area() + right_cost[b + 1];
+            if cost < best_cost {
+                best_cost = cost;
+                best_bucket = b;
+            }
+        }
+
+        let area = bounds.surface_area();
+        let split_cost = if area > 0.0 {
+            TRAVERSAL_COST + best_cost / area
+        } else {
+            TRAVERSAL_COST
+        };
+        if prims.len() <= MAX_LEAF_SIZE && split_cost >= prims.len() as f32 {
+            return None;
+        }
+
+        let mut mid = 0;
+        for i in 0..prims.len() {
+            if bucket_of(&prims[i]) <= best_bucket {
+                prims.swap(i, mid);
+                mid += 1;
+            }
+        }
+        Some(mid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        math::Vec3,
+        shape::{Sphere, Triangle},
+    };
+
+    // small deterministic generator so the test does not depend on the sampler
+    fn random(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (*state >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn random_point(state: &mut u32) -> Point3 {
+        let v = Vec3::new(random(state), random(state), random(state));
+        (v * 4.0 - Vec3::splat(2.0)).to_point()
+    }
+
+    fn primitives() -> Vec<Primitive> {
+        let mut state = 7;
+        let mut shapes = Vec::new();
+        for i in 0..500 {
+            let p = random_point(&mut state);
+            if i % 5 == 0 {
+                shapes.push(Geometry::from(Sphere::new(p, 0.1 * random(&mut state))));
+            } else if i % 50 == 1 {
+                // a stack of identical triangles ends up in a single large leaf
+                let triangle = Triangle::new(p, p + Vec3::new(0.2, 0.0, 0.0), p + Vec3::new(0.0, 0.2, 0.0));
+                for _ in 0..9 {
+                    shapes.push(Geometry::from(triangle.clone()));
+                }
+            } else {
+                let e1 = Vec3::new(random(&mut state), random(&mut state), random(&mut state));
+                let e2 = Vec3::new(random(&mut state), random(&mut state), random(&mut state));
+                shapes.push(Geometry::from(Triangle::new(p, p + 0.3 * e1, p + 0.3 * e2)));
+            }
+        }
+        shapes
+            .into_iter()
+            .map(|geometry| Primitive::new_material(geometry, 0))
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_linear_search() {
+        let shapes = primitives();
+        let bvh = Bvh::build(&shapes);
+        assert_eq!(bvh.primitive_count(), shapes.len());
+
+        let mut state = 13;
+        for _ in 0..2000 {
+            let o = (random_point(&mut state).to_vec() * 2.0).to_point();
+            let target = random_point(&mut state);
+            let ray = Ray::new(o, target - o);
+
+            let expected = shapes
+                .iter()
+                .filter_map(|s| s.intersect(&ray).map(|(_, t)| t))
+                .filter(|&t| t > 0.0)
+                .fold(f32::INFINITY, f32::min);
+            let found = bvh.intersect(&shapes, &ray);
+
+            if expected.is_finite() {
+                let (prim, _) = found.unwrap();
+                assert_eq!(prim.intersect(&ray).unwrap().1, expected);
+                assert!(bvh.intersect_any(&shapes, &ray, 0.0, expected * 1.001, |_| false));
+                assert!(!bvh.intersect_any(&shapes, &ray, 0.0, expected * 0.999, |_| false));
+                assert!(!bvh.intersect_any(&shapes, &ray, 0.0, expected * 1.001, |p| {
+                    p.intersect(&ray).is_some_and(|(_, t)| t == expected)
+                }));
+                assert!(bvh.intersect_any(&shapes, &ray, expected * 0.999, expected * 1.001, |_| false));
+                assert!(!bvh.intersect_any(&shapes, &ray, expected * 1.001, expected * 1.001, |_| false));
+            } else {
+                assert!(found.is_none());
+                assert!(!bvh.intersect_any(&shapes, &ray, 0.0, f32::INFINITY, |_| false));
+            }
+        }
+    }
+
+    #[test]
+    fn test_signed_zero_direction() {
+        // many distant triangles so that the ray has to pass interior nodes, and one on the
+        // ray whose box straddles the plane x = 0 the ray runs in
+        let mut shapes = Vec::new();
+        for i in 0..64 {
+            let p = Point3::new(10.0 + (i % 8) as f32, -10.0 - (i / 8) as f32, 5.0 + i as f32);
+            shapes.push(Triangle::new(p, p + Vec3::new(0.5, 0.0, 0.0), p + Vec3::new(0.0, 0.5, 0.0)));
+        }
+        shapes.push(Triangle::new(
+            Point3::new(-1.0, -1.0, 2.0),
+            Point3::new(1.0, -1.0, 2.0),
+            Point3::new(0.0, 1.0, 2.0),
+        ));
+        let shapes = shapes
+            .into_iter()
+            .map(|triangle| Primitive::new_material(Geometry::from(triangle), 0))
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&shapes);
+
+        for &zero in &[0.0f32, -0.0] {
+            let ray = Ray::new(Point3::splat(0.0), Vec3::new(zero, zero, 1.0));
+            let (_, hit) = bvh.intersect(&shapes, &ray).unwrap();
+            assert_eq!(hit.point.z(), 2.0);
+            assert!(bvh.intersect_any(&shapes, &ray, 0.0, 3.0, |_| false));
+        }
+    }
+
+    #[test]
+    fn test_nan_centroids() {
+        // below the SAH depth the nodes are split at the median, where a NaN centroid, e.g.
+        // of a sphere with a NaN center, must not panic
+        let mut prims = (0..16)
+            .map(|index| {
+                let x = if index % 3 == 0 { f32::NAN } else { index as f32 };
+                let bounds = Sphere::new(Point3::new(x, 0.0, 0.0), 0.1).bounds();
+                BuildPrimitive { index, bounds, centroid: bounds.centroid() }
+            })
+            .collect::<Vec<_>>();
+        let mut builder = Builder::default();
+        builder.build_node(&mut prims, 0, MAX_SAH_DEPTH);
+        assert!(builder.nodes.len() > 1);
+
+        let mut indices = prims.iter().map(|prim| prim.index).collect::<Vec<_>>();
+        indices.sort_unstable();
+        assert_eq!(indices, (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_empty() {
+        let shapes: Vec<Primitive> = Vec::new();
+        let bvh = Bvh::build(&shapes);
+        let ray = Ray::new(Point3::splat(0.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(bvh.intersect(&shapes, &ray).is_none());
+    }
+}
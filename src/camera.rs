@@ -1,5 +1,6 @@
 use crate::math::{Camera as CameraCoord, Clip, Matrix, Point3, Vec3, World};
 
+#[derive(Debug)]
 pub struct Camera {
     pub position: Point3,
     pub world_to_clip: Matrix<World, Clip>,
@@ -8,8 +9,12 @@ pub struct Camera {
 
 impl Camera {
     pub fn new(pos: Point3, aspect_ratio: f32) -> Self {
+        Self::with_fov(pos, aspect_ratio, 90.0)
+    }
+
+    pub fn with_fov(pos: Point3, aspect_ratio: f32, fov: f32) -> Self {
         let camera_to_clip =
-            Matrix::<CameraCoord, Clip>::projection(aspect_ratio, 0.1, 100.0, 90.0);
+            Matrix::<CameraCoord, Clip>::projection(aspect_ratio, 0.1, 100.0, fov);
         let world_to_camera = Matrix::translation(-Vec3::new(pos.x, pos.y, pos.z));
         let world_to_clip = &camera_to_clip * &world_to_camera;
 
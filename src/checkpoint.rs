@@ -0,0 +1,348 @@
+// Checkpoints of renders to a file, so that a long render survives a crash or reboot. Saved
+// with `--checkpoint <seconds>` as `<output name>.checkpoint` and continued with `--resume`.
+//
+// A checkpoint holds the state of every tile: the filtered sums of its passes, its spectral
+// film, AOVs and moments, and how many samples it has left. The moments count the samples
+// of each pixel, which picks the Sobol sample indices where the render continues. As the
+// passes of a tile only depend on that state and the image is assembled from the tiles in a
+// fixed order, a resumed render is bit-identical to one that was never interrupted. With a
+// time limit the passes depend on time, so only the samples are reproducible then.
+//
+// The file is little endian: a magic number and version, the render settings as text, a
+// hash of the scene and then the tiles in the order of their index.
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    camera::Camera,
+    color::Xyz,
+    integrator::SpectralMode,
+    scene::Scene,
+    tile::{PixelSum, TileData},
+    variance::PixelMoments,
+    Render,
+};
+
+const MAGIC: &[u8; 8] = b"IRISCKPT";
+const VERSION: u32 = 1;
+
+// What the estimate of a pixel depends on besides the scene and the samples, which renders
+// added together have to share
+pub fn estimator_settings(render: &Render, spectral_mode: SpectralMode) -> String {
+    format!(
+        "size {}x{}, integrator {}, spectral {}, wavelengths {} {}, filter {} {:?}, color space {}",
+        render.width,
+        render.height,
+        render.integrator.name(),
+        // the others are named after theirs
+        if render.integrator.has_spectral_modes() { spectral_mode.name() } else { "fixed" },
+        render.wavelengths,
+        render.wavelength_sampling.name(),
+        render.filter.kind.name(),
+        render.filter.radius,
+        render.color_space.name(),
+    )
+}
+
+// Everything the samples depend on besides the scene, which a resumed render has to match
+pub fn settings(render: &Render, spectral_mode: SpectralMode) -> String {
+    let aovs = render.aovs.iter().map(|aov| aov.name()).collect::<Vec<_>>();
+    let adaptive = render
+        .adaptive
+        .map_or(String::from("none"), |adaptive| format!("{:?} {}", adaptive.threshold, adaptive.max_spp));
+    format!(
+        "{}, spp {}, seed {}, spectral film {}, aovs [{}], adaptive {}",
+        estimator_settings(render, spectral_mode),
+        render.spp,
+        render.seed,
+        render.spectral_bands,
+        aovs.join(","),
+        adaptive,
+    )
+}
+
+// FNV-1a hash of the debug output of the scene and camera, the geometry of meshes included.
+// Only meant to notice a changed scene, it is the same for the same build of the renderer.
+pub fn scene_hash(scene: &Scene, camera: &Camera) -> u64 {
+    struct Fnv(u64);
+
+    impl fmt::Write for Fnv {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for &byte in s.as_bytes() {
+                self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+            Ok(())
+        }
+    }
+
+    let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+    fmt::write(
+        &mut hash,
+        format_args!(
+            "{:?}{:?}{:?}{:?}{:?}",
+            scene.lights, scene.materials, scene.primitives, scene.material_indices, camera
+        ),
+    )
+    .unwrap();
+    hash.0
+}
+
+// Writes next to `path` first and renames it over, so that a crash while writing leaves
+// the previous checkpoint intact
+pub fn write(path: &str, settings: &str, scene_hash: u64, tiles: &[&TileData]) -> io::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    let mut w = BufWriter::new(File::create(&temporary)?);
+    write_to(&mut w, settings, scene_hash, tiles)?;
+    w.into_inner()?.sync_all()?;
+    std::fs::rename(&temporary, path)
+}
+
+// Reads the tiles of a checkpoint made with the same settings and scene
+pub fn read(path: &str, settings: &str, scene_hash: u64, render: &Render) -> io::Result<Vec<TileData>> {
+    let mut r = BufReader::new(File::open(path)?);
+    read_from(&mut r, settings, scene_hash, |idx| TileData::new(render, idx))
+}
+
+fn write_to<W: Write>(w: &mut W, settings: &str, scene_hash: u64, tiles: &[&TileData]) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(&(settings.len() as u32).to_le_bytes())?;
+    w.write_all(settings.as_bytes())?;
+    w.write_all(&scene_hash.to_le_bytes())?;
+    w.write_all(&(tiles.len() as u32).to_le_bytes())?;
+
+    let f32s = |w: &mut W, values: &[f32]| values.iter().try_for_each(|value| w.write_all(&value.to_le_bytes()));
+    let f64s = |w: &mut W, values: &[f64]| values.iter().try_for_each(|value| w.write_all(&value.to_le_bytes()));
+    for tile in tiles {
+        for &value in &[tile.idx, tile.remaining_samples, tile.active_pixels, tile.passes] {
+            w.write_all(&(value as u64).to_le_bytes())?;
+        }
+        f64s(w, &[tile.error])?;
+        for sum in &tile.sums {
+            f32s(w, &[sum.xyz.x(), sum.xyz.y(), sum.xyz.z(), sum.weight])?;
+        }
+        f32s(w, &tile.spectral_buffer)?;
+        for value in &tile.aov_buffer {
+            f32s(w, &[value.0, value.1, value.2])?;
+        }
+        for moments in &tile.moments {
+            w.write_all(&moments.count.to_le_bytes())?;
+            f64s(w, &moments.sum)?;
+            f64s(w, &moments.sum_squares)?;
+        }
+    }
+    Ok(())
+}
+
+// `new_tile` gives the empty tile of an index, which sets the size of its buffers
+fn read_from<R: Read, F: FnMut(usize) -> Option<TileData>>(
+    r: &mut R,
+    settings: &str,
+    scene_hash: u64,
+    mut new_tile: F,
+) -> io::Result<Vec<TileData>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid(String::from("not a checkpoint")));
+    }
+    let version = read_u32(r)?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported checkpoint version {}", version)));
+    }
+    let mut saved_settings = vec![0; read_u32(r)? as usize];
+    r.read_exact(&mut saved_settings)?;
+    let saved_settings = String::from_utf8_lossy(&saved_settings);
+    if saved_settings != settings {
+        return Err(invalid(format!("the checkpoint is of a render with other settings: {}", saved_settings)));
+    }
+    if read_u64(r)? != scene_hash {
+        return Err(invalid(String::from("the checkpoint is of a render of another scene")));
+    }
+
+    let count = read_u32(r)? as usize;
+    let mut tiles = Vec::with_capacity(count);
+    for _ in 0..count {
+        let idx = read_u64(r)? as usize;
+        let mut tile = new_tile(idx).ok_or_else(|| invalid(format!("no tile {} in the image", idx)))?;
+        tile.remaining_samples = read_u64(r)? as usize;
+        tile.active_pixels = read_u64(r)? as usize;
+        tile.passes = read_u64(r)? as usize;
+        tile.error = read_f64(r)?;
+        for sum in tile.sums.iter_mut() {
+            let (x, y, z) = (read_f32(r)?, read_f32(r)?, read_f32(r)?);
+            *sum = PixelSum {
+                xyz: Xyz::new(x, y, z),
+                weight: read_f32(r)?,
+            };
+        }
+        for value in tile.spectral_buffer.iter_mut() {
+            *value = read_f32(r)?;
+        }
+        for value in tile.aov_buffer.iter_mut() {
+            *value = (read_f32(r)?, read_f32(r)?, read_f32(r)?);
+        }
+        for moments in tile.moments.iter_mut() {
+            let mut loaded = PixelMoments::ZERO;
+            loaded.count = read_u32(r)?;
+            for value in loaded.sum.iter_mut().chain(loaded.sum_squares.iter_mut()) {
+                *value = read_f64(r)?;
+            }
+            *moments = loaded;
+        }
+        tiles.push(tile);
+    }
+
+    // every tile once
+    let mut indices = tiles.iter().map(|tile| tile.idx).collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices.dedup();
+    if indices.len() != count || new_tile(count).is_some() {
+        return Err(invalid(String::from("the checkpoint does not have every tile of the image")));
+    }
+    Ok(tiles)
+}
+
+pub fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(r)?))
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(r)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::tests::small_render;
+
+    fn empty_tile(idx: usize) -> Option<TileData> {
+        if idx >= 2 {
+            return None;
+        }
+        Some(TileData {
+            idx,
+            width: 2,
+            height: 1,
+            pixel_x: 2 * idx,
+            pixel_y: 0,
+            distance_from_center: 0.0,
+            remaining_samples: 8,
+            active_pixels: 0,
+            error: 0.0,
+            passes: 0,
+            margin: 1,
+            sums: vec![PixelSum::ZERO; 12],
+            spectral_buffer: vec![0.0; 2 * 3],
+            aov_buffer: vec![(0.0, 0.0, 0.0); 2],
+            moments: vec![PixelMoments::ZERO; 2],
+        })
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut tiles = [empty_tile(1).unwrap(), empty_tile(0).unwrap()];
+        tiles[0].remaining_samples = 3;
+        tiles[0].passes = 2;
+        tiles[0].error = 0.1;
+        tiles[0].sums[5] = PixelSum {
+            xyz: Xyz::new(0.1, 0.2, f32::MIN_POSITIVE),
+            weight: 5.0,
+        };
+        tiles[0].spectral_buffer[4] = 1.5;
+        tiles[0].aov_buffer[1] = (-1.0, f32::INFINITY, 0.3);
+        tiles[0].moments[1].add((0.1, 0.2, 0.3), 0.7);
+
+        let mut data = Vec::new();
+        write_to(&mut data, "settings", 42, &[&tiles[0], &tiles[1]]).unwrap();
+        let loaded = read_from(&mut data.as_slice(), "settings", 42, empty_tile).unwrap();
+        assert_eq!(loaded.len(), 2);
+        let (tile, loaded) = (&tiles[0], &loaded[0]);
+        assert_eq!((loaded.idx, loaded.remaining_samples, loaded.passes), (1, 3, 2));
+        assert_eq!(loaded.error, tile.error);
+        assert_eq!(loaded.sums[5].xyz.z(), f32::MIN_POSITIVE);
+        assert_eq!(loaded.sums[5].weight, 5.0);
+        assert_eq!(loaded.spectral_buffer, tile.spectral_buffer);
+        assert_eq!(loaded.aov_buffer, tile.aov_buffer);
+        assert_eq!(loaded.moments[1].count, 1);
+        assert_eq!(loaded.moments[1].sum, tile.moments[1].sum);
+        assert_eq!(loaded.moments[1].sum_squares, tile.moments[1].sum_squares);
+
+        // other settings, another scene or a missing tile are refused
+        assert!(read_from(&mut data.as_slice(), "other", 42, empty_tile).is_err());
+        assert!(read_from(&mut data.as_slice(), "settings", 43, empty_tile).is_err());
+        let mut data = Vec::new();
+        write_to(&mut data, "settings", 42, &[&tiles[0]]).unwrap();
+        assert!(read_from(&mut data.as_slice(), "settings", 42, empty_tile).is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let spectral_mode = SpectralMode::default();
+        let finish = |tiles: &mut [TileData], render: &Render| {
+            for tile in tiles.iter_mut() {
+                while !tile.is_done(render) {
+                    *tile = tile.clone().render(render);
+                }
+            }
+            TileData::resolve(tiles, render);
+        };
+
+        let straight = small_render(40);
+        let mut tiles = (0..).map_while(|idx| TileData::new(&straight, idx)).collect::<Vec<_>>();
+        finish(&mut tiles, &straight);
+
+        // paused with the tiles a different number of passes in, some not started
+        let paused = small_render(40);
+        let mut tiles = (0..).map_while(|idx| TileData::new(&paused, idx)).collect::<Vec<_>>();
+        for tile in tiles.iter_mut() {
+            for _ in 0..tile.idx % 5 {
+                *tile = tile.clone().render(&paused);
+            }
+        }
+        let paused_settings = settings(&paused, spectral_mode);
+        let hash = scene_hash(&paused.scene, &paused.camera);
+        let mut data = Vec::new();
+        write_to(&mut data, &paused_settings, hash, &tiles.iter().collect::<Vec<_>>()).unwrap();
+
+        // and resumed by a render of its own
+        let resumed = small_render(40);
+        assert_eq!(paused_settings, settings(&resumed, spectral_mode));
+        assert_eq!(hash, scene_hash(&resumed.scene, &resumed.camera));
+        let mut tiles = read_from(&mut data.as_slice(), &paused_settings, hash, |idx| TileData::new(&resumed, idx)).unwrap();
+        finish(&mut tiles, &resumed);
+
+        let bits = |render: &Render| {
+            let sums = render.pixel_sums.read().unwrap();
+            let moments = render.moments.read().unwrap();
+            let spectral = render.spectral_buffer.read().unwrap();
+            let mut bits = Vec::new();
+            for (sum, moments) in sums.iter().zip(moments.iter()) {
+                bits.extend([sum.xyz.x(), sum.xyz.y(), sum.xyz.z(), sum.weight].iter().map(|v| v.to_bits() as u64));
+                bits.push(moments.count as u64);
+                bits.extend(moments.sum.iter().chain(&moments.sum_squares).map(|v| v.to_bits()));
+            }
+            bits.extend(spectral.iter().map(|v| v.to_bits() as u64));
+            bits
+        };
+        assert!(straight.moments.read().unwrap().iter().all(|moments| moments.count == 40));
+        assert!(bits(&resumed) == bits(&straight), "the resumed render differs");
+    }
+}
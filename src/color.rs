@@ -1,5 +1,7 @@
 #![allow(clippy::excessive_precision, clippy::unreadable_literal)]
 
+use std::sync::OnceLock;
+
 use crate::spectrum::wavelength::{LAMBDA_MAX_NM, LAMBDA_MIN_NM};
 
 const CIE_SAMPLES: usize = 830 - 360 + 1;
@@ -19,10 +21,10 @@ impl Xyz {
 
     // TODO: We should be able to use SIMD for the lookups (_mm_i32gather_ps)
     pub fn from_wavelength(lambda: f32, mut value: f32) -> Self {
-        debug_assert!(lambda >= LAMBDA_MIN_NM && lambda <= LAMBDA_MAX_NM);
+        debug_assert!((LAMBDA_MIN_NM..=LAMBDA_MAX_NM).contains(&lambda));
         let index = (lambda as usize) - (LAMBDA_MIN_NM as usize);
 
-        value *= (LAMBDA_MAX_NM - LAMBDA_MIN_NM) / CIE_Y_INTEGRAL;
+        value /= CIE_Y_INTEGRAL;
 
         unsafe {
             Self {
@@ -33,23 +35,181 @@ impl Xyz {
         }
     }
 
-    pub fn to_rgb_hdr(self) -> (f32, f32, f32) {
-        let r = 3.240479 * self.x - 1.537150 * self.y - 0.498535 * self.z;
-        let g = -0.969256 * self.x + 1.875991 * self.y + 0.041556 * self.z;
-        let b = 0.055648 * self.x - 0.204043 * self.y + 1.057311 * self.z;
-        (r, g, b)
+    pub fn x(self) -> f32 {
+        self.x
+    }
+
+    pub fn y(self) -> f32 {
+        self.y
+    }
+
+    pub fn z(self) -> f32 {
+        self.z
     }
 
-    pub fn to_srgb(self) -> Srgb {
-        let (r, g, b) = self.to_rgb_hdr();
-        Srgb::new(
-            gamma_correct(tonemap(r)),
-            gamma_correct(tonemap(g)),
-            gamma_correct(tonemap(b)),
-        )
+    // Linear RGB in the given color space, without clamping
+    pub fn to_rgb(self, color_space: ColorSpace) -> (f32, f32, f32) {
+        let m = color_space.xyz_to_rgb();
+        let row = |i: usize| (m[i][0] * self.x as f64 + m[i][1] * self.y as f64 + m[i][2] * self.z as f64) as f32;
+        (row(0), row(1), row(2))
     }
 }
 
+// CIE Y matching function at `lambda`, tabulated in 1nm steps
+pub fn cie_y(lambda: f32) -> f32 {
+    debug_assert!((LAMBDA_MIN_NM..=LAMBDA_MAX_NM).contains(&lambda));
+    CIE_Y[(lambda as usize) - (LAMBDA_MIN_NM as usize)]
+}
+
+// Color matching functions linearly interpolated between their 1nm samples
+pub fn cie_xyz(lambda: f32) -> [f32; 3] {
+    let x = (lambda - LAMBDA_MIN_NM).max(0.0).min((CIE_SAMPLES - 1) as f32);
+    let i = (x as usize).min(CIE_SAMPLES - 2);
+    let t = x - i as f32;
+    let lerp = |table: &[f32; CIE_SAMPLES]| table[i] * (1.0 - t) + table[i + 1] * t;
+    [lerp(&CIE_X), lerp(&CIE_Y), lerp(&CIE_Z)]
+}
+
+// RGB color spaces for input colors and output images. The renderer works with a D65 white,
+// so the matrices of spaces with another white point include a Bradford adaptation to D65:
+// white in any space upsamples to the same spectrum and maps back to white.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    Rec2020,
+    AcesCg,
+}
+
+// Command line and scene file names of the color spaces with a short description, the first
+// is the default
+pub const COLOR_SPACES: [(&str, &str); 3] = [
+    ("srgb", "sRGB / Rec. 709 primaries, D65 white"),
+    ("rec2020", "Rec. 2020 primaries, D65 white"),
+    ("acescg", "ACES AP1 primaries, ACES white (about D60)"),
+];
+
+const D65_WHITE: [f64; 2] = [0.3127, 0.3290];
+
+struct ColorSpaceMatrices {
+    rgb_to_xyz: [[f64; 3]; 3],
+    xyz_to_rgb: [[f64; 3]; 3],
+}
+
+impl ColorSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "srgb" => ColorSpace::Srgb,
+            "rec2020" => ColorSpace::Rec2020,
+            "acescg" => ColorSpace::AcesCg,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::Rec2020 => "rec2020",
+            ColorSpace::AcesCg => "acescg",
+        }
+    }
+
+    // xy chromaticities of the red, green and blue primaries
+    pub fn primaries(self) -> [[f64; 2]; 3] {
+        match self {
+            ColorSpace::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            ColorSpace::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+            ColorSpace::AcesCg => [[0.713, 0.293], [0.165, 0.830], [0.128, 0.044]],
+        }
+    }
+
+    // xy chromaticity of the white point
+    pub fn white_point(self) -> [f64; 2] {
+        match self {
+            ColorSpace::Srgb | ColorSpace::Rec2020 => D65_WHITE,
+            ColorSpace::AcesCg => [0.32168, 0.33767],
+        }
+    }
+
+    pub fn rgb_to_xyz(self) -> [[f64; 3]; 3] {
+        self.matrices().rgb_to_xyz
+    }
+
+    pub fn xyz_to_rgb(self) -> [[f64; 3]; 3] {
+        self.matrices().xyz_to_rgb
+    }
+
+    fn matrices(self) -> &'static ColorSpaceMatrices {
+        static MATRICES: OnceLock<[ColorSpaceMatrices; 3]> = OnceLock::new();
+        let matrices = MATRICES.get_or_init(|| {
+            [ColorSpace::Srgb, ColorSpace::Rec2020, ColorSpace::AcesCg].map(|space| {
+                let rgb_to_xyz = space.compute_rgb_to_xyz();
+                ColorSpaceMatrices {
+                    rgb_to_xyz,
+                    xyz_to_rgb: invert(rgb_to_xyz),
+                }
+            })
+        });
+        &matrices[self as usize]
+    }
+
+    // The primaries scaled so that RGB (1, 1, 1) is the white point at Y = 1, then adapted to D65
+    fn compute_rgb_to_xyz(self) -> [[f64; 3]; 3] {
+        let to_xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+
+        let p = self.primaries().map(to_xyz);
+        let primaries = [
+            [p[0][0], p[1][0], p[2][0]],
+            [p[0][1], p[1][1], p[2][1]],
+            [p[0][2], p[1][2], p[2][2]],
+        ];
+        let white = to_xyz(self.white_point());
+        let s = mul_vector(invert(primaries), white);
+        let rgb_to_xyz = primaries.map(|row| [row[0] * s[0], row[1] * s[1], row[2] * s[2]]);
+
+        if self.white_point() == D65_WHITE {
+            return rgb_to_xyz;
+        }
+
+        const BRADFORD: [[f64; 3]; 3] = [
+            [0.8951, 0.2664, -0.1614],
+            [-0.7502, 1.7135, 0.0367],
+            [0.0389, -0.0685, 1.0296],
+        ];
+        let source = mul_vector(BRADFORD, white);
+        let target = mul_vector(BRADFORD, to_xyz(D65_WHITE));
+        // von Kries scaling of the cone responses: BRADFORD^-1 * diag(target / source) * BRADFORD
+        let mut scaled = BRADFORD;
+        for ((row, target), source) in scaled.iter_mut().zip(&target).zip(&source) {
+            for value in row.iter_mut() {
+                *value *= target / source;
+            }
+        }
+        let adaptation = mul(invert(BRADFORD), scaled);
+        mul(adaptation, rgb_to_xyz)
+    }
+}
+
+fn mul(a: [[f64; 3]; 3], b: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    a.map(|row| [0, 1, 2].map(|j| row.iter().zip(&b).map(|(a, b)| a * b[j]).sum()))
+}
+
+fn mul_vector(a: [[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    a.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (i0, i1) = ((i + 1) % 3, (i + 2) % 3);
+        let (j0, j1) = ((j + 1) % 3, (j + 2) % 3);
+        m[i0][j0] * m[i1][j1] - m[i0][j1] * m[i1][j0]
+    };
+    let det = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+    debug_assert!(det != 0.0);
+
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / det))
+}
+
 impl std::ops::Add for Xyz {
     type Output = Self;
 
@@ -106,42 +266,6 @@ impl std::ops::Div<f32> for Xyz {
     }
 }
 
-#[derive(Debug)]
-pub struct Srgb {
-    pub r: f32,
-    pub g: f32,
-    pub b: f32,
-}
-
-impl Srgb {
-    fn new(r: f32, g: f32, b: f32) -> Self {
-        Self {
-            r: (r * 255.99).clamp(0.0, 255.99),
-            g: (g * 255.99).clamp(0.0, 255.99),
-            b: (b * 255.99).clamp(0.0, 255.99),
-        }
-    }
-
-    pub fn to_u32(&self) -> u32 {
-        let (r, g, b) = (self.r as u32, self.g as u32, self.b as u32);
-        (r << 16) | (g << 8) | b as u32
-    }
-}
-
-fn tonemap(val: f32) -> f32 {
-    // Avoid division by zero
-    //val / (1.0 + val.abs())
-    val.clamp(0.0, 1.0)
-}
-
-fn gamma_correct(val: f32) -> f32 {
-    if val <= 0.0031308 {
-        12.92 * val
-    } else {
-        1.055 * val.powf(1.0 / 2.4) - 0.055
-    }
-}
-
 const CIE_X: [f32; CIE_SAMPLES] = [
     0.000000122200,
     0.000000185138,
@@ -1563,3 +1687,48 @@ const CIE_Z: [f32; CIE_SAMPLES] = [
     0.000000000000,
     0.000000000000,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_spaces() {
+        // the derived sRGB matrix matches the published one
+        let srgb = ColorSpace::Srgb.xyz_to_rgb();
+        let expected = [
+            [3.240479, -1.537150, -0.498535],
+            [-0.969256, 1.875991, 0.041556],
+            [0.055648, -0.204043, 1.057311],
+        ];
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((srgb[i][j] - expected[i][j]).abs() < 1e-3, "{:?}", srgb);
+            }
+        }
+
+        // white maps to D65 at unit luminance in every space, and back
+        let d65 = Xyz::new(0.3127 / 0.3290, 1.0, (1.0 - 0.3127 - 0.3290) / 0.3290);
+        for &(name, _) in COLOR_SPACES.iter() {
+            let space = ColorSpace::from_name(name).unwrap();
+            let (r, g, b) = d65.to_rgb(space);
+            for &c in &[r, g, b] {
+                assert!((c - 1.0).abs() < 1e-5, "{}: {:?}", name, (r, g, b));
+            }
+
+            let m = mul(space.rgb_to_xyz(), space.xyz_to_rgb());
+            for (i, row) in m.iter().enumerate() {
+                for (j, value) in row.iter().enumerate() {
+                    let identity = if i == j { 1.0 } else { 0.0 };
+                    assert!((value - identity).abs() < 1e-12);
+                }
+            }
+        }
+
+        // ACEScg is wider than sRGB: a saturated sRGB green stays inside it
+        let green = ColorSpace::Srgb.rgb_to_xyz();
+        let green = Xyz::new(green[0][1] as f32, green[1][1] as f32, green[2][1] as f32);
+        let (r, g, b) = green.to_rgb(ColorSpace::AcesCg);
+        assert!(r > 0.0 && g > 0.0 && b > 0.0, "{:?}", (r, g, b));
+    }
+}
@@ -0,0 +1,157 @@
+// Display pipeline turning the linear RGB of the render buffer into 8 bit values, shared by
+// the PNG writer and the progressive window: exposure, tone mapping, the sRGB transfer
+// curve and optional dithering, in that order. The high dynamic range formats bypass it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapper {
+    #[default]
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+// Names and descriptions of the tone mappers, the first one is the default
+pub const TONE_MAPPERS: [(&str, &str); 3] = [
+    ("clamp", "clip values above one"),
+    ("reinhard", "x / (1 + x) per channel"),
+    ("aces", "ACES filmic curve fit by Narkowicz (2015)"),
+];
+
+impl ToneMapper {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "clamp" => ToneMapper::Clamp,
+            "reinhard" => ToneMapper::Reinhard,
+            "aces" => ToneMapper::Aces,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "clamp",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::Aces => "aces",
+        }
+    }
+
+    // Maps a linear value to [0, 1]
+    pub fn apply(self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        let mapped = match self {
+            ToneMapper::Clamp => value,
+            ToneMapper::Reinhard => value / (1.0 + value),
+            ToneMapper::Aces => {
+                (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+            }
+        };
+        mapped.min(1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayPipeline {
+    // in stops, the linear values are scaled by 2^exposure
+    pub exposure: f32,
+    pub tone_mapper: ToneMapper,
+    pub dither: bool,
+}
+
+impl Default for DisplayPipeline {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::default(),
+            dither: false,
+        }
+    }
+}
+
+impl DisplayPipeline {
+    // 8 bit values of the pixel at (x, y), the position only matters for the dither
+    pub fn to_8bit(self, pixel: (f32, f32, f32), x: usize, y: usize) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+        let noise = if self.dither { dither_noise(x, y) } else { 0.0 };
+        let quantize = |value: f32| {
+            let encoded = srgb_oetf(self.tone_mapper.apply(value * scale));
+            (encoded * 255.0 + 0.5 + noise).clamp(0.0, 255.0) as u8
+        };
+        [quantize(pixel.0), quantize(pixel.1), quantize(pixel.2)]
+    }
+
+    // 0RGB as expected by the window
+    pub fn to_u32(self, pixel: (f32, f32, f32), x: usize, y: usize) -> u32 {
+        let [r, g, b] = self.to_8bit(pixel, x, y);
+        ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+    }
+}
+
+// sRGB transfer function from linear [0, 1] to the encoded value
+pub fn srgb_oetf(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Triangular noise in (-1, 1) of a quantization step, from a hash of the pixel position so
+// that the file and the window agree
+fn dither_noise(x: usize, y: usize) -> f32 {
+    let hash = |mut v: u32| {
+        v ^= v >> 16;
+        v = v.wrapping_mul(0x7feb_352d);
+        v ^= v >> 15;
+        v = v.wrapping_mul(0x846c_a68b);
+        v ^ (v >> 16)
+    };
+    let h = hash(x as u32 ^ hash(y as u32 ^ 0x9e37_79b9));
+    let u1 = (h & 0xffff) as f32 / 65536.0;
+    let u2 = (h >> 16) as f32 / 65536.0;
+    u1 - u2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tone_mappers() {
+        for &(name, _) in &TONE_MAPPERS {
+            let tone_mapper = ToneMapper::from_name(name).unwrap();
+            assert_eq!(tone_mapper.name(), name);
+            assert_eq!(tone_mapper.apply(0.0), 0.0);
+            assert_eq!(tone_mapper.apply(-1.0), 0.0);
+            assert!(tone_mapper.apply(1e6) <= 1.0);
+            assert!(tone_mapper.apply(0.5) < tone_mapper.apply(0.6));
+        }
+        assert_eq!(ToneMapper::Reinhard.apply(1.0), 0.5);
+        assert!((ToneMapper::Aces.apply(0.18) - 0.2667).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_display_pipeline() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.18) - 0.4614).abs() < 1e-3);
+
+        let mut display = DisplayPipeline::default();
+        assert_eq!(display.to_8bit((0.0, 0.18, 2.0), 0, 0), [0, 118, 255]);
+        assert_eq!(display.to_u32((1.0, 0.0, 0.0), 0, 0), 0xff0000);
+
+        // one stop up doubles the linear value
+        display.exposure = 1.0;
+        assert_eq!(display.to_8bit((0.09, 0.09, 0.09), 0, 0), [118, 118, 118]);
+
+        // the dither moves values by at most one step and is the same for a pixel every time
+        display.exposure = 0.0;
+        display.dither = true;
+        for y in 0..16 {
+            for x in 0..16 {
+                let [r, _, _] = display.to_8bit((0.18, 0.0, 0.0), x, y);
+                assert!((117..=119).contains(&r));
+                assert_eq!(display.to_8bit((0.18, 0.0, 0.0), x, y)[0], r);
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Spectral film: besides the color image, the radiance of each pixel can be kept in equally
+// wide wavelength bands over the visible range, to compare the noise of the spectral
+// sampling modes band by band. Enabled with `--spectral-film <bands>`.
+//
+// The bands are written as an ENVI image, a raw file (`.img`) of little endian 32 bit floats
+// with the bands of a pixel next to each other (band interleaved by pixel), rows from top to
+// bottom, and a text header (`.hdr`) describing it. Each value is the mean spectral radiance
+// in the band, per nm.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use crate::spectrum::wavelength::{LAMBDA_MAX_NM, LAMBDA_MIN_NM, LAMBDA_RANGE_NM};
+
+// Band a wavelength falls into
+pub fn band(lambda: f32, bands: usize) -> usize {
+    (((lambda - LAMBDA_MIN_NM) / LAMBDA_RANGE_NM * bands as f32) as usize).min(bands - 1)
+}
+
+// Wavelength range of a band
+pub fn band_range(band: usize, bands: usize) -> (f32, f32) {
+    let width = LAMBDA_RANGE_NM / bands as f32;
+    let start = LAMBDA_MIN_NM + band as f32 * width;
+    (start, (start + width).min(LAMBDA_MAX_NM))
+}
+
+// Writes `<base>.img` and `<base>.hdr`
+pub fn write_envi(base: &str, width: usize, height: usize, bands: usize, data: &[f32]) -> io::Result<()> {
+    assert_eq!(data.len(), width * height * bands);
+
+    let mut image = BufWriter::new(File::create(format!("{}.img", base))?);
+    for value in data {
+        image.write_all(&value.to_le_bytes())?;
+    }
+    image.flush()?;
+
+    let mut header = BufWriter::new(File::create(format!("{}.hdr", base))?);
+    header.write_all(envi_header(width, height, bands).as_bytes())?;
+    header.flush()
+}
+
+fn envi_header(width: usize, height: usize, bands: usize) -> String {
+    let list = |f: &dyn Fn(f32, f32) -> f32| {
+        (0..bands)
+            .map(|band| {
+                let (start, end) = band_range(band, bands);
+                format!("{}", f(start, end))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!(
+        "ENVI\n\
+         description = {{iris spectral film, mean spectral radiance per nm}}\n\
+         samples = {}\n\
+         lines = {}\n\
+         bands = {}\n\
+         header offset = 0\n\
+         file type = ENVI Standard\n\
+         data type = 4\n\
+         interleave = bip\n\
+         byte order = 0\n\
+         wavelength units = Nanometers\n\
+         wavelength = {{{}}}\n\
+         fwhm = {{{}}}\n",
+        width,
+        height,
+        bands,
+        list(&|start, end| 0.5 * (start + end)),
+        list(&|start, end| end - start),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bands() {
+        assert_eq!(band(LAMBDA_MIN_NM, 10), 0);
+        assert_eq!(band(LAMBDA_MAX_NM, 10), 9);
+        assert_eq!(band(LAMBDA_MIN_NM + 47.5, 10), 1);
+        assert_eq!(band_range(1, 10), (LAMBDA_MIN_NM + 47.0, LAMBDA_MIN_NM + 94.0));
+
+        let header = envi_header(3, 2, 2);
+        assert!(header.starts_with("ENVI\n"));
+        assert!(header.contains("\nsamples = 3\nlines = 2\nbands = 2\n"));
+        assert!(header.contains("\nwavelength = {477.5, 712.5}\n"));
+    }
+}
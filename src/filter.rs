@@ -0,0 +1,185 @@
+// Pixel reconstruction filters. Every sample is splatted onto all pixels whose center lies
+// within the filter radius of it, weighted by the filter, and each pixel is the weighted
+// sum divided by the sum of the weights. The filters are separable, the weight of an
+// offset (dx, dy) from the pixel center is f(dx) * f(dy).
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+// Names and descriptions of the filters, the first one is the default
+pub const FILTERS: [(&str, &str); 5] = [
+    ("box", "average of the samples in the pixel, radius 0.5"),
+    ("tent", "linear falloff, radius 1"),
+    ("gaussian", "Gaussian with a standard deviation of 0.5, radius 1.5"),
+    ("mitchell", "Mitchell-Netravali with B = C = 1/3, radius 2"),
+    ("lanczos", "Lanczos windowed sinc, radius 3"),
+];
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "box" => FilterKind::Box,
+            "tent" => FilterKind::Tent,
+            "gaussian" => FilterKind::Gaussian,
+            "mitchell" => FilterKind::Mitchell,
+            "lanczos" => FilterKind::Lanczos,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    // in pixels
+    pub radius: f32,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Self {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    // Pixels beyond its own that a sample can reach on each side
+    pub fn margin(&self) -> usize {
+        (self.radius - 0.5).max(0.0).ceil() as usize
+    }
+
+    // First and last pixel, inclusive, whose center c satisfies c - r <= x < c + r
+    pub fn pixel_range(&self, x: f32) -> (i64, i64) {
+        (
+            (x - self.radius - 0.5).floor() as i64 + 1,
+            (x + self.radius - 0.5).floor() as i64,
+        )
+    }
+
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                // shifted down to reach zero at the radius
+                let gaussian = |x: f32| (-2.0 * x * x).exp();
+                (gaussian(x) - gaussian(self.radius)).max(0.0)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+// Mitchell-Netravali cubic on [0, 2]
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+            + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2)
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else {
+        ((-b - 6.0 * c) * x.powi(3)
+            + (6.0 * b + 30.0 * c) * x.powi(2)
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters() {
+        for &(name, _) in &FILTERS {
+            let filter = Filter::new(FilterKind::from_name(name).unwrap());
+            assert_eq!(filter.kind.name(), name);
+            assert!(filter.evaluate(0.0, 0.0) > 0.0);
+            assert_eq!(filter.evaluate(filter.radius + 0.01, 0.0), 0.0);
+            assert!((filter.evaluate_1d(0.3) - filter.evaluate_1d(-0.3)).abs() < 1e-6);
+        }
+
+        let tent = Filter::new(FilterKind::Tent);
+        assert_eq!(tent.evaluate(0.5, 0.5), 0.25);
+
+        // the Mitchell-Netravali cubic with B = C = 1/3 integrates to one and has a
+        // negative lobe
+        let mitchell = Filter::new(FilterKind::Mitchell);
+        let steps = 4000;
+        let integral: f32 = (0..steps)
+            .map(|i| mitchell.evaluate_1d(-2.0 + 4.0 * (i as f32 + 0.5) / steps as f32) * 4.0 / steps as f32)
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-3);
+        assert!(mitchell.evaluate_1d(1.5) < 0.0);
+
+        let lanczos = Filter::new(FilterKind::Lanczos);
+        assert!(lanczos.evaluate_1d(1.0).abs() < 1e-6);
+        assert!(lanczos.evaluate_1d(2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pixel_range() {
+        // with the box filter a sample only reaches the pixel it is in
+        let filter = Filter::default();
+        assert_eq!(filter.margin(), 0);
+        assert_eq!(filter.pixel_range(3.0), (3, 3));
+        assert_eq!(filter.pixel_range(3.99), (3, 3));
+
+        let filter = Filter::new(FilterKind::Mitchell);
+        assert_eq!(filter.margin(), 2);
+        assert_eq!(filter.pixel_range(3.0), (1, 4));
+        assert_eq!(filter.pixel_range(3.6), (2, 5));
+    }
+}
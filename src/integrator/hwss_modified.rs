@@ -1,9 +1,10 @@
 #[allow(unused)]
 use crate::{
+    aov::FirstHit,
     bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SampleableBsdf, SpecularBsdf},
-    integrator::Integrator,
+    integrator::{Integrator, SpectralMode},
     math::Ray,
-    math::{PdfSet, Point3, Shading, Vec3},
+    math::{Lanes, PdfSet, Point3, Shading, Vec3},
     sampling::Sampler,
     sampling::{self, mis},
     scene::Scene,
@@ -16,48 +17,77 @@ use crate::{
 const MAX_DEPTH: u32 = 15;
 const MIN_DEPTH: u32 = 2;
 
-pub struct HwssModified;
+pub struct HwssModified {
+    mode: SpectralMode,
+}
+
+// State of the path carried through the recursion
+#[derive(Clone, Copy)]
+struct PathState<L: Lanes> {
+    more_bounces: u32,
+    throughput: SpectralSample<L>,
+    path_pdfs: PdfSet<L>,
+}
+
+impl HwssModified {
+    pub fn new(mode: SpectralMode) -> Self {
+        Self { mode }
+    }
+}
 
 impl Default for HwssModified {
     #[allow(dead_code)]
     fn default() -> Self {
-        Self
+        Self::new(SpectralMode::default())
     }
 }
 
 impl Integrator for HwssModified {
-    fn radiance(
+    fn radiance<L: Lanes>(
         &self,
         scene: &Scene,
         mut ray: Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> SpectralSample {
+        first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L> {
         //let mut radiance = SpectralSample::splat(0.0);
-        let throughput = SpectralSample::splat(1.0);
-        let path_pdfs = PdfSet::splat(1.0);
+        let state = PathState {
+            more_bounces: MAX_DEPTH,
+            throughput: SpectralSample::splat(1.0),
+            path_pdfs: PdfSet::splat(1.0),
+        };
 
-        let radiance = self.radiance_recursive(scene, ray, wavelength, sampler,
-            MAX_DEPTH, throughput, path_pdfs);
+        let radiance = self.radiance_recursive(scene, ray, wavelength, sampler, state, first_hit);
 
-        #[cfg(feature = "hwss")]
-        return radiance;
-        #[cfg(not(feature = "hwss"))]
-        return SpectralSample::new(radiance.hero(), 0.0, 0.0, 0.0);
+        self.mode.resolve(radiance)
     }
 }
 
 impl HwssModified {
-    fn radiance_recursive(
+    // Weight of the hero wavelength's path, applied to the whole bundle,
+    // see `PathIntegrator::get_mis_weight` for the wavelength pdfs
+    fn weight<L: Lanes>(&self, path_pdfs: PdfSet<L>, wavelength: Wavelength<L>) -> PdfSet<L> {
+        match self.mode {
+            SpectralMode::HwssBalance => {
+                PdfSet::splat(mis::balance_heuristic_1(path_pdfs * wavelength.pdfs()))
+            }
+            SpectralMode::HwssNoMis => super::equal_weights(wavelength),
+            SpectralMode::Swss => PdfSet::splat(1.0),
+        }
+    }
+
+    fn radiance_recursive<L: Lanes>(
         &self,
         scene: &Scene,
         ray: Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-        more_bounces: u32,
-        throughput: SpectralSample,
-        path_pdfs: PdfSet,
-    ) -> SpectralSample {
+        state: PathState<L>,
+        mut first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L> {
+        let PathState { more_bounces, throughput, path_pdfs } = state;
+
         // check if we should continue
         if more_bounces == 0 {
             // no more bounces
@@ -73,6 +103,11 @@ impl HwssModified {
             None => return SpectralSample::splat(0.0),
         };
 
+        // the AOVs are filled at the first intersection
+        if let Some(first_hit) = first_hit.as_deref_mut() {
+            first_hit.record_surface(scene, &ray, prim, &hit, wavelength);
+        }
+
         // check for material at intersected primitive
         let bsdf = match prim.get_material(&scene.materials) {
             Some(bsdf) => bsdf,
@@ -82,14 +117,11 @@ impl HwssModified {
         // if the primitive is emissive, count emissive term: Le(x, -w)
         if let Some(light) = prim.get_light(&scene.lights) {
 
-            #[cfg(feature = "hwss")]
-            let weight = mis::balance_heuristic_1(path_pdfs);
-            #[cfg(not(feature = "hwss"))]
-            let weight = 1.0;
+            let weight = self.weight(path_pdfs, wavelength);
 
-            radiance += throughput
-                * light.evaluate(wavelength)
-                * weight;
+            radiance += weight
+                * throughput
+                * light.evaluate(wavelength);
         }
 
         // generate next ray
@@ -97,6 +129,9 @@ impl HwssModified {
         let (bsdf_sampled_wi, bsdf_values, bsdf_pdfs) =
             bsdf.sample(shading_wo, wavelength, sampler);
         let cos_theta = bsdf_sampled_wi.cos_theta().abs();
+        if let Some(first_hit) = first_hit.take() {
+            first_hit.record_albedo(bsdf_values, cos_theta, bsdf_pdfs.hero(), wavelength);
+        }
         if bsdf_pdfs.hero() == 0.0 || cos_theta == 0.0 {
             return SpectralSample::splat(0.0);
         }
@@ -106,14 +141,14 @@ impl HwssModified {
         let new_ray = Ray::spawn(hit.point, world_wi, hit.normal);
 
         // calculate recursive term and weight
-        #[cfg(feature = "hwss")]
-        let weight = mis::balance_heuristic_1(path_pdfs);
-        #[cfg(not(feature = "hwss"))]
-        let weight = 1.0;
+        let weight = self.weight(path_pdfs, wavelength);
         
-        let recursive_value = self.radiance_recursive(scene, new_ray, wavelength, sampler, more_bounces-1, 
-            throughput * bsdf_values * cos_theta / bsdf_pdfs.hero(), 
-            path_pdfs * bsdf_pdfs);
+        let next_state = PathState {
+            more_bounces: more_bounces - 1,
+            throughput: throughput * bsdf_values * cos_theta / bsdf_pdfs.hero(),
+            path_pdfs: path_pdfs * bsdf_pdfs,
+        };
+        let recursive_value = self.radiance_recursive(scene, new_ray, wavelength, sampler, next_state, None);
 
         return radiance // Le(x,w)
             + weight * bsdf_values * recursive_value * cos_theta / bsdf_pdfs.hero();
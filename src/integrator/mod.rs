@@ -1,17 +1,197 @@
 use crate::{
-    math::Ray,
+    aov::FirstHit,
+    math::{Lanes, PdfSet, Ray},
     sampling::Sampler,
     spectrum::{SpectralSample, Wavelength},
     scene::Scene,
 };
+use enum_dispatch::enum_dispatch;
 
 pub mod swss_slow;
+pub use swss_slow::SwssSlow;
+
 pub mod hwss_slow;
+pub use hwss_slow::HwssSlow;
+
 pub mod swss_naive;
+pub use swss_naive::SwssNaive;
+
 pub mod hwss_naive;
+pub use hwss_naive::HwssNaive;
+
 pub mod hwss_modified;
+pub use hwss_modified::HwssModified;
+
 pub mod path_integrator;
+pub use path_integrator::PathIntegrator;
 
+#[enum_dispatch]
 pub trait Integrator {
-    fn radiance(&self, scene: &Scene, ray: Ray, wavelength: Wavelength, sampler: &mut Sampler) -> SpectralSample;
+    // `first_hit`, if given, is filled with what the ray hits first for the AOVs
+    fn radiance<L: Lanes>(
+        &self,
+        scene: &Scene,
+        ray: Ray,
+        wavelength: Wavelength<L>,
+        sampler: &mut Sampler,
+        first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L>;
+}
+
+// How the wavelengths of a sample contribute. With SWSS only the hero wavelength is kept,
+// with HWSS all of the bundle are, weighted by the balance heuristic over their path pdfs or,
+// without MIS, equally.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SpectralMode {
+    #[default]
+    Swss,
+    HwssBalance,
+    HwssNoMis,
+}
+
+// Command line names of the spectral modes, the first is the default
+pub const SPECTRAL_MODES: [(&str, &str); 3] = [
+    ("swss", "single wavelength spectral sampling"),
+    ("hwss", "hero wavelength spectral sampling with balance heuristic weights"),
+    ("hwss-no-mis", "hero wavelength spectral sampling with equal weights"),
+];
+
+impl SpectralMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "hwss" => SpectralMode::HwssBalance,
+            "swss" => SpectralMode::Swss,
+            "hwss-no-mis" => SpectralMode::HwssNoMis,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SpectralMode::HwssBalance => "hwss",
+            SpectralMode::Swss => "swss",
+            SpectralMode::HwssNoMis => "hwss-no-mis",
+        }
+    }
+
+    // Drops the secondary wavelengths for SWSS
+    pub fn resolve<L: Lanes>(self, radiance: SpectralSample<L>) -> SpectralSample<L> {
+        match self {
+            SpectralMode::Swss => SpectralSample::hero_only(radiance.hero()),
+            SpectralMode::HwssBalance | SpectralMode::HwssNoMis => radiance,
+        }
+    }
+}
+
+// Weights of the wavelengths of a bundle without MIS: each contributes the same share of an
+// estimate made with the pdf of the hero, once divided by its own pdf in `to_xyz`
+pub fn equal_weights<L: Lanes>(wavelength: Wavelength<L>) -> PdfSet<L> {
+    wavelength.pdfs() / (L::N as f32 * wavelength.hero_pdf())
+}
+
+// Integrator chosen at runtime with `--integrator <name>`
+#[enum_dispatch(Integrator)]
+pub enum AnyIntegrator {
+    PathIntegrator,
+    SwssNaive,
+    HwssNaive,
+    HwssModified,
+    SwssSlow,
+    HwssSlow,
+}
+
+// Command line names of the integrators with a short description, the first is the default
+pub const INTEGRATORS: [(&str, &str); 6] = [
+    ("path", "path tracing with next event estimation"),
+    ("swss-naive", "single wavelength, next event estimation with MIS"),
+    ("hwss-naive", "hero wavelength, next event estimation with MIS"),
+    ("hwss-modified", "recursive, no next event estimation"),
+    ("swss-slow", "single wavelength, no next event estimation"),
+    ("hwss-slow", "hero wavelength, no next event estimation"),
+];
+
+impl AnyIntegrator {
+    // The spectral mode only applies to the integrators that support several,
+    // the others always use the one they are named after.
+    pub fn from_name(name: &str, mode: SpectralMode) -> Option<Self> {
+        Some(match name {
+            "path" => PathIntegrator::new(mode).into(),
+            "swss-naive" => SwssNaive.into(),
+            "hwss-naive" => HwssNaive.into(),
+            "hwss-modified" => HwssModified::new(mode).into(),
+            "swss-slow" => SwssSlow.into(),
+            "hwss-slow" => HwssSlow.into(),
+            _ => return None,
+        })
+    }
+
+    // Whether the integrator takes the spectral mode given to `from_name`
+    pub fn has_spectral_modes(&self) -> bool {
+        matches!(self, AnyIntegrator::PathIntegrator(_) | AnyIntegrator::HwssModified(_))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnyIntegrator::PathIntegrator(_) => "path",
+            AnyIntegrator::SwssNaive(_) => "swss-naive",
+            AnyIntegrator::HwssNaive(_) => "hwss-naive",
+            AnyIntegrator::HwssModified(_) => "hwss-modified",
+            AnyIntegrator::SwssSlow(_) => "swss-slow",
+            AnyIntegrator::HwssSlow(_) => "hwss-slow",
+        }
+    }
+}
+
+impl Default for AnyIntegrator {
+    fn default() -> Self {
+        PathIntegrator::default().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        math::{Vec4, VecN},
+        spectrum::wavelength::WavelengthSampling,
+    };
+
+    #[test]
+    fn test_spectral_modes() {
+        for &(name, _) in &SPECTRAL_MODES {
+            assert_eq!(SpectralMode::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(SpectralMode::from_name(SPECTRAL_MODES[0].0), Some(SpectralMode::default()));
+        assert_eq!(SpectralMode::from_name("hero"), None);
+
+        // SWSS keeps the hero wavelength only, HWSS the whole bundle
+        let radiance = SpectralSample::new(1.0, 2.0, 3.0, 4.0);
+        let lanes = |sample: SpectralSample| (0..4).map(|i| sample.lane(i)).collect::<Vec<_>>();
+        assert_eq!(lanes(SpectralMode::Swss.resolve(radiance)), [1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(lanes(SpectralMode::HwssBalance.resolve(radiance)), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(lanes(SpectralMode::HwssNoMis.resolve(radiance)), [1.0, 2.0, 3.0, 4.0]);
+
+        assert!(AnyIntegrator::from_name("path", SpectralMode::Swss).unwrap().has_spectral_modes());
+        assert!(!AnyIntegrator::from_name("hwss-naive", SpectralMode::Swss).unwrap().has_spectral_modes());
+    }
+
+    #[test]
+    fn test_equal_weights() {
+        // with uniform sampling every wavelength gets 1 / N
+        let wavelength = Wavelength::<Vec4>::new(500.0, WavelengthSampling::Uniform);
+        let weights = equal_weights(wavelength);
+        for i in 0..4 {
+            assert!((weights.lane(i) - 0.25).abs() < 1e-6);
+        }
+
+        // otherwise the hero still gets 1 / N and the others in proportion to their pdf,
+        // so that after the division by their own pdf all contribute alike
+        let wavelength = Wavelength::<VecN<8>>::new(450.0, WavelengthSampling::CieY);
+        let weights = equal_weights(wavelength);
+        assert!((weights.lane(0) - 0.125).abs() < 1e-6);
+        for i in 1..8 {
+            let share = weights.lane(i) / wavelength.pdfs().lane(i);
+            assert!((share - 0.125 / wavelength.hero_pdf()).abs() < 1e-3 * share, "{}", i);
+        }
+    }
 }
@@ -1,9 +1,10 @@
 #[allow(unused)]
 use crate::{
+    aov::FirstHit,
     bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SampleableBsdf, SpecularBsdf},
-    integrator::Integrator,
+    integrator::{Integrator, SpectralMode},
     math::Ray,
-    math::{PdfSet, Point3, Shading, Vec3},
+    math::{Lanes, PdfSet, Point3, Shading, Vec3},
     sampling::Sampler,
     sampling::{self, mis},
     scene::Scene,
@@ -15,23 +16,32 @@ use crate::{
 
 const MAX_DEPTH: u32 = 32;
 
-pub struct PathIntegrator;
+pub struct PathIntegrator {
+    mode: SpectralMode,
+}
+
+impl PathIntegrator {
+    pub fn new(mode: SpectralMode) -> Self {
+        Self { mode }
+    }
+}
 
 impl Default for PathIntegrator {
     #[allow(dead_code)]
     fn default() -> Self {
-        Self
+        Self::new(SpectralMode::default())
     }
 }
 
 impl Integrator for PathIntegrator {
-    fn radiance(
+    fn radiance<L: Lanes>(
         &self,
         scene: &Scene,
         mut ray: Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> SpectralSample {
+        mut first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L> {
         
         // keep track of total radiance contributed so far
         let mut radiance = SpectralSample::splat(0.0);
@@ -53,13 +63,18 @@ impl Integrator for PathIntegrator {
                 None => break,
             };
 
+            // the AOVs are filled at the first intersection
+            if let Some(first_hit) = first_hit.as_deref_mut() {
+                first_hit.record_surface(scene, &ray, prim, &hit, wavelength);
+            }
+
             // handle a special case:
             // at first intersection of camera ray there was no
             // previous loop that could determine the emissive radiance
             // therefore we count the emissiveness of this surface
             if bounce == 0 {
                 if let Some(light) = prim.get_light(&scene.lights) {
-                    radiance += self.get_mis_weight(path_pdfs) * throughput * light.evaluate(wavelength);
+                    radiance += self.get_mis_weight(path_pdfs, wavelength) * throughput * light.evaluate(wavelength);
                 }
             }
 
@@ -86,7 +101,7 @@ impl Integrator for PathIntegrator {
                 if light_pdf > 0.0
                     && facing_forward != hit.back_face
                     && light_pos.distance_squared(hit.point) > 0.00001
-                    && scene.ray_hits_point(&ray_to_light, light_pos)
+                    && !scene.light_occluded(hit.point, hit.normal, light_pos, light_prim)
                 {
                     // evaluate BSDF for the solid angle towards the light
                     let shading_wi = hit.world_to_shading(ray_to_light.d());
@@ -98,7 +113,7 @@ impl Integrator for PathIntegrator {
                     let single_light_radiance = bsdf_values * cos_theta * light_emission / light_pdf;
 
                     // adjust for number of lights and apply throughput of the paths so far
-                    radiance += self.get_mis_weight(path_pdfs) * throughput * light_pick_factor * single_light_radiance;
+                    radiance += self.get_mis_weight(path_pdfs, wavelength) * throughput * light_pick_factor * single_light_radiance;
                 }
             }
 
@@ -111,6 +126,9 @@ impl Integrator for PathIntegrator {
             let shading_wo = hit.world_to_shading(-ray.d());
             let (bsdf_sampled_wi, bsdf_values, bsdf_pdfs) = bsdf.sample(shading_wo, wavelength, sampler);
             let cos_theta = bsdf_sampled_wi.cos_theta().abs();
+            if let Some(first_hit) = first_hit.take() {
+                first_hit.record_albedo(bsdf_values, cos_theta, bsdf_pdfs.hero(), wavelength);
+            }
             if bsdf_pdfs.hero() == 0.0 || cos_theta == 0.0 {
                 break;
             }
@@ -131,43 +149,34 @@ impl Integrator for PathIntegrator {
 impl PathIntegrator {
     // Returns the Multiple Importance Sampling (MIS) weight
     // for given product of path BSDF PDFs.
-    fn get_mis_weight(&self, path_pdfs: PdfSet) -> PdfSet {
-        #[cfg(feature = "hwss")]
-        {
-            // for hero wavelength sampling, we use equation (8) from Wilkie et al. (2014), L=lambda
-            // ws(X,L) = ps(X,L) / sum_(k of C)(pk(X,L)) with ps(X,L) = pXs(X|L) * pLs(L).
-            // We can omit the factor pLs(L) if (and only if!) the wavelengths are sampled from a uniform distribution.
-            // So all pLs(L) terms in the fraction are equal and can be cancelled out because all wavelengths are sampled
-            // with the same probability.
-            // The other term pXs(X|L) refers to the probability of sampling the whole path X up to this point with wavelength
-            // L which is simply the product of the BSDF PDFs for wavelength L (found in path_pdfs).
-            // So we divide each path_pdfs entry by the sum of path_pdfs.
-            let weight = path_pdfs / path_pdfs.sum();
-            //assert_eq!(weight.x(), 0.25);
-            //assert_eq!(weight.y(), 0.25);
-            //assert_eq!(weight.z(), 0.25);
-            //assert_eq!(weight.w(), 0.25);
-            //assert_eq!(weight.sum(), 1.0);
-            return weight;
-        }
-        #[cfg(not(feature = "hwss"))]
-        {
-            // for single wavelength sampling, only
-            // the hero wavelength is considered
-            return PdfSet::new(1.0, 0.0, 0.0, 0.0);
+    fn get_mis_weight<L: Lanes>(&self, path_pdfs: PdfSet<L>, wavelength: Wavelength<L>) -> PdfSet<L> {
+        match self.mode {
+            SpectralMode::HwssBalance => {
+                // for hero wavelength sampling, we use equation (8) from Wilkie et al. (2014), L=lambda
+                // ws(X,L) = ps(X,L) / sum_(k of C)(pk(X,L)) with ps(X,L) = pXs(X|L) * pLs(L).
+                // The term pXs(X|L) refers to the probability of sampling the whole path X up to this point with wavelength
+                // L which is simply the product of the BSDF PDFs for wavelength L (found in path_pdfs).
+                // The term pLs(L) is the probability of sampling L as the hero wavelength. It only cancels out if the
+                // wavelengths are sampled from a uniform distribution, so we keep it for the other strategies.
+                // The division by pLs(L) of the estimate itself happens in SpectralSample::to_xyz.
+                let pdfs = path_pdfs * wavelength.pdfs();
+                pdfs / pdfs.sum()
+            }
+            SpectralMode::HwssNoMis => {
+                // without MIS, every wavelength of the bundle contributes the
+                // same share of an estimate made with the hero's pdf
+                super::equal_weights(wavelength)
+            }
+            SpectralMode::Swss => {
+                // for single wavelength sampling, only
+                // the hero wavelength is considered
+                PdfSet::hero_only(1.0)
+            }
         }
     }
 
     // Returns the radiance to return from the integrator.
-    fn determine_radiance(&self, radiance: SpectralSample) -> SpectralSample {
-        #[cfg(feature = "hwss")]
-        {
-            //assert_eq!(radiance.x(), radiance.y());
-            //assert_eq!(radiance.x(), radiance.z());
-            //assert_eq!(radiance.x(), radiance.w());
-            return radiance;
-        }
-        #[cfg(not(feature = "hwss"))]
-        return SpectralSample::new(radiance.hero(), 0.0, 0.0, 0.0);
+    fn determine_radiance<L: Lanes>(&self, radiance: SpectralSample<L>) -> SpectralSample<L> {
+        self.mode.resolve(radiance)
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,10 @@
 #[allow(unused)]
 use crate::{
+    aov::FirstHit,
     bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SampleableBsdf, SpecularBsdf},
     integrator::Integrator,
     math::Ray,
-    math::{PdfSet, Point3, Shading, Vec3},
+    math::{Lanes, PdfSet, Point3, Shading, Vec3},
     sampling::Sampler,
     sampling::{self, mis},
     scene::Scene,
@@ -26,13 +27,14 @@ impl Default for SwssNaive {
 }
 
 impl Integrator for SwssNaive {
-    fn radiance(
+    fn radiance<L: Lanes>(
         &self,
         scene: &Scene,
         mut ray: Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> SpectralSample {
+        mut first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L> {
         let mut radiance = SpectralSample::splat(0.0);
         let mut throughput = SpectralSample::splat(1.0);
 
@@ -42,6 +44,11 @@ impl Integrator for SwssNaive {
                 None => break,
             };
 
+            // the AOVs are filled at the first intersection
+            if let Some(first_hit) = first_hit.as_deref_mut() {
+                first_hit.record_surface(scene, &ray, prim, &hit, wavelength);
+            }
+
             let bsdf = match prim.get_material(&scene.materials) {
                 Some(bsdf) => bsdf,
                 None => break, // TODO: Should this break be below?
@@ -63,6 +70,9 @@ impl Integrator for SwssNaive {
             let (bsdf_sampled_wi, bsdf_values, bsdf_pdfs) =
                 bsdf.sample(shading_wo, wavelength, sampler);
             let cos_theta = bsdf_sampled_wi.cos_theta().abs();
+            if let Some(first_hit) = first_hit.take() {
+                first_hit.record_albedo(bsdf_values, cos_theta, bsdf_pdfs.hero(), wavelength);
+            }
             if bsdf_pdfs.hero() == 0.0 || cos_theta == 0.0 {
                 break;
             }
@@ -84,20 +94,20 @@ impl Integrator for SwssNaive {
             ray = Ray::spawn(hit.point, world_wi, hit.normal);
         }
 
-        SpectralSample::new(radiance.hero(), 0.0, 0.0, 0.0)
+        SpectralSample::hero_only(radiance.hero())
     }
 }
 
 impl SwssNaive {
-    fn direct_light(
+    fn direct_light<L: Lanes>(
         &self,
         bsdf: &Bsdf,
         hit: &Intersection,
         scene: &Scene,
         ray: &Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> SpectralSample {
+    ) -> SpectralSample<L> {
         let mut radiance = SpectralSample::splat(0.0);
 
         let shading_wo = hit.world_to_shading(-ray.d());
@@ -116,7 +126,7 @@ impl SwssNaive {
             if light_pdf > 0.0
                 && facing_forward != hit.back_face
                 && light_pos.distance_squared(hit.point) > 0.00001
-                && scene.ray_hits_point(&ray_to_light, light_pos)
+                && !scene.light_occluded(hit.point, hit.normal, light_pos, light_prim)
             {
                 // Add light sample contribution
                 let shading_wi = hit.world_to_shading(ray_to_light.d());
@@ -138,8 +148,16 @@ impl SwssNaive {
             let ray_to_light =
                 Ray::spawn(hit.point, hit.shading_to_world(bsdf_sampled_wi), hit.normal);
 
+            // Check that the sampled direction reaches the light without being blocked
+            let reaches_light = match light_prim.intersect(&ray_to_light) {
+                Some((light_hit, _)) => {
+                    !scene.light_occluded(hit.point, hit.normal, light_hit.point, light_prim)
+                }
+                None => false,
+            };
+
             // Check that the light has a non-zero contribution
-            if bsdf_pdfs.hero() > 0.0 && scene.ray_hits_object(&ray_to_light, light_prim) {
+            if bsdf_pdfs.hero() > 0.0 && reaches_light {
                 // Add light sample contribution
                 let light_pdf = light_prim.pdf(&hit, ray_to_light.d());
                 let mis_weight = bsdf_pdfs.hero() / (bsdf_pdfs.hero() + light_pdf);
@@ -1,9 +1,10 @@
 #[allow(unused)]
 use crate::{
+    aov::FirstHit,
     bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SampleableBsdf, SpecularBsdf},
     integrator::Integrator,
     math::Ray,
-    math::{PdfSet, Point3, Shading, Vec3},
+    math::{Lanes, PdfSet, Point3, Shading, Vec3},
     sampling::Sampler,
     sampling::{self, mis},
     scene::Scene,
@@ -26,13 +27,14 @@ impl Default for SwssSlow {
 }
 
 impl Integrator for SwssSlow {
-    fn radiance(
+    fn radiance<L: Lanes>(
         &self,
         scene: &Scene,
         mut ray: Ray,
-        wavelength: Wavelength,
+        wavelength: Wavelength<L>,
         sampler: &mut Sampler,
-    ) -> SpectralSample {
+        mut first_hit: Option<&mut FirstHit>,
+    ) -> SpectralSample<L> {
         let mut radiance = SpectralSample::splat(0.0);
         let mut throughput = SpectralSample::splat(1.0);
 
@@ -42,6 +44,11 @@ impl Integrator for SwssSlow {
                 None => break,
             };
 
+            // the AOVs are filled at the first intersection
+            if let Some(first_hit) = first_hit.as_deref_mut() {
+                first_hit.record_surface(scene, &ray, prim, &hit, wavelength);
+            }
+
             // Accumulate emission
             if let Some(light) = prim.get_light(&scene.lights) {
                 radiance += throughput * light.evaluate(wavelength);
@@ -57,6 +64,9 @@ impl Integrator for SwssSlow {
             let (bsdf_sampled_wi, bsdf_values, bsdf_pdfs) =
                 bsdf.sample(shading_wo, wavelength, sampler);
             let cos_theta = bsdf_sampled_wi.cos_theta().abs();
+            if let Some(first_hit) = first_hit.take() {
+                first_hit.record_albedo(bsdf_values, cos_theta, bsdf_pdfs.hero(), wavelength);
+            }
             if bsdf_pdfs.hero() == 0.0 || cos_theta == 0.0 {
                 break;
             }
@@ -78,6 +88,6 @@ impl Integrator for SwssSlow {
             ray = Ray::spawn(hit.point, world_wi, hit.normal);
         }
 
-        SpectralSample::new(radiance.hero(), 0.0, 0.0, 0.0)
+        SpectralSample::hero_only(radiance.hero())
     }
 }
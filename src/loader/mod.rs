@@ -0,0 +1,161 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    color::ColorSpace,
+    math::{Point3, Vec3},
+    spectrum::{upsample::UpsampleTable, ConstantSpectrum, Spectrum},
+};
+
+pub mod obj;
+pub mod ply;
+pub mod scene_file;
+pub mod spd;
+
+// Error produced while reading a scene or asset file.
+// A line of 0 means the error has no position (e.g. the file could not be opened).
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl LoadError {
+    pub fn new<S: Into<String>>(file: &str, line: usize, column: usize, message: S) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    pub fn io(file: &str, err: std::io::Error) -> Self {
+        Self::new(file, 0, 0, err.to_string())
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub column: usize,
+}
+
+// Splits a line into whitespace separated tokens with their 1-based column,
+// ignoring everything after a `#`.
+pub fn tokenize(line: &str) -> Vec<Token<'_>> {
+    // strip comments
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None;
+
+    for (column, (i, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (true, Some((s, col))) => {
+                tokens.push(Token { text: &line[s..i], column: col + 1 });
+                start = None;
+            }
+            (false, None) => start = Some((i, column)),
+            _ => {}
+        }
+    }
+
+    if let Some((s, col)) = start {
+        tokens.push(Token { text: &line[s..], column: col + 1 });
+    }
+
+    tokens
+}
+
+// Turns RGB colors from asset files into spectra.
+// The upsampling table of a color space is only loaded once its first color is requested.
+#[derive(Default)]
+pub struct RgbUpsampler {
+    color_space: ColorSpace,
+    tables: HashMap<ColorSpace, UpsampleTable>,
+}
+
+impl RgbUpsampler {
+    // Color space of the colors passed to `spectrum`
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    // Components above 1 are treated as HDR values: the color is normalized
+    // and its maximum is folded into the scale of the spectrum.
+    pub fn spectrum(&mut self, rgb: [f32; 3], scale: f32) -> Spectrum {
+        let max = rgb[0].max(rgb[1]).max(rgb[2]);
+        if max <= 0.0 {
+            // the upsampling table cannot represent pure black
+            return Spectrum::from(ConstantSpectrum::new(0.0));
+        }
+
+        let (rgb, scale) = if max > 1.0 {
+            ([rgb[0] / max, rgb[1] / max, rgb[2] / max], scale * max)
+        } else {
+            (rgb, scale)
+        };
+
+        let color_space = self.color_space;
+        let table = self
+            .tables
+            .entry(color_space)
+            .or_insert_with(|| UpsampleTable::load(color_space));
+        let mut spectrum = table.get_spectrum(rgb);
+        spectrum.set_scale(scale);
+        Spectrum::from(spectrum)
+    }
+}
+
+// Places a mesh in the scene: vertices are scaled around the origin, then translated.
+#[derive(Debug, Clone, Copy)]
+pub struct MeshTransform {
+    pub scale: f32,
+    pub translation: Vec3,
+}
+
+impl Default for MeshTransform {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            translation: Vec3::splat(0.0),
+        }
+    }
+}
+
+impl MeshTransform {
+    pub fn apply(&self, p: Point3) -> Point3 {
+        (p.to_vec() * self.scale + self.translation).to_point()
+    }
+}
+
+// Number of triangles a mesh loader added to the scene and how many it rejected.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MeshStats {
+    pub triangles: usize,
+    pub degenerate: usize,
+}
+
+// Triangles without area would get a NaN normal from `Triangle::new`.
+pub fn is_degenerate(v1: Point3, v2: Point3, v3: Point3) -> bool {
+    let normal = (v2 - v1).cross(v3 - v1);
+    let len_squared = normal.len_squared();
+    !(len_squared > 0.0 && len_squared.is_finite())
+}
@@ -0,0 +1,534 @@
+// Loader for Wavefront OBJ meshes and their MTL material libraries.
+//
+// Polygons are fan triangulated, texture coordinates and normals are ignored.
+// MTL materials are mapped onto the available BSDFs:
+//
+//   - transparent materials (illum 4, 6 or 7, or d < 1) become a FresnelBsdf with Ni as
+//     index of refraction, Ks as reflected and Tf as transmitted color, white if not given
+//   - materials whose specular color Ks outweighs the diffuse color Kd become a
+//     MicrofacetBsdf with a roughness derived from the Phong exponent Ns
+//   - everything else becomes a LambertianBsdf with Kd as albedo
+//
+// Colors must not be negative, Ni must be positive and d between 0 and 1.
+// A material with a non-black Ke makes its faces emissive. Faces without a material
+// get a gray diffuse BSDF.
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf},
+    loader::{is_degenerate, tokenize, LoadError, MeshStats, MeshTransform, RgbUpsampler, Token},
+    math::Point3,
+    scene::Scene,
+    shape::Triangle,
+    spectrum::{ConstantSpectrum, Spectrum},
+};
+
+const DEFAULT_ALBEDO: f32 = 0.8;
+const DEFAULT_IOR: f32 = 1.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emission: [f32; 3],
+    pub transmission: [f32; 3],
+    pub specular_exponent: f32,
+    pub ior: f32,
+    pub dissolve: f32,
+    pub illum: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: [DEFAULT_ALBEDO; 3],
+            specular: [0.0; 3],
+            emission: [0.0; 3],
+            transmission: [1.0; 3],
+            specular_exponent: 0.0,
+            ior: DEFAULT_IOR,
+            dissolve: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    pub fn is_transparent(&self) -> bool {
+        matches!(self.illum, 4 | 6 | 7) || self.dissolve < 1.0
+    }
+
+    pub fn is_glossy(&self) -> bool {
+        max_component(self.specular) > max_component(self.diffuse)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        max_component(self.emission) > 0.0
+    }
+
+    // A dielectric always reflects, without Ks the reflection is white like the default
+    // transmission
+    pub fn reflected_color(&self) -> [f32; 3] {
+        if max_component(self.specular) > 0.0 {
+            self.specular
+        } else {
+            [1.0; 3]
+        }
+    }
+
+    // Converts the Phong exponent to a microfacet roughness (Walter et al. 2007).
+    pub fn roughness(&self) -> f32 {
+        (2.0 / (self.specular_exponent.max(0.0) + 2.0)).sqrt()
+    }
+
+    fn to_bsdf(&self, upsampler: &mut RgbUpsampler) -> Bsdf {
+        if self.is_transparent() {
+            Bsdf::from(FresnelBsdf::new(
+                upsampler.spectrum(self.reflected_color(), 1.0),
+                upsampler.spectrum(self.transmission, 1.0),
+                self.ior,
+                0.0,
+            ))
+        } else if self.is_glossy() {
+            let roughness = self.roughness();
+            Bsdf::from(MicrofacetBsdf::new(
+                upsampler.spectrum(self.specular, 1.0),
+                roughness,
+                roughness,
+            ))
+        } else {
+            Bsdf::from(LambertianBsdf::new(upsampler.spectrum(self.diffuse, 1.0)))
+        }
+    }
+}
+
+fn max_component(rgb: [f32; 3]) -> f32 {
+    rgb[0].max(rgb[1]).max(rgb[2])
+}
+
+// Loads the mesh at `path` into the scene, skipping degenerate faces.
+// If given, `material` and `emission` replace whatever the MTL file specifies.
+pub fn load(
+    path: &str,
+    scene: &mut Scene,
+    transform: MeshTransform,
+    material: Option<Bsdf>,
+    emission: Option<Spectrum>,
+    upsampler: &mut RgbUpsampler,
+) -> Result<MeshStats, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    let mesh = parse(path, &source, |name, token, line| {
+        let mtl_path = Path::new(path).with_file_name(name);
+        let mtl_path = mtl_path.to_string_lossy();
+        let mtl_source = std::fs::read_to_string(mtl_path.as_ref()).map_err(|e| {
+            LoadError::new(path, line, token.column, format!("cannot read `{}`: {}", name, e))
+        })?;
+        parse_mtl(&mtl_path, &mtl_source)
+    })?;
+
+    let bsdfs = mesh
+        .materials
+        .iter()
+        .map(|m| match &material {
+            Some(material) => material.clone(),
+            None => m.to_bsdf(upsampler),
+        })
+        .collect::<Vec<_>>();
+    let emissions = mesh
+        .materials
+        .iter()
+        .map(|m| match &emission {
+            Some(emission) => Some(emission.clone()),
+            None if m.is_emissive() => Some(upsampler.spectrum(m.emission, 1.0)),
+            None => None,
+        })
+        .collect::<Vec<_>>();
+    // with a material given, the caller has set its index, otherwise each MTL material and
+    // the default one get their own
+    let material_indices = match material {
+        Some(_) => None,
+        None => Some((0..=mesh.materials.len()).map(|_| scene.new_material_index()).collect::<Vec<_>>()),
+    };
+    let default_bsdf = material.unwrap_or_else(|| {
+        Bsdf::from(LambertianBsdf::new(ConstantSpectrum::new(DEFAULT_ALBEDO)))
+    });
+
+    let mut stats = MeshStats::default();
+    for face in &mesh.faces {
+        let v1 = transform.apply(mesh.vertices[face.vertices[0]]);
+        let v2 = transform.apply(mesh.vertices[face.vertices[1]]);
+        let v3 = transform.apply(mesh.vertices[face.vertices[2]]);
+
+        if is_degenerate(v1, v2, v3) {
+            stats.degenerate += 1;
+            continue;
+        }
+
+        let triangle = Triangle::new(v1, v2, v3);
+        if let Some(indices) = &material_indices {
+            // the default material comes last
+            let index = face.material.unwrap_or(mesh.materials.len());
+            scene.set_material_index(Some(indices[index]));
+        }
+        let (bsdf, light) = match face.material {
+            Some(i) => (bsdfs[i].clone(), emissions[i].clone()),
+            None => (default_bsdf.clone(), emission.clone()),
+        };
+        match light {
+            Some(light) => scene.add_emissive_material(triangle, bsdf, light),
+            None => scene.add_material(triangle, bsdf),
+        }
+        stats.triangles += 1;
+    }
+    if material_indices.is_some() {
+        scene.set_material_index(None);
+    }
+
+    Ok(stats)
+}
+
+#[derive(Debug, Default)]
+pub struct ObjMesh {
+    pub vertices: Vec<Point3>,
+    pub faces: Vec<ObjFace>,
+    pub materials: Vec<MtlMaterial>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjFace {
+    pub vertices: [usize; 3],
+    pub material: Option<usize>,
+}
+
+// Parses OBJ source. `load_mtl` is called for every `mtllib` statement with the library
+// name, its token and line.
+pub fn parse<F>(file: &str, source: &str, mut load_mtl: F) -> Result<ObjMesh, LoadError>
+where
+    F: FnMut(&str, Token, usize) -> Result<HashMap<String, MtlMaterial>, LoadError>,
+{
+    let mut mesh = ObjMesh::default();
+    let mut library: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+    let mut current_material = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let tokens = tokenize(line);
+        let error = |token: &Token, message: String| {
+            LoadError::new(file, line_number, token.column, message)
+        };
+
+        let keyword = match tokens.first() {
+            Some(token) => token,
+            None => continue,
+        };
+
+        match keyword.text {
+            "v" => {
+                if tokens.len() < 4 {
+                    return Err(error(keyword, "vertex needs three coordinates".to_string()));
+                }
+                let mut coords = [0.0; 3];
+                for (coord, token) in coords.iter_mut().zip(&tokens[1..4]) {
+                    *coord = match token.text.parse::<f32>() {
+                        Ok(value) if value.is_finite() => value,
+                        _ => {
+                            return Err(error(
+                                token,
+                                format!("expected vertex coordinate, found `{}`", token.text),
+                            ));
+                        }
+                    };
+                }
+                mesh.vertices.push(Point3::new(coords[0], coords[1], coords[2]));
+            }
+            "f" => {
+                if tokens.len() < 4 {
+                    return Err(error(keyword, "face needs at least three vertices".to_string()));
+                }
+
+                let mut indices = Vec::with_capacity(tokens.len() - 1);
+                for token in &tokens[1..] {
+                    // only the position index of `v/vt/vn` is used
+                    let index = token.text.split('/').next().unwrap();
+                    let index = match index.parse::<i64>() {
+                        Ok(index) if index > 0 && index as usize <= mesh.vertices.len() => {
+                            index as usize - 1
+                        }
+                        Ok(index) if index < 0 && (-index) as usize <= mesh.vertices.len() => {
+                            mesh.vertices.len() - (-index) as usize
+                        }
+                        _ => {
+                            return Err(error(
+                                token,
+                                format!("invalid vertex reference `{}`", token.text),
+                            ));
+                        }
+                    };
+                    indices.push(index);
+                }
+
+                // fan triangulation
+                for j in 1..(indices.len() - 1) {
+                    mesh.faces.push(ObjFace {
+                        vertices: [indices[0], indices[j], indices[j + 1]],
+                        material: current_material,
+                    });
+                }
+            }
+            "mtllib" => {
+                for token in &tokens[1..] {
+                    library.extend(load_mtl(token.text, *token, line_number)?);
+                }
+            }
+            "usemtl" => {
+                let name = match tokens.get(1) {
+                    Some(token) => token,
+                    None => return Err(error(keyword, "expected material name".to_string())),
+                };
+                let material = match library.get(name.text) {
+                    Some(material) => material,
+                    None => {
+                        return Err(error(name, format!("unknown material `{}`", name.text)));
+                    }
+                };
+                let index = *material_indices
+                    .entry(name.text.to_string())
+                    .or_insert_with(|| {
+                        mesh.materials.push(material.clone());
+                        mesh.materials.len() - 1
+                    });
+                current_material = Some(index);
+            }
+            // normals, texture coordinates, groups, objects, smoothing groups, lines, ...
+            _ => {}
+        }
+    }
+
+    Ok(mesh)
+}
+
+pub fn parse_mtl(file: &str, source: &str) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let tokens = tokenize(line);
+        let keyword = match tokens.first() {
+            Some(token) => token,
+            None => continue,
+        };
+
+        let numbers = |count: usize| -> Result<Vec<f32>, LoadError> {
+            if tokens.len() < count + 1 {
+                return Err(LoadError::new(
+                    file,
+                    line_number,
+                    keyword.column,
+                    format!("`{}` needs {} value(s)", keyword.text, count),
+                ));
+            }
+            tokens[1..=count]
+                .iter()
+                .map(|token| match token.text.parse::<f32>() {
+                    Ok(value) if value.is_finite() => Ok(value),
+                    _ => Err(LoadError::new(
+                        file,
+                        line_number,
+                        token.column,
+                        format!("expected number, found `{}`", token.text),
+                    )),
+                })
+                .collect()
+        };
+        // a color, with components that must not be negative
+        let rgb = || -> Result<[f32; 3], LoadError> {
+            let values = numbers(3)?;
+            match values.iter().position(|&value| value < 0.0) {
+                Some(k) => Err(LoadError::new(
+                    file,
+                    line_number,
+                    tokens[k + 1].column,
+                    format!("`{}` components must not be negative", keyword.text),
+                )),
+                None => Ok([values[0], values[1], values[2]]),
+            }
+        };
+        // a single value, `expected` describes the valid ones
+        let number = |valid: fn(f32) -> bool, expected: &str| -> Result<f32, LoadError> {
+            let value = numbers(1)?[0];
+            if !valid(value) {
+                return Err(LoadError::new(
+                    file,
+                    line_number,
+                    tokens[1].column,
+                    format!("`{}` must be {}", keyword.text, expected),
+                ));
+            }
+            Ok(value)
+        };
+
+        if keyword.text == "newmtl" {
+            let name = match tokens.get(1) {
+                Some(token) => token.text.to_string(),
+                None => {
+                    return Err(LoadError::new(
+                        file,
+                        line_number,
+                        keyword.column,
+                        "expected material name",
+                    ));
+                }
+            };
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name, MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => {
+                return Err(LoadError::new(
+                    file,
+                    line_number,
+                    keyword.column,
+                    format!("`{}` before the first `newmtl`", keyword.text),
+                ));
+            }
+        };
+
+        match keyword.text {
+            "Kd" => material.diffuse = rgb()?,
+            "Ks" => material.specular = rgb()?,
+            "Ke" => material.emission = rgb()?,
+            "Tf" => material.transmission = rgb()?,
+            "Ns" => material.specular_exponent = numbers(1)?[0],
+            "Ni" => material.ior = number(|ior| ior > 0.0, "positive")?,
+            "d" => material.dissolve = number(|d| (0.0..=1.0).contains(&d), "between 0 and 1")?,
+            "Tr" => material.dissolve = 1.0 - number(|tr| (0.0..=1.0).contains(&tr), "between 0 and 1")?,
+            "illum" => material.illum = numbers(1)?[0] as u32,
+            // ambient color, texture maps, ...
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current.take() {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTL: &str = "
+        newmtl wall
+        Kd 0.8 0.2 0.2
+        newmtl glass
+        Ks 1 1 1
+        Ni 1.45
+        illum 7
+        newmtl metal
+        Kd 0.1 0.1 0.1
+        Ks 0.9 0.9 0.9
+        Ns 98
+        newmtl lamp
+        Ke 10 10 8
+        newmtl tinted
+        Kd 0 0 0
+        Tf 0.5 0.9 0.5
+        d 0.4
+    ";
+
+    #[test]
+    fn test_parse_mtl() {
+        let materials = parse_mtl("test.mtl", MTL).unwrap();
+        assert_eq!(materials.len(), 5);
+
+        let wall = &materials["wall"];
+        assert_eq!(wall.diffuse, [0.8, 0.2, 0.2]);
+        assert!(!wall.is_transparent() && !wall.is_glossy() && !wall.is_emissive());
+
+        let glass = &materials["glass"];
+        assert!(glass.is_transparent());
+        assert_eq!(glass.ior, 1.45);
+        assert_eq!((glass.reflected_color(), glass.transmission), ([1.0; 3], [1.0; 3]));
+
+        // without Ks a transparent material still reflects
+        let tinted = &materials["tinted"];
+        assert!(tinted.is_transparent() && !tinted.is_glossy());
+        assert_eq!(tinted.reflected_color(), [1.0; 3]);
+        assert_eq!(tinted.transmission, [0.5, 0.9, 0.5]);
+
+        let metal = &materials["metal"];
+        assert!(metal.is_glossy() && !metal.is_transparent());
+        assert_eq!(metal.roughness(), 0.02f32.sqrt());
+
+        assert!(materials["lamp"].is_emissive());
+
+        // negative colors, an index of refraction of 0 and opacities out of range
+        let invalid = [
+            ("Kd 0.5 -0.1 0.5", 16, "`Kd` components must not be negative"),
+            ("Ke -1 0 0", 12, "`Ke` components must not be negative"),
+            ("Tf 1 1 -1", 16, "`Tf` components must not be negative"),
+            ("Ni 0", 12, "`Ni` must be positive"),
+            ("d 1.5", 11, "`d` must be between 0 and 1"),
+            ("Tr -0.5", 12, "`Tr` must be between 0 and 1"),
+        ];
+        for &(line, column, message) in &invalid {
+            let source = format!("newmtl m\n        {}\n", line);
+            let err = parse_mtl("test.mtl", &source).err().unwrap();
+            assert_eq!((err.line, err.column), (2, column), "{}", line);
+            assert_eq!(err.message, message);
+        }
+    }
+
+    #[test]
+    fn test_parse_obj() {
+        let source = "
+            mtllib test.mtl
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vn 0 0 1
+            f 1 2 3
+            usemtl wall
+            f 1//1 2//1 3//1 4//1
+            usemtl lamp
+            f -4/1 -3/2 -1/3
+        ";
+
+        let mesh = parse("test.obj", source, |name, _, _| {
+            assert_eq!(name, "test.mtl");
+            parse_mtl(name, MTL)
+        })
+        .unwrap();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(
+            mesh.faces,
+            vec![
+                ObjFace { vertices: [0, 1, 2], material: None },
+                ObjFace { vertices: [0, 1, 2], material: Some(0) },
+                ObjFace { vertices: [0, 2, 3], material: Some(0) },
+                ObjFace { vertices: [0, 1, 3], material: Some(1) },
+            ]
+        );
+        assert_eq!(mesh.materials.len(), 2);
+        assert!(mesh.materials[1].is_emissive());
+    }
+
+    #[test]
+    fn test_invalid_face() {
+        let err = parse("test.obj", "v 0 0 0\nv 1 0 0\nf 1 2 3", |_, _, _| Ok(HashMap::new()))
+            .err()
+            .unwrap();
+        assert_eq!((err.line, err.column), (3, 7));
+    }
+}
@@ -0,0 +1,605 @@
+// Loader for triangle meshes in the Stanford PLY format.
+//
+// ASCII as well as little and big endian binary files are supported. Only the `vertex`
+// element (x, y, z and optional nx, ny, nz) and the `face` element (a `vertex_indices`
+// list) are used, all other elements and properties are skipped. Polygons are fan
+// triangulated. If the file contains vertex normals, they are interpolated for shading.
+use std::convert::TryInto;
+
+use crate::{
+    bsdf::{Bsdf, LambertianBsdf},
+    loader::{is_degenerate, LoadError, MeshStats, MeshTransform},
+    math::{Point3, Vec3},
+    scene::Scene,
+    shape::Triangle,
+    spectrum::{ConstantSpectrum, Spectrum},
+};
+
+const DEFAULT_ALBEDO: f32 = 0.8;
+
+#[derive(Debug, Default)]
+pub struct PlyMesh {
+    pub vertices: Vec<Point3>,
+    // empty if the file has no vertex normals
+    pub normals: Vec<Vec3>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+// Loads the mesh at `path` into the scene, skipping degenerate faces.
+// Faces get `material` (a gray diffuse BSDF if none is given) and `emission`.
+pub fn load(
+    path: &str,
+    scene: &mut Scene,
+    transform: MeshTransform,
+    material: Option<Bsdf>,
+    emission: Option<Spectrum>,
+) -> Result<MeshStats, LoadError> {
+    let data = std::fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    let mesh = parse(path, &data)?;
+
+    let material = material.unwrap_or_else(|| {
+        Bsdf::from(LambertianBsdf::new(ConstantSpectrum::new(DEFAULT_ALBEDO)))
+    });
+    // a negative scale mirrors the mesh and with it the normals
+    let normal_sign = transform.scale.signum();
+
+    let mut stats = MeshStats::default();
+    for face in &mesh.faces {
+        let v1 = transform.apply(mesh.vertices[face[0]]);
+        let v2 = transform.apply(mesh.vertices[face[1]]);
+        let v3 = transform.apply(mesh.vertices[face[2]]);
+
+        if is_degenerate(v1, v2, v3) {
+            stats.degenerate += 1;
+            continue;
+        }
+
+        let triangle = if mesh.normals.is_empty() {
+            Triangle::new(v1, v2, v3)
+        } else {
+            let normals = [
+                normal_sign * mesh.normals[face[0]],
+                normal_sign * mesh.normals[face[1]],
+                normal_sign * mesh.normals[face[2]],
+            ];
+            Triangle::with_normals(v1, v2, v3, normals)
+        };
+
+        match &emission {
+            Some(emission) => {
+                scene.add_emissive_material(triangle, material.clone(), emission.clone())
+            }
+            None => scene.add_material(triangle, material.clone()),
+        }
+        stats.triangles += 1;
+    }
+
+    Ok(stats)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, ScalarType),
+    List(String, ScalarType, ScalarType),
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+pub fn parse(file: &str, data: &[u8]) -> Result<PlyMesh, LoadError> {
+    let (format, elements, body_start, body_line) = parse_header(file, data)?;
+
+    let mut body = match format {
+        Format::Ascii => Body::Ascii {
+            lines: std::str::from_utf8(&data[body_start..])
+                .map_err(|_| LoadError::new(file, body_line, 1, "invalid ascii data"))?
+                .lines()
+                .collect(),
+            line: 0,
+            tokens: Vec::new(),
+        },
+        _ => Body::Binary {
+            data: &data[body_start..],
+            pos: 0,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut mesh = PlyMesh::default();
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(file, body_line, element, &mut body, &mut mesh)?,
+            "face" => read_faces(file, body_line, element, &mut body, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    body.begin_element(file, body_line, element)?;
+                    for property in &element.properties {
+                        match property {
+                            Property::Scalar(_, ty) => {
+                                body.read(file, body_line, element, *ty)?;
+                            }
+                            Property::List(_, count_ty, item_ty) => {
+                                let count = body.read(file, body_line, element, *count_ty)?;
+                                for _ in 0..(count as usize) {
+                                    body.read(file, body_line, element, *item_ty)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn parse_header(
+    file: &str,
+    data: &[u8],
+) -> Result<(Format, Vec<Element>, usize, usize), LoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    let mut line_number = 0;
+
+    loop {
+        let line_end = match data[pos..].iter().position(|&b| b == b'\n') {
+            Some(i) => pos + i,
+            None => return Err(LoadError::new(file, 0, 0, "missing `end_header`")),
+        };
+        line_number += 1;
+        let line = std::str::from_utf8(&data[pos..line_end])
+            .map_err(|_| LoadError::new(file, line_number, 1, "header is not valid text"))?
+            .trim_end_matches('\r');
+        pos = line_end + 1;
+
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let error = |message: &str| LoadError::new(file, line_number, 1, message);
+
+        if line_number == 1 {
+            if line != "ply" {
+                return Err(error("not a PLY file"));
+            }
+            continue;
+        }
+
+        match words.first().copied() {
+            Some("format") => {
+                format = Some(match words.get(1).copied() {
+                    Some("ascii") => Format::Ascii,
+                    Some("binary_little_endian") => Format::BinaryLittleEndian,
+                    Some("binary_big_endian") => Format::BinaryBigEndian,
+                    _ => return Err(error("unknown PLY format")),
+                });
+            }
+            Some("element") => {
+                let count = words.get(2).and_then(|c| c.parse::<usize>().ok());
+                match (words.get(1), count) {
+                    (Some(name), Some(count)) => elements.push(Element {
+                        name: name.to_string(),
+                        count,
+                        properties: Vec::new(),
+                    }),
+                    _ => return Err(error("expected `element <name> <count>`")),
+                }
+            }
+            Some("property") => {
+                let element = match elements.last_mut() {
+                    Some(element) => element,
+                    None => return Err(error("property before the first element")),
+                };
+                let property = if words.get(1) == Some(&"list") {
+                    let count_ty = words.get(2).and_then(|t| ScalarType::parse(t));
+                    let item_ty = words.get(3).and_then(|t| ScalarType::parse(t));
+                    match (count_ty, item_ty, words.get(4)) {
+                        (Some(count_ty), Some(item_ty), Some(name)) => {
+                            Property::List(name.to_string(), count_ty, item_ty)
+                        }
+                        _ => return Err(error("expected `property list <type> <type> <name>`")),
+                    }
+                } else {
+                    let ty = words.get(1).and_then(|t| ScalarType::parse(t));
+                    match (ty, words.get(2)) {
+                        (Some(ty), Some(name)) => Property::Scalar(name.to_string(), ty),
+                        _ => return Err(error("expected `property <type> <name>`")),
+                    }
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {}
+            Some(other) => {
+                return Err(error(&format!("unknown header keyword `{}`", other)));
+            }
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements, pos, line_number + 1)),
+        None => Err(LoadError::new(file, 0, 0, "missing `format` in header")),
+    }
+}
+
+fn read_vertices(
+    file: &str,
+    body_line: usize,
+    element: &Element,
+    body: &mut Body,
+    mesh: &mut PlyMesh,
+) -> Result<(), LoadError> {
+    let index_of = |wanted: &str| {
+        element.properties.iter().position(|p| match p {
+            Property::Scalar(name, _) => name == wanted,
+            Property::List(..) => false,
+        })
+    };
+
+    let (x, y, z) = match (index_of("x"), index_of("y"), index_of("z")) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => return Err(LoadError::new(file, 0, 0, "vertex element needs x, y and z")),
+    };
+    let normal = match (index_of("nx"), index_of("ny"), index_of("nz")) {
+        (Some(nx), Some(ny), Some(nz)) => Some((nx, ny, nz)),
+        _ => None,
+    };
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        body.begin_element(file, body_line, element)?;
+        for (value, property) in values.iter_mut().zip(&element.properties) {
+            *value = match property {
+                Property::Scalar(_, ty) => body.read(file, body_line, element, *ty)?,
+                Property::List(_, count_ty, item_ty) => {
+                    let count = body.read(file, body_line, element, *count_ty)?;
+                    for _ in 0..(count as usize) {
+                        body.read(file, body_line, element, *item_ty)?;
+                    }
+                    0.0
+                }
+            };
+        }
+
+        let vertex = Point3::new(values[x] as f32, values[y] as f32, values[z] as f32);
+        if !(vertex.x().is_finite() && vertex.y().is_finite() && vertex.z().is_finite()) {
+            return Err(body.error(file, body_line, element, "vertex is not finite"));
+        }
+        mesh.vertices.push(vertex);
+
+        if let Some((nx, ny, nz)) = normal {
+            let n = [values[nx] as f32, values[ny] as f32, values[nz] as f32];
+            // unusable normals are zeroed, the triangle then falls back to its geometric normal
+            let n = if n.iter().all(|c| c.is_finite()) { n } else { [0.0; 3] };
+            mesh.normals.push(Vec3::new(n[0], n[1], n[2]));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_faces(
+    file: &str,
+    body_line: usize,
+    element: &Element,
+    body: &mut Body,
+    mesh: &mut PlyMesh,
+) -> Result<(), LoadError> {
+    let indices_property = element.properties.iter().position(|p| match p {
+        Property::List(name, ..) => name == "vertex_indices" || name == "vertex_index",
+        Property::Scalar(..) => false,
+    });
+    let indices_property = match indices_property {
+        Some(i) => i,
+        None => return Err(LoadError::new(file, 0, 0, "face element needs vertex_indices")),
+    };
+
+    let mut indices = Vec::new();
+    for _ in 0..element.count {
+        body.begin_element(file, body_line, element)?;
+        for (i, property) in element.properties.iter().enumerate() {
+            match property {
+                Property::Scalar(_, ty) => {
+                    body.read(file, body_line, element, *ty)?;
+                }
+                Property::List(_, count_ty, item_ty) => {
+                    let count = body.read(file, body_line, element, *count_ty)? as usize;
+                    indices.clear();
+                    for _ in 0..count {
+                        indices.push(body.read(file, body_line, element, *item_ty)?);
+                    }
+
+                    if i != indices_property {
+                        continue;
+                    }
+                    if count < 3 {
+                        return Err(body.error(file, body_line, element, "face has less than three vertices"));
+                    }
+
+                    let mut face = Vec::with_capacity(count);
+                    for &index in &indices {
+                        if index < 0.0 || index as usize >= mesh.vertices.len() {
+                            return Err(body.error(
+                                file,
+                                body_line,
+                                element,
+                                &format!("vertex index {} out of range", index),
+                            ));
+                        }
+                        face.push(index as usize);
+                    }
+
+                    // fan triangulation
+                    for j in 1..(face.len() - 1) {
+                        mesh.faces.push([face[0], face[j], face[j + 1]]);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Body<'a> {
+    Ascii {
+        lines: Vec<&'a str>,
+        // index of the next line and the remaining tokens of the current one
+        line: usize,
+        tokens: Vec<&'a str>,
+    },
+    Binary {
+        data: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl<'a> Body<'a> {
+    fn error(&self, file: &str, body_line: usize, element: &Element, message: &str) -> LoadError {
+        match self {
+            Body::Ascii { line, .. } => {
+                LoadError::new(file, body_line + line.saturating_sub(1), 1, message)
+            }
+            Body::Binary { pos, .. } => LoadError::new(
+                file,
+                0,
+                0,
+                format!("{} (element `{}` at byte {} of the body)", message, element.name, pos),
+            ),
+        }
+    }
+
+    // In ascii files every element instance is on its own line.
+    fn begin_element(
+        &mut self,
+        file: &str,
+        body_line: usize,
+        element: &Element,
+    ) -> Result<(), LoadError> {
+        if let Body::Ascii { lines, line, tokens } = self {
+            loop {
+                match lines.get(*line) {
+                    Some(text) => {
+                        *line += 1;
+                        *tokens = text.split_whitespace().rev().collect();
+                        if !tokens.is_empty() {
+                            return Ok(());
+                        }
+                    }
+                    None => {
+                        return Err(self.error(file, body_line, element, "unexpected end of file"));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read(
+        &mut self,
+        file: &str,
+        body_line: usize,
+        element: &Element,
+        ty: ScalarType,
+    ) -> Result<f64, LoadError> {
+        let value = match self {
+            Body::Ascii { tokens, .. } => {
+                tokens.pop().and_then(|token| token.parse::<f64>().ok())
+            }
+            Body::Binary { data, pos, big_endian } => {
+                let size = ty.size();
+                if *pos + size > data.len() {
+                    None
+                } else {
+                    let bytes = &data[*pos..(*pos + size)];
+                    *pos += size;
+                    Some(decode(bytes, ty, *big_endian))
+                }
+            }
+        };
+
+        match value {
+            Some(value) => Ok(value),
+            None => Err(self.error(file, body_line, element, "missing or invalid value")),
+        }
+    }
+}
+
+fn decode(bytes: &[u8], ty: ScalarType, big_endian: bool) -> f64 {
+    macro_rules! decode_as {
+        ($t:ty) => {{
+            let bytes = bytes.try_into().unwrap();
+            (if big_endian { <$t>::from_be_bytes(bytes) } else { <$t>::from_le_bytes(bytes) })
+                as f64
+        }};
+    }
+
+    match ty {
+        ScalarType::I8 => decode_as!(i8),
+        ScalarType::U8 => decode_as!(u8),
+        ScalarType::I16 => decode_as!(i16),
+        ScalarType::U16 => decode_as!(u16),
+        ScalarType::I32 => decode_as!(i32),
+        ScalarType::U32 => decode_as!(u32),
+        ScalarType::F32 => decode_as!(f32),
+        ScalarType::F64 => decode_as!(f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Ray;
+
+    const HEADER: &str = "ply
+format {}
+comment a unit square with normals
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element face 2
+property list uchar int vertex_indices
+end_header
+";
+
+    fn vertices() -> [[f32; 6]; 4] {
+        [
+            [0.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            [1.0, 0.0, 0.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        ]
+    }
+
+    fn check(mesh: &PlyMesh) {
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.vertices[2], Point3::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.normals.len(), 4);
+        assert_eq!(mesh.normals[3], Vec3::new(0.0, 0.0, 1.0));
+        // the quad is fan triangulated, the second face is degenerate but still parsed
+        assert_eq!(mesh.faces, vec![[0, 1, 2], [0, 2, 3], [0, 0, 1]]);
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut source = HEADER.replace("{}", "ascii 1.0");
+        for v in &vertices() {
+            let v = v.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            source.push_str(&v.join(" "));
+            source.push('\n');
+        }
+        source.push_str("4 0 1 2 3\n3 0 0 1\n");
+
+        check(&parse("test.ply", source.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_binary() {
+        for &big_endian in &[false, true] {
+            let format = if big_endian { "binary_big_endian 1.0" } else { "binary_little_endian 1.0" };
+            let mut data = HEADER.replace("{}", format).into_bytes();
+            for v in &vertices() {
+                for c in v {
+                    let bytes = if big_endian { c.to_be_bytes() } else { c.to_le_bytes() };
+                    data.extend_from_slice(&bytes);
+                }
+            }
+            for face in &[&[0i32, 1, 2, 3][..], &[0, 0, 1][..]] {
+                data.push(face.len() as u8);
+                for i in face.iter() {
+                    let bytes = if big_endian { i.to_be_bytes() } else { i.to_le_bytes() };
+                    data.extend_from_slice(&bytes);
+                }
+            }
+
+            check(&parse("test.ply", &data).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        // the vertex normals lean away from the geometric normal, which points up for this
+        // winding, so the triangles are flipped to face down
+        let mut source = HEADER.replace("{}", "ascii 1.0");
+        source.push_str("0 0 0 0 0 -1\n1 0 0 1 0 -1\n1 1 0 0 1 -1\n0 1 0 0 0 -1\n");
+        source.push_str("4 0 1 2 3\n3 0 0 1\n");
+        let path = std::env::temp_dir().join(format!("iris-test-{}.ply", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+
+        let mut scene = Scene::default();
+        let result = load(path.to_str().unwrap(), &mut scene, MeshTransform::default(), None, None);
+        std::fs::remove_file(&path).unwrap();
+        let stats = result.unwrap();
+        assert_eq!((stats.triangles, stats.degenerate), (2, 1));
+
+        // (0.75, 0.5) is the barycentric point (0.25, 0.25, 0.5) of the first triangle
+        scene.build_bvh();
+        let ray = Ray::new(Point3::new(0.75, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let (_, hit) = scene.intersection(&ray).unwrap();
+        assert!(!hit.back_face);
+        let expected = Vec3::new(0.25, 0.5, -1.0).normalize();
+        assert!((hit.normal - expected).len() < 1e-5, "{:?}", hit.normal);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut data = HEADER.replace("{}", "binary_little_endian 1.0").into_bytes();
+        data.extend_from_slice(&[0; 10]);
+        let err = parse("test.ply", &data).err().unwrap();
+        assert!(err.message.starts_with("missing or invalid value (element `vertex`"));
+    }
+}
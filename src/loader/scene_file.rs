@@ -0,0 +1,742 @@
+// Parser for the plain text scene description format.
+//
+// A scene file is a sequence of statements, one per line. Everything after a `#` is a
+// comment. The following statements are understood:
+//
+//   resolution <width> <height>
+//   spp <samples per pixel>
+//   camera <x> <y> <z> [fov <degrees>]
+//   color_space <srgb|rec2020|acescg>
+//   spectrum <name> rgb <r> <g> <b> [scale <s>]
+//   spectrum <name> constant <value>
+//   spectrum <name> file <file> [scale <s>] [extrapolate <clamp|zero>]
+//   spectrum <name> blackbody <kelvin> [normalized] [scale <s>]
+//   spectrum <name> illuminant <a|d50|d55|d65|d75|f1..f12> [scale <s>]
+//   material <name> lambertian <spectrum>
+//   material <name> microfacet <spectrum> <roughness x> <roughness y>
+//   material <name> specular <spectrum>
+//   material <name> fresnel <reflected spectrum> <transmitted spectrum> <ior> <dispersion>
+//   sphere <x> <y> <z> <radius> [material <name>] [emission <spectrum>]
+//   triangle <x y z> <x y z> <x y z> [material <name>] [emission <spectrum>]
+//   box <center x y z> <half size x y z> [material <name>] [emission <spectrum>]
+//   mesh <file> [scale <s>] [translate <x y z>] [material <name>] [emission <spectrum>]
+//
+// RGB colors, of `rgb` spectra and of the MTL libraries of meshes, are in the color space
+// set by the last `color_space` statement before them, sRGB by default.
+// `file` spectra are measured spectra read from CSV or SPD files, see `loader::spd`. Outside
+// of the measured range they hold the nearest value, or are zero with `extrapolate zero`.
+// `blackbody` spectra are in W / (sr m^2 nm), or peak at one if `normalized`. The CIE
+// `illuminant` spectra are relative, 100 at 560nm.
+// Wherever a spectrum is expected, a plain number can be given instead of a name as a
+// shorthand for a constant spectrum. Every shape needs a material, an emission or both.
+// Meshes are Wavefront OBJ or PLY files, their path is relative to the scene file. A material
+// or emission given for a mesh replaces the one from its MTL library. Degenerate triangles are
+// skipped with a warning.
+// See the `scenes` directory for examples.
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SpecularBsdf},
+    camera::Camera,
+    color::ColorSpace,
+    loader::{obj, ply, spd, tokenize, LoadError, MeshTransform, RgbUpsampler, Token},
+    math::Point3,
+    scene::Scene,
+    shape::{Geometry, Sphere, Triangle},
+    spectrum::{
+        illuminant, BlackbodySpectrum, CieIlluminant, ConstantSpectrum, Extrapolation, Spectrum,
+        TabulatedSpectrum,
+    },
+};
+
+const DEFAULT_FOV: f32 = 90.0;
+
+pub struct SceneDescription {
+    pub scene: Scene,
+    pub camera: Camera,
+    pub width: usize,
+    pub height: usize,
+    pub spp: usize,
+}
+
+pub fn load(path: &str) -> Result<SceneDescription, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse(path, &source)
+}
+
+pub fn parse(file: &str, source: &str) -> Result<SceneDescription, LoadError> {
+    let mut parser = Parser::new(file);
+
+    for (i, line) in source.lines().enumerate() {
+        let tokens = tokenize(line);
+        if tokens.is_empty() {
+            continue;
+        }
+
+        let mut statement = Statement {
+            file,
+            tokens,
+            pos: 0,
+            line: i + 1,
+            end_column: line.chars().count() + 1,
+        };
+        parser.statement(&mut statement)?;
+    }
+
+    parser.finish()
+}
+
+// The tokens of a single line, consumed from left to right.
+struct Statement<'a> {
+    file: &'a str,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    line: usize,
+    end_column: usize,
+}
+
+impl<'a> Statement<'a> {
+    fn error<S: Into<String>>(&self, token: Option<Token>, message: S) -> LoadError {
+        let column = token.map_or(self.end_column, |t| t.column);
+        LoadError::new(self.file, self.line, column, message)
+    }
+
+    fn current(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.current();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.current().map(|t| t.text)
+    }
+
+    fn word(&mut self, what: &str) -> Result<Token<'a>, LoadError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(self.error(None, format!("expected {}", what))),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<f32, LoadError> {
+        let token = self.word(what)?;
+        match token.text.parse::<f32>() {
+            Ok(value) if value.is_finite() => Ok(value),
+            _ => Err(self.error(
+                Some(token),
+                format!("expected {}, found `{}`", what, token.text),
+            )),
+        }
+    }
+
+    fn count(&mut self, what: &str) -> Result<usize, LoadError> {
+        let token = self.word(what)?;
+        match token.text.parse::<usize>() {
+            Ok(value) if value > 0 => Ok(value),
+            _ => Err(self.error(
+                Some(token),
+                format!("expected {} (a positive integer), found `{}`", what, token.text),
+            )),
+        }
+    }
+
+    fn point(&mut self, what: &str) -> Result<Point3, LoadError> {
+        let x = self.number(&format!("x coordinate of {}", what))?;
+        let y = self.number(&format!("y coordinate of {}", what))?;
+        let z = self.number(&format!("z coordinate of {}", what))?;
+        Ok(Point3::new(x, y, z))
+    }
+
+    fn end(&mut self) -> Result<(), LoadError> {
+        match self.next() {
+            Some(token) => Err(self.error(
+                Some(token),
+                format!("unexpected `{}` at end of statement", token.text),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    file: &'a str,
+    width: usize,
+    height: usize,
+    spp: usize,
+    camera_position: Point3,
+    fov: f32,
+    upsampler: RgbUpsampler,
+    spectra: HashMap<String, Spectrum>,
+    // with the material index of each in the scene
+    materials: HashMap<String, (Bsdf, usize)>,
+    scene: Scene,
+}
+
+impl<'a> Parser<'a> {
+    fn new(file: &'a str) -> Self {
+        Self {
+            file,
+            width: crate::WIDTH,
+            height: crate::HEIGHT,
+            spp: crate::TOTAL_SPP,
+            camera_position: Point3::new(0.0, 0.0, 0.0),
+            fov: DEFAULT_FOV,
+            upsampler: RgbUpsampler::default(),
+            spectra: HashMap::new(),
+            materials: HashMap::new(),
+            scene: Scene::default(),
+        }
+    }
+
+    fn statement(&mut self, st: &mut Statement) -> Result<(), LoadError> {
+        let keyword = st.word("statement")?;
+
+        match keyword.text {
+            "resolution" => {
+                self.width = st.count("image width")?;
+                self.height = st.count("image height")?;
+            }
+            "spp" => {
+                self.spp = st.count("samples per pixel")?;
+            }
+            "camera" => {
+                self.camera_position = st.point("camera position")?;
+                if st.peek() == Some("fov") {
+                    st.next();
+                    let fov_token = st.current();
+                    let fov = st.number("field of view")?;
+                    if fov <= 0.0 || fov >= 180.0 {
+                        return Err(st.error(
+                            fov_token,
+                            "field of view must be between 0 and 180 degrees",
+                        ));
+                    }
+                    self.fov = fov;
+                }
+            }
+            "color_space" => {
+                let name = st.word("color space")?;
+                match ColorSpace::from_name(name.text) {
+                    Some(color_space) => self.upsampler.set_color_space(color_space),
+                    None => {
+                        return Err(st.error(
+                            Some(name),
+                            format!(
+                                "unknown color space `{}`, expected `srgb`, `rec2020` or `acescg`",
+                                name.text
+                            ),
+                        ));
+                    }
+                }
+            }
+            "spectrum" => self.spectrum_statement(st)?,
+            "material" => self.material_statement(st)?,
+            "sphere" => {
+                let center = st.point("sphere center")?;
+                let radius_token = st.current();
+                let radius = st.number("sphere radius")?;
+                if radius <= 0.0 {
+                    return Err(st.error(radius_token, "sphere radius must be positive"));
+                }
+                self.shape_statement(st, Sphere::new(center, radius))?;
+            }
+            "triangle" => {
+                let v1 = st.point("first vertex")?;
+                let v2 = st.point("second vertex")?;
+                let v3 = st.point("third vertex")?;
+                if (v2 - v1).cross(v3 - v1).len_squared() == 0.0 {
+                    return Err(st.error(Some(keyword), "triangle is degenerate"));
+                }
+                self.shape_statement(st, Triangle::new(v1, v2, v3))?;
+            }
+            "box" => {
+                let center = st.point("box center")?;
+                let half_size = st.point("box half size")?.to_vec();
+                if half_size.x() <= 0.0 || half_size.y() <= 0.0 || half_size.z() <= 0.0 {
+                    return Err(st.error(Some(keyword), "box half size must be positive"));
+                }
+
+                let (material, emission) = self.shape_attributes(st)?;
+                let vertices = Scene::get_vertices_closed_box(center, half_size);
+                for v in vertices.chunks_exact(3) {
+                    self.add_shape(
+                        Triangle::new(v[0], v[1], v[2]),
+                        material.clone(),
+                        emission.clone(),
+                    );
+                }
+            }
+            "mesh" => self.mesh_statement(st)?,
+            other => {
+                return Err(st.error(Some(keyword), format!("unknown statement `{}`", other)));
+            }
+        }
+
+        st.end()
+    }
+
+    fn spectrum_statement(&mut self, st: &mut Statement) -> Result<(), LoadError> {
+        let name = st.word("spectrum name")?;
+        if self.spectra.contains_key(name.text) {
+            return Err(st.error(
+                Some(name),
+                format!("spectrum `{}` is already defined", name.text),
+            ));
+        }
+
+        let kind = st.word("spectrum type")?;
+        let spectrum = match kind.text {
+            "rgb" => {
+                let rgb = [
+                    st.number("red component")?,
+                    st.number("green component")?,
+                    st.number("blue component")?,
+                ];
+                if rgb.iter().any(|&c| c < 0.0) {
+                    return Err(st.error(Some(kind), "rgb components must not be negative"));
+                }
+
+                let scale = optional_scale(st)?;
+
+                self.upsampler.spectrum(rgb, scale)
+            }
+            "constant" => {
+                let value_token = st.current();
+                let value = st.number("constant value")?;
+                if value < 0.0 {
+                    return Err(st.error(value_token, "constant spectrum must not be negative"));
+                }
+
+                Spectrum::from(ConstantSpectrum::new(value))
+            }
+            "file" => self.file_spectrum(st)?,
+            "blackbody" => {
+                let temperature_token = st.current();
+                let temperature = st.number("blackbody temperature")?;
+                if temperature <= 0.0 {
+                    return Err(st.error(temperature_token, "temperature must be positive"));
+                }
+
+                let normalized = st.peek() == Some("normalized");
+                if normalized {
+                    st.next();
+                }
+                let scale = optional_scale(st)?;
+
+                let spectrum = if normalized {
+                    BlackbodySpectrum::normalized(temperature)
+                } else {
+                    BlackbodySpectrum::new(temperature)
+                };
+                Spectrum::from(spectrum.scaled(scale))
+            }
+            "illuminant" => {
+                let name = st.word("illuminant name")?;
+                let illuminant = match CieIlluminant::from_name(name.text) {
+                    Some(illuminant) => illuminant,
+                    None => {
+                        return Err(st.error(
+                            Some(name),
+                            format!(
+                                "unknown illuminant `{}`, expected one of {}",
+                                name.text,
+                                illuminant::CIE_ILLUMINANTS.join(", ")
+                            ),
+                        ));
+                    }
+                };
+                let scale = optional_scale(st)?;
+                Spectrum::from(illuminant.spectrum().scaled(scale))
+            }
+            other => {
+                return Err(st.error(
+                    Some(kind),
+                    format!(
+                        "unknown spectrum type `{}`, expected `rgb`, `constant`, `file`, \
+                         `blackbody` or `illuminant`",
+                        other
+                    ),
+                ));
+            }
+        };
+
+        self.spectra.insert(name.text.to_string(), spectrum);
+        Ok(())
+    }
+
+    fn file_spectrum(&mut self, st: &mut Statement) -> Result<Spectrum, LoadError> {
+        let file = st.word("spectrum file name")?;
+        let path = Path::new(self.file).with_file_name(file.text);
+
+        let mut scale = 1.0;
+        let mut extrapolation = Extrapolation::Clamp;
+        while let Some(token) = st.next() {
+            match token.text {
+                "scale" => scale = scale_value(st)?,
+                "extrapolate" => {
+                    let mode = st.word("extrapolation (`clamp` or `zero`)")?;
+                    extrapolation = match mode.text {
+                        "clamp" => Extrapolation::Clamp,
+                        "zero" => Extrapolation::Zero,
+                        other => {
+                            return Err(st.error(
+                                Some(mode),
+                                format!("unknown extrapolation `{}`, expected `clamp` or `zero`", other),
+                            ));
+                        }
+                    };
+                }
+                other => {
+                    return Err(st.error(
+                        Some(token),
+                        format!("unexpected `{}`, expected `scale` or `extrapolate`", other),
+                    ));
+                }
+            }
+        }
+
+        let samples = spd::load(&path.to_string_lossy())?;
+        Ok(Spectrum::from(TabulatedSpectrum::new(&samples, extrapolation).scaled(scale)))
+    }
+
+    fn material_statement(&mut self, st: &mut Statement) -> Result<(), LoadError> {
+        let name = st.word("material name")?;
+        if self.materials.contains_key(name.text) {
+            return Err(st.error(
+                Some(name),
+                format!("material `{}` is already defined", name.text),
+            ));
+        }
+
+        let kind = st.word("material type")?;
+        let material = match kind.text {
+            "lambertian" => Bsdf::from(LambertianBsdf::new(self.spectrum_ref(st)?)),
+            "microfacet" => {
+                let reflectance = self.spectrum_ref(st)?;
+                let roughness_x = st.number("roughness in x direction")?;
+                let roughness_y = st.number("roughness in y direction")?;
+                if roughness_x <= 0.0 || roughness_y <= 0.0 {
+                    return Err(st.error(Some(kind), "microfacet roughness must be positive"));
+                }
+                Bsdf::from(MicrofacetBsdf::new(reflectance, roughness_x, roughness_y))
+            }
+            "specular" => Bsdf::from(SpecularBsdf::new(self.spectrum_ref(st)?)),
+            "fresnel" => {
+                let reflected = self.spectrum_ref(st)?;
+                let transmitted = self.spectrum_ref(st)?;
+                let ior = st.number("index of refraction")?;
+                let dispersion = st.number("dispersion")?;
+                Bsdf::from(FresnelBsdf::new(reflected, transmitted, ior, dispersion))
+            }
+            other => {
+                return Err(st.error(
+                    Some(kind),
+                    format!(
+                        "unknown material type `{}`, expected `lambertian`, `microfacet`, \
+                         `specular` or `fresnel`",
+                        other
+                    ),
+                ));
+            }
+        };
+
+        let index = self.scene.new_material_index();
+        self.materials.insert(name.text.to_string(), (material, index));
+        Ok(())
+    }
+
+    fn mesh_statement(&mut self, st: &mut Statement) -> Result<(), LoadError> {
+        let file = st.word("mesh file name")?;
+        let path = Path::new(self.file).with_file_name(file.text);
+        let path = path.to_string_lossy();
+
+        let mut transform = MeshTransform::default();
+        let mut material = None;
+        let mut emission = None;
+
+        while let Some(token) = st.next() {
+            match token.text {
+                "scale" => transform.scale = st.number("mesh scale")?,
+                "translate" => transform.translation = st.point("mesh translation")?.to_vec(),
+                "material" => material = Some(self.material_ref(st)?),
+                "emission" => emission = Some(self.spectrum_ref(st)?),
+                other => {
+                    return Err(st.error(
+                        Some(token),
+                        format!(
+                            "unexpected `{}`, expected `scale`, `translate`, `material` or \
+                             `emission`",
+                            other
+                        ),
+                    ));
+                }
+            }
+        }
+
+        // the materials of an OBJ file get their own indices when none is given here
+        let material = material.map(|(bsdf, index)| {
+            self.scene.set_material_index(Some(index));
+            bsdf
+        });
+        let extension = Path::new(file.text).extension().and_then(|e| e.to_str());
+        let stats = match extension {
+            Some("obj") => obj::load(
+                &path,
+                &mut self.scene,
+                transform,
+                material,
+                emission,
+                &mut self.upsampler,
+            )?,
+            Some("ply") => ply::load(&path, &mut self.scene, transform, material, emission)?,
+            _ => {
+                return Err(st.error(
+                    Some(file),
+                    format!(
+                        "unsupported mesh format `{}`, expected an .obj or .ply file",
+                        file.text
+                    ),
+                ));
+            }
+        };
+        self.scene.set_material_index(None);
+        if stats.degenerate > 0 {
+            eprintln!(
+                "warning: {}: skipped {} degenerate triangles",
+                path, stats.degenerate
+            );
+        }
+
+        Ok(())
+    }
+
+    fn material_ref(&self, st: &mut Statement) -> Result<(Bsdf, usize), LoadError> {
+        let name = st.word("material name")?;
+        match self.materials.get(name.text) {
+            Some(material) => Ok(material.clone()),
+            None => Err(st.error(Some(name), format!("unknown material `{}`", name.text))),
+        }
+    }
+
+    // Reads either the name of a previously defined spectrum or a number for a constant
+    // spectrum.
+    fn spectrum_ref(&self, st: &mut Statement) -> Result<Spectrum, LoadError> {
+        let token = st.word("spectrum")?;
+        if let Ok(value) = token.text.parse::<f32>() {
+            if !value.is_finite() {
+                return Err(st.error(
+                    Some(token),
+                    format!("constant spectrum `{}` is not finite", token.text),
+                ));
+            }
+            if value < 0.0 {
+                return Err(st.error(Some(token), "constant spectrum must not be negative"));
+            }
+            return Ok(Spectrum::from(ConstantSpectrum::new(value)));
+        }
+
+        match self.spectra.get(token.text) {
+            Some(spectrum) => Ok(spectrum.clone()),
+            None => Err(st.error(Some(token), format!("unknown spectrum `{}`", token.text))),
+        }
+    }
+
+    fn shape_attributes(
+        &self,
+        st: &mut Statement,
+    ) -> Result<(Option<(Bsdf, usize)>, Option<Spectrum>), LoadError> {
+        let mut material = None;
+        let mut emission = None;
+
+        while let Some(token) = st.next() {
+            match token.text {
+                "material" => material = Some(self.material_ref(st)?),
+                "emission" => emission = Some(self.spectrum_ref(st)?),
+                other => {
+                    return Err(st.error(
+                        Some(token),
+                        format!("unexpected `{}`, expected `material` or `emission`", other),
+                    ));
+                }
+            }
+        }
+
+        if material.is_none() && emission.is_none() {
+            return Err(st.error(None, "shape needs a `material`, an `emission` or both"));
+        }
+
+        Ok((material, emission))
+    }
+
+    fn shape_statement<G: Into<Geometry>>(
+        &mut self,
+        st: &mut Statement,
+        geometry: G,
+    ) -> Result<(), LoadError> {
+        let (material, emission) = self.shape_attributes(st)?;
+        self.add_shape(geometry, material, emission);
+        Ok(())
+    }
+
+    fn add_shape<G: Into<Geometry>>(
+        &mut self,
+        geometry: G,
+        material: Option<(Bsdf, usize)>,
+        emission: Option<Spectrum>,
+    ) {
+        let material = material.map(|(bsdf, index)| {
+            self.scene.set_material_index(Some(index));
+            bsdf
+        });
+        match (material, emission) {
+            (Some(material), Some(emission)) => {
+                self.scene.add_emissive_material(geometry, material, emission)
+            }
+            (Some(material), None) => self.scene.add_material(geometry, material),
+            (None, Some(emission)) => self.scene.add_light(geometry, emission),
+            (None, None) => unreachable!(),
+        }
+        self.scene.set_material_index(None);
+    }
+
+    fn finish(self) -> Result<SceneDescription, LoadError> {
+        if self.scene.lights.is_empty() {
+            return Err(LoadError::new(self.file, 0, 0, "scene contains no light source"));
+        }
+
+        Ok(SceneDescription {
+            scene: self.scene,
+            camera: Camera::with_fov(
+                self.camera_position,
+                (self.width as f32) / (self.height as f32),
+                self.fov,
+            ),
+            width: self.width,
+            height: self.height,
+            spp: self.spp,
+        })
+    }
+}
+
+// The optional `scale <s>` at the end of a spectrum statement, which must not make the
+// spectrum negative
+fn optional_scale(st: &mut Statement) -> Result<f32, LoadError> {
+    if st.peek() != Some("scale") {
+        return Ok(1.0);
+    }
+    st.next();
+    scale_value(st)
+}
+
+// The value after `scale`
+fn scale_value(st: &mut Statement) -> Result<f32, LoadError> {
+    let scale_token = st.current();
+    let scale = st.number("spectrum scale")?;
+    if scale < 0.0 {
+        return Err(st.error(scale_token, "spectrum scale must not be negative"));
+    }
+    Ok(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_scene() {
+        let source = "
+            # a small test scene
+            resolution 64 32
+            spp 16
+            camera 0 0 -1 fov 60
+
+            spectrum white constant 0.8
+            spectrum sun blackbody 5778 normalized scale 10
+            spectrum lamp illuminant f11 scale 0.5
+            material wall lambertian white
+            material glass fresnel white 1.0 1.5 0.0
+
+            triangle -1 -1 2  1 -1 2  0 1 2  material wall
+            sphere 0 0.8 1 0.1 material wall emission 70
+            sphere 0 0 1 0.25 material glass
+            box 0 -1 1  0.5 0.1 0.5  material wall
+            sphere 0 5 0 1 emission white
+        ";
+
+        let description = parse("test.scene", source).unwrap();
+        assert_eq!(description.width, 64);
+        assert_eq!(description.height, 32);
+        assert_eq!(description.spp, 16);
+        assert_eq!(description.camera.position, Point3::new(0.0, 0.0, -1.0));
+
+        let scene = description.scene;
+        assert_eq!(scene.primitives.len(), 3 + 12 + 1);
+        assert_eq!(scene.materials.len(), 3 + 12);
+        assert_eq!(scene.lights.len(), 2);
+
+        // the primitives made of the same material share its index
+        let material_indices = scene.primitives.iter().map(|p| scene.material_index(p)).collect::<Vec<_>>();
+        assert_eq!(&material_indices[..3], &[Some(0), Some(0), Some(1)]);
+        assert!(material_indices[3..15].iter().all(|&index| index == Some(0)));
+        assert_eq!(material_indices[15], None);
+    }
+
+    #[test]
+    fn test_error_position() {
+        let source = "spectrum white constant 1\nsphere 0 0 1 0.5 material gray emission white\n";
+        let err = parse("test.scene", source).err().unwrap();
+        assert_eq!((err.line, err.column), (2, 27));
+        assert_eq!(err.message, "unknown material `gray`");
+
+        let err = parse("test.scene", "sphere 0 0 x 1 emission 1").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 12));
+
+        let err = parse("test.scene", "sphere 0 0 1").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 13));
+        assert_eq!(err.message, "expected sphere radius");
+
+        let err = parse("test.scene", "spectrum sky illuminant d60").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 25));
+        assert!(err.message.starts_with("unknown illuminant `d60`"));
+
+        // negative scales of any spectrum kind
+        let err = parse("test.scene", "spectrum gray rgb 0.8 0.8 0.8 scale -1").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 37));
+        assert_eq!(err.message, "spectrum scale must not be negative");
+        let err = parse("test.scene", "spectrum sun blackbody 5800 normalized scale -2").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 46));
+        assert_eq!(err.message, "spectrum scale must not be negative");
+        let err = parse("test.scene", "spectrum sky illuminant d65 scale -0.5").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 35));
+
+        let err = parse("test.scene", "spectrum leaf file leaf.csv scale -3").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 35));
+
+        // negative constants, defined or inline
+        let err = parse("test.scene", "spectrum dark constant -1").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 24));
+        assert_eq!(err.message, "constant spectrum must not be negative");
+        let err = parse("test.scene", "sphere 0 0 1 1 emission -1").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 25));
+        assert_eq!(err.message, "constant spectrum must not be negative");
+        let err = parse("test.scene", "sphere 0 0 1 1 emission 1e40").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 25));
+        assert_eq!(err.message, "constant spectrum `1e40` is not finite");
+
+        let err = parse("test.scene", "color_space p3").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 13));
+        assert!(err.message.starts_with("unknown color space `p3`"));
+    }
+
+    #[test]
+    fn test_no_light() {
+        let err = parse("test.scene", "material m lambertian 0.5\nsphere 0 0 1 1 material m")
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "test.scene: scene contains no light source");
+    }
+}
@@ -0,0 +1,117 @@
+// Loader for measured spectra as two-column text files.
+//
+// Each line holds a wavelength in nm followed by its value, separated by whitespace, a
+// comma or a semicolon, so both CSV files and pbrt style SPD files can be read. SPD files
+// may have several pairs on one line. Everything after a `#` is a comment, and lines
+// before the first sample that do not start with a number (e.g. a CSV header) are
+// skipped. The wavelengths must be increasing and the values must not be negative.
+use crate::loader::{tokenize, LoadError};
+
+pub fn load(path: &str) -> Result<Vec<(f32, f32)>, LoadError> {
+    let source = std::fs::read_to_string(path).map_err(|e| LoadError::io(path, e))?;
+    parse(path, &source)
+}
+
+pub fn parse(file: &str, source: &str) -> Result<Vec<(f32, f32)>, LoadError> {
+    let mut samples: Vec<(f32, f32)> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        // separators are replaced by spaces of the same width to keep the columns
+        let line = line.replace([',', ';'], " ");
+        let tokens = tokenize(&line);
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if samples.is_empty() && tokens[0].text.parse::<f32>().is_err() {
+            continue;
+        }
+
+        let mut numbers = Vec::with_capacity(tokens.len());
+        for token in &tokens {
+            match token.text.parse::<f32>() {
+                Ok(value) if value.is_finite() => numbers.push(value),
+                _ => {
+                    return Err(LoadError::new(
+                        file,
+                        i + 1,
+                        token.column,
+                        format!("expected a number, found `{}`", token.text),
+                    ));
+                }
+            }
+        }
+        if numbers.len() % 2 != 0 {
+            let token = tokens[tokens.len() - 1];
+            return Err(LoadError::new(file, i + 1, token.column, "wavelength without a value"));
+        }
+
+        for (pair, pair_tokens) in numbers.chunks_exact(2).zip(tokens.chunks_exact(2)) {
+            let (lambda, value) = (pair[0], pair[1]);
+            if let Some(&(previous, _)) = samples.last() {
+                if lambda <= previous {
+                    return Err(LoadError::new(
+                        file,
+                        i + 1,
+                        pair_tokens[0].column,
+                        format!("wavelength {} is not above the previous one, {}", lambda, previous),
+                    ));
+                }
+            }
+            // a spectrum is never negative
+            if value < 0.0 {
+                return Err(LoadError::new(
+                    file,
+                    i + 1,
+                    pair_tokens[1].column,
+                    format!("value {} at {} nm is negative", value, lambda),
+                ));
+            }
+            samples.push((lambda, value));
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(LoadError::new(file, 0, 0, "file contains no samples"));
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let csv = "wavelength,reflectance\n400,0.1\n410, 0.2 # comment\n\n420;0.25\n";
+        assert_eq!(
+            parse("test.csv", csv).unwrap(),
+            vec![(400.0, 0.1), (410.0, 0.2), (420.0, 0.25)]
+        );
+
+        let spd = "# pbrt style\n300 1.5 310 1.25\n320 1\n";
+        assert_eq!(
+            parse("test.spd", spd).unwrap(),
+            vec![(300.0, 1.5), (310.0, 1.25), (320.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse("test.csv", "400,0.1\n410,x\n").err().unwrap();
+        assert_eq!((err.line, err.column), (2, 5));
+
+        let err = parse("test.csv", "400,0.1\n390,0.2\n").err().unwrap();
+        assert_eq!((err.line, err.column), (2, 1));
+
+        let err = parse("test.spd", "400 0.1 410 -0.5\n").err().unwrap();
+        assert_eq!((err.line, err.column), (1, 13));
+        assert_eq!(err.message, "value -0.5 at 410 nm is negative");
+
+        let err = parse("test.csv", "400 0.1 410\n").err().unwrap();
+        assert_eq!(err.message, "wavelength without a value");
+
+        let err = parse("test.csv", "# empty\nheader\n").err().unwrap();
+        assert_eq!(err.to_string(), "test.csv: file contains no samples");
+    }
+}
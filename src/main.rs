@@ -11,36 +11,46 @@ use std::{
     io::Write
 };
 
+mod aov;
 mod bsdf;
+mod bvh;
 mod camera;
+mod checkpoint;
 mod color;
+mod display;
+mod film;
+mod filter;
 mod integrator;
+mod loader;
 mod math;
+mod merge;
+mod output;
 mod sampling;
 mod scene;
 mod shape;
 mod spectrum;
 mod tile;
 mod types;
+mod variance;
 
 use std::env;
+use aov::Aov;
 use camera::Camera;
+use color::ColorSpace;
+use display::{DisplayPipeline, ToneMapper};
+use filter::{Filter, FilterKind};
 use scene::Scene;
-use tile::TileData;
-use sampling::Sampler;
-use spectrum::Wavelength;
-use math::Ray;
-use integrator::Integrator;
+use tile::{PixelSum, TileData, DEFAULT_SEED};
+use variance::{AdaptiveSampling, PixelMoments};
+use spectrum::WavelengthSampling;
+use integrator::{AnyIntegrator, SpectralMode};
+use output::{ExrOptions, ImageFormat};
 
 const WIDTH: usize = 1024;
 const HEIGHT: usize = 1024;
 const TOTAL_SPP: usize = 4;
-
-//#[cfg(feature = "hwss")]
-type CurrentIntegrator = integrator::path_integrator::PathIntegrator;
-//#[cfg(not(feature = "hwss"))]
-//type CurrentIntegrator = integrator::swss_naive::SwssNaive;
-//type CurrentIntegrator = integrator::hwss_naive::HwssNaive;
+// Supported sizes of the wavelength bundle traced with each sample
+const WAVELENGTH_COUNTS: [usize; 5] = [1, 2, 4, 8, 16];
 
 pub struct Render {
     pub width: usize,
@@ -49,45 +59,353 @@ pub struct Render {
     pub scene: Scene,
     pub camera: Camera,
     pub buffer: RwLock<Vec<(f32, f32, f32)>>,
-    pub integrator: CurrentIntegrator,
+    // Filter weighted sums of the samples, `buffer` holds their RGB
+    pub pixel_sums: RwLock<Vec<PixelSum>>,
+    pub filter: Filter,
+    // Wavelength bands of the spectral film per pixel, 0 without one
+    pub spectral_bands: usize,
+    pub spectral_buffer: RwLock<Vec<f32>>,
+    // AOVs saved next to the image, with one value per pixel for each
+    pub aovs: Vec<Aov>,
+    pub aov_buffer: RwLock<Vec<(f32, f32, f32)>>,
+    // Statistics of the samples of each pixel, saved as images if `save_variance`
+    pub moments: RwLock<Vec<PixelMoments>>,
+    pub save_variance: bool,
+    // Without it every pixel gets `spp` samples, with it that is the base count
+    pub adaptive: Option<AdaptiveSampling>,
+    // With it the tiles take passes of `spp` samples until the time is up
+    pub time_limit: Option<Duration>,
+    // Time between checkpoints of renders to a file, and whether to continue from one
+    pub checkpoint_interval: Option<Duration>,
+    pub resume: bool,
+    // Of the sample scrambling, and whether to save the sums to merge with other renders
+    pub seed: u32,
+    pub save_sums: bool,
+    pub integrator: AnyIntegrator,
+    pub wavelengths: usize,
+    pub wavelength_sampling: WavelengthSampling,
+    // Color space of the output image
+    pub color_space: ColorSpace,
+    // Exposure, tone mapping and dither of the PNG output and the window
+    pub display: DisplayPipeline,
+    pub exr_options: ExrOptions,
+    pub bvh_build_time: Duration,
 }
 
 fn main() {
 
-    let mut total_spp = TOTAL_SPP;
+    let mut total_spp = None;
     let mut output_file_name = String::from("");
+    let mut scene_file_name = None;
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        total_spp = (&args[1]).parse::<usize>().unwrap();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print_usage();
+        return;
+    }
+    if args.first().map(String::as_str) == Some("rgb2spec") {
+        generate_upsample_table(&args[1..]);
+        return;
+    }
+    if args.first().map(String::as_str) == Some("merge") {
+        merge_renders(args.split_off(1));
+        return;
+    }
+    if let Some(name) = take_option(&mut args, "--scene", "a file name") {
+        scene_file_name = Some(name);
+    }
+    let integrator_name = take_option(&mut args, "--integrator", "a name")
+        .unwrap_or_else(|| String::from(integrator::INTEGRATORS[0].0));
+    let spectral_option = take_option(&mut args, "--spectral", "a list of modes");
+    let spectral_modes = match &spectral_option {
+        Some(names) => names
+            .split(',')
+            .map(|name| match SpectralMode::from_name(name) {
+                Some(mode) => mode,
+                None => {
+                    eprintln!("unknown spectral mode `{}`, see --help for the available ones", name);
+                    std::process::exit(1);
+                }
+            })
+            .collect::<Vec<_>>(),
+        None => vec![SpectralMode::default()],
+    };
+    let wavelengths = match take_option(&mut args, "--wavelengths", "a number") {
+        Some(count) => match count.parse::<usize>() {
+            Ok(count) if WAVELENGTH_COUNTS.contains(&count) => count,
+            _ => {
+                eprintln!("--wavelengths must be one of {:?}", WAVELENGTH_COUNTS);
+                std::process::exit(1);
+            }
+        },
+        None => 4,
+    };
+    let wavelength_sampling = match take_option(&mut args, "--wavelength-sampling", "a name") {
+        Some(name) => match WavelengthSampling::from_name(&name) {
+            Some(sampling) => sampling,
+            None => {
+                eprintln!("unknown wavelength sampling `{}`, see --help for the available ones", name);
+                std::process::exit(1);
+            }
+        },
+        None => WavelengthSampling::default(),
+    };
+    let color_space = match take_option(&mut args, "--color-space", "a name") {
+        Some(name) => match ColorSpace::from_name(&name) {
+            Some(color_space) => color_space,
+            None => {
+                eprintln!("unknown color space `{}`, see --help for the available ones", name);
+                std::process::exit(1);
+            }
+        },
+        None => ColorSpace::default(),
+    };
+    let spectral_bands = match take_option(&mut args, "--spectral-film", "a number of bands") {
+        Some(bands) => match bands.parse::<usize>() {
+            Ok(bands) if bands > 0 => bands,
+            _ => {
+                eprintln!("--spectral-film requires a positive number of bands");
+                std::process::exit(1);
+            }
+        },
+        None => 0,
+    };
+    let aovs = match take_option(&mut args, "--aov", "a list of AOVs") {
+        Some(names) => names
+            .split(',')
+            .map(|name| match Aov::from_name(name) {
+                Some(aov) => aov,
+                None => {
+                    eprintln!("unknown AOV `{}`, see --help for the available ones", name);
+                    std::process::exit(1);
+                }
+            })
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    let mut filter = match take_option(&mut args, "--filter", "a name") {
+        Some(name) => match FilterKind::from_name(&name) {
+            Some(kind) => Filter::new(kind),
+            None => {
+                eprintln!("unknown filter `{}`, see --help for the available ones", name);
+                std::process::exit(1);
+            }
+        },
+        None => Filter::default(),
+    };
+    if let Some(radius) = take_option(&mut args, "--filter-radius", "a number of pixels") {
+        filter.radius = match radius.parse::<f32>() {
+            Ok(radius) if radius > 0.0 && radius.is_finite() => radius,
+            _ => {
+                eprintln!("--filter-radius requires a positive number of pixels");
+                std::process::exit(1);
+            }
+        };
+    }
+    let display = take_display_options(&mut args);
+    let save_variance = match args.iter().position(|arg| arg == "--variance") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let adaptive_threshold = take_option(&mut args, "--adaptive", "a relative error").map(|threshold| {
+        match threshold.parse::<f64>() {
+            Ok(threshold) if threshold > 0.0 && threshold.is_finite() => threshold,
+            _ => {
+                eprintln!("--adaptive requires a positive relative error");
+                std::process::exit(1);
+            }
+        }
+    });
+    let max_spp = take_option(&mut args, "--max-spp", "a number").map(|count| match count.parse::<usize>() {
+        Ok(count) if count > 0 => count,
+        _ => {
+            eprintln!("--max-spp requires a positive number");
+            std::process::exit(1);
+        }
+    });
+    let time_limit = take_option(&mut args, "--time-limit", "a number of seconds").map(|seconds| {
+        match seconds.parse::<f32>() {
+            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Duration::from_secs_f32(seconds),
+            _ => {
+                eprintln!("--time-limit requires a positive number of seconds");
+                std::process::exit(1);
+            }
+        }
+    });
+    let checkpoint_interval = take_option(&mut args, "--checkpoint", "a number of seconds").map(|seconds| {
+        match seconds.parse::<f32>() {
+            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Duration::from_secs_f32(seconds),
+            _ => {
+                eprintln!("--checkpoint requires a positive number of seconds");
+                std::process::exit(1);
+            }
+        }
+    });
+    let resume = match args.iter().position(|arg| arg == "--resume") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let seed = match take_option(&mut args, "--seed", "a number") {
+        Some(seed) => match seed.parse::<u32>() {
+            Ok(seed) => seed,
+            Err(_) => {
+                eprintln!("--seed requires a number from 0 to {}", u32::MAX);
+                std::process::exit(1);
+            }
+        },
+        None => DEFAULT_SEED,
+    };
+    let save_sums = match args.iter().position(|arg| arg == "--save-sums") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    if max_spp.is_some() && adaptive_threshold.is_none() {
+        eprintln!("--max-spp only applies with --adaptive");
+        std::process::exit(1);
+    }
+    let mut exr_options = ExrOptions::default();
+    if let Some(pixel_type) = take_option(&mut args, "--exr-pixel", "`half` or `float`") {
+        exr_options.half = match pixel_type.as_str() {
+            "half" => true,
+            "float" => false,
+            _ => {
+                eprintln!("--exr-pixel must be `half` or `float`");
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(compression) = take_option(&mut args, "--exr-compression", "`zip` or `none`") {
+        exr_options.zip = match compression.as_str() {
+            "zip" => true,
+            "none" => false,
+            _ => {
+                eprintln!("--exr-compression must be `zip` or `none`");
+                std::process::exit(1);
+            }
+        };
+    }
+    let integrator = match AnyIntegrator::from_name(&integrator_name, spectral_modes[0]) {
+        Some(integrator) => integrator,
+        None => {
+            eprintln!("unknown integrator `{}`, see --help for the available ones", integrator_name);
+            std::process::exit(1);
+        }
+    };
+    if spectral_option.is_some() && !integrator.has_spectral_modes() {
+        eprintln!(
+            "the {} integrator has a fixed spectral mode, --spectral only applies to path and hwss-modified",
+            integrator_name
+        );
+        std::process::exit(1);
+    }
+    // every option was taken above, what is left are the positional arguments
+    if let Some(option) = args.iter().find(|arg| arg.starts_with("--")) {
+        eprintln!("unknown option `{}`, see --help for the available ones", option);
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
     }
-    if args.len() > 2 {
+    if !args.is_empty() {
+        total_spp = match args[0].parse::<usize>() {
+            Ok(spp) if spp > 0 => Some(spp),
+            _ => {
+                eprintln!("expected a positive number of samples per pixel, found `{}`", args[0]);
+                eprintln!("{}", USAGE);
+                std::process::exit(1);
+            }
+        };
+    }
+    // the extension of the output name picks the image format, PNG if it has none
+    let mut image_format = ImageFormat::Png;
+    if args.len() > 1 {
         output_file_name.push_str("results/");
-        output_file_name.push_str(&args[2]);
-        output_file_name.push_str(".png");
-    }
-
-    let render = Arc::new(Render {
-        width: WIDTH,
-        height: HEIGHT,
-        spp: total_spp,
-        integrator: CurrentIntegrator::default(),
-        scene: scene::Scene::cornell_box(),
-        buffer: RwLock::new(vec![(0.0, 0.0, 0.0); WIDTH * HEIGHT]),
-        camera: Camera::new(
-            math::Point3::new(0.0, 0.0, 0.0),
-            (WIDTH as f32) / (HEIGHT as f32),
+        match ImageFormat::from_path(&args[1]) {
+            Some(format) => {
+                image_format = format;
+                output_file_name.push_str(&args[1][..args[1].len() - format.extension().len() - 1]);
+            }
+            None => output_file_name.push_str(&args[1]),
+        }
+    }
+
+    let (mut scene, camera, width, height, spp) = match scene_file_name {
+        Some(scene_file_name) => {
+            let description = match loader::scene_file::load(&scene_file_name) {
+                Ok(description) => description,
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+            };
+
+            (
+                description.scene,
+                description.camera,
+                description.width,
+                description.height,
+                total_spp.unwrap_or(description.spp),
+            )
+        }
+        None => (
+            scene::Scene::cornell_box(),
+            Camera::new(
+                math::Point3::new(0.0, 0.0, 0.0),
+                (WIDTH as f32) / (HEIGHT as f32),
+            ),
+            WIDTH,
+            HEIGHT,
+            total_spp.unwrap_or(TOTAL_SPP),
         ),
+    };
+
+    let adaptive = adaptive_threshold.map(|threshold| AdaptiveSampling {
+        threshold,
+        max_spp: max_spp
+            .unwrap_or(if time_limit.is_some() { usize::MAX } else { 8 * spp })
+            .max(spp),
+    });
+
+    let bvh_start = Instant::now();
+    scene.build_bvh();
+    let bvh_build_time = bvh_start.elapsed();
+
+    let mut render = Arc::new(Render {
+        width,
+        height,
+        spp,
+        integrator,
+        wavelengths,
+        wavelength_sampling,
+        color_space,
+        display,
+        exr_options,
+        scene,
+        buffer: RwLock::new(vec![(0.0, 0.0, 0.0); width * height]),
+        pixel_sums: RwLock::new(vec![PixelSum::ZERO; width * height]),
+        filter,
+        spectral_bands,
+        spectral_buffer: RwLock::new(vec![0.0; width * height * spectral_bands]),
+        aov_buffer: RwLock::new(vec![(0.0, 0.0, 0.0); width * height * aovs.len()]),
+        aovs,
+        moments: RwLock::new(vec![PixelMoments::ZERO; width * height]),
+        save_variance,
+        adaptive,
+        time_limit,
+        checkpoint_interval,
+        resume,
+        seed,
+        save_sums,
+        camera,
+        bvh_build_time,
     });
-    
-    let tile_priorities = Arc::new(Mutex::new(
-        // TODO: Make this nice
-        (0..)
-            .map(|idx| TileData::new(&render, idx))
-            .take_while(|t| t.is_some())
-            .map(|t| t.unwrap())
-            .collect::<BinaryHeap<TileData>>(),
-    ));
     
     let num_threads = std::env::var("NTHREADS")
         .ok()
@@ -95,54 +413,479 @@ fn main() {
         .unwrap_or_else(num_cpus::get);
     
     if output_file_name.len() > 0 {
-        do_render_png(render, tile_priorities, num_threads, output_file_name);
+        // the modes are rendered back to back with the same scene, each to its own file
+        // if there is more than one
+        let mut summaries = Vec::new();
+        for (i, &mode) in spectral_modes.iter().enumerate() {
+            if i > 0 {
+                let render = Arc::get_mut(&mut render).expect("render threads still running");
+                render.integrator = AnyIntegrator::from_name(&integrator_name, mode).unwrap();
+                render.buffer = RwLock::new(vec![(0.0, 0.0, 0.0); width * height]);
+                render.pixel_sums = RwLock::new(vec![PixelSum::ZERO; width * height]);
+                render.spectral_buffer = RwLock::new(vec![0.0; width * height * spectral_bands]);
+                render.aov_buffer = RwLock::new(vec![(0.0, 0.0, 0.0); width * height * render.aovs.len()]);
+                render.moments = RwLock::new(vec![PixelMoments::ZERO; width * height]);
+            }
+
+            let file_name = if spectral_modes.len() > 1 {
+                format!("{}-{}", output_file_name, mode.name())
+            } else {
+                output_file_name.clone()
+            };
+            let summary = do_render_to_file(&render, tile_priorities(&render), num_threads, mode, file_name, image_format);
+            summaries.push((mode, summary));
+        }
+
+        // the product of variance and time compares the modes at equal time
+        if summaries.len() > 1 {
+            println!();
+            println!(
+                "{:<12} {:>10} {:>10} {:>16} {:>16}",
+                "mode", "time (s)", "spp", "rel. variance", "rel. var. * time"
+            );
+            for (mode, (elapsed, mean_spp, relative_variance)) in summaries {
+                println!(
+                    "{:<12} {:>10.3} {:>10.2} {:>16.6e} {:>16.6e}",
+                    mode.name(),
+                    elapsed,
+                    mean_spp,
+                    relative_variance,
+                    relative_variance * elapsed as f64,
+                );
+            }
+        }
     }
     else {
-        do_render_progressive(render, tile_priorities, num_threads);
+        if spectral_bands > 0 {
+            eprintln!("the spectral film needs an output name to save it");
+            std::process::exit(1);
+        }
+        if !render.aovs.is_empty() {
+            eprintln!("the AOVs need an output name to save them");
+            std::process::exit(1);
+        }
+        if render.save_sums {
+            eprintln!("the sums need an output name to save them");
+            std::process::exit(1);
+        }
+        if render.checkpoint_interval.is_some() || render.resume {
+            eprintln!("checkpoints need an output name to save them");
+            std::process::exit(1);
+        }
+        if spectral_modes.len() > 1 {
+            eprintln!("only one spectral mode can be shown in progressive mode");
+            std::process::exit(1);
+        }
+        let tile_priorities = tile_priorities(&render);
+        do_render_progressive(render, tile_priorities, num_threads, spectral_modes[0]);
     }
 }
 
-fn do_render_png(
-    render: Arc<Render>,
+fn tile_priorities(render: &Render) -> Arc<Mutex<BinaryHeap<TileData>>> {
+    Arc::new(Mutex::new(
+        // TODO: Make this nice
+        (0..)
+            .map(|idx| TileData::new(render, idx))
+            .take_while(|t| t.is_some())
+            .map(|t| t.unwrap())
+            .collect::<BinaryHeap<TileData>>(),
+    ))
+}
+
+const USAGE: &str = "Usage: iris [options] [samples per pixel] [output name]";
+
+fn print_usage() {
+    println!("{}", USAGE);
+    println!("       iris rgb2spec <color space> <resolution> <output file>");
+    println!("       iris merge [display options] <output name> <sums files...>");
+    println!();
+    println!("Renders the built-in Cornell box, or the scene given with --scene. With an output");
+    println!("name the image is saved to results/<output name>, otherwise it is shown in a");
+    println!("window as it renders. The extension of the name picks the format: .png (the");
+    println!("default), or .pfm and .exr for the unclamped linear values.");
+    println!();
+    println!("Options:");
+    println!("  --scene <file>         render a scene description file, see scenes/");
+    println!("  --integrator <name>    light transport algorithm, one of:");
+    for (i, (name, description)) in integrator::INTEGRATORS.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --spectral <modes>     comma separated spectral sampling modes, rendered one after");
+    println!("                         another (path and hwss-modified integrators only), of:");
+    for (i, (name, description)) in integrator::SPECTRAL_MODES.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --wavelengths <n>      wavelengths per sample, the hero and its companions, one of");
+    println!("                         1, 2, 4 (default), 8 or 16");
+    println!("  --wavelength-sampling <name>");
+    println!("                         distribution of the hero wavelength, one of:");
+    for (i, (name, description)) in spectrum::wavelength::WAVELENGTH_SAMPLINGS.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --spectral-film <n>    also save the radiance in n wavelength bands as an ENVI");
+    println!("                         image, results/<output name>-spectral.img and .hdr");
+    println!("  --exr-pixel <type>     half (default) or float pixels for OpenEXR output");
+    println!("  --exr-compression <c>  zip (default) or none for OpenEXR output");
+    println!("  --color-space <name>   color space of the output image, recorded in the PNG");
+    println!("                         chromaticities, one of:");
+    for (i, (name, description)) in color::COLOR_SPACES.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --aov <names>          comma separated AOVs to save next to the image as");
+    println!("                         <output name>-<aov>.pfm, or .exr for OpenEXR output, of:");
+    for (name, description) in aov::AOVS.iter() {
+        println!("      {:<15}{}", name, description);
+    }
+    println!("  --variance             also save the variance and standard error of the mean of");
+    println!("                         each pixel as <output name>-variance and -stderr images");
+    println!("  --adaptive <error>     take the samples per pixel, then more in the pixels whose");
+    println!("                         relative standard error is above the given one, and save");
+    println!("                         the sample counts as <output name>-spp");
+    println!("  --max-spp <n>          samples per pixel at most with --adaptive (default 8 times");
+    println!("                         the samples per pixel)");
+    println!("  --time-limit <s>       render for the given number of seconds, in passes of the");
+    println!("                         samples per pixel, with --adaptive until the pixels");
+    println!("                         converge at most; several spectral modes get the same time");
+    println!("  --checkpoint <s>       save the state of the render every given number of seconds");
+    println!("                         as <output name>.checkpoint, removed once it is done");
+    println!("  --resume               continue from the checkpoint of an interrupted render with");
+    println!("                         the same options, to the same image it would have made");
+    println!("  --seed <n>             scramble the samples with another seed, renders with");
+    println!("                         different seeds take independent samples");
+    println!("  --save-sums            also save the filtered sums and sample moments of the");
+    println!("                         pixels as <output name>.sums, for iris merge");
+    println!("  --filter <name>        pixel reconstruction filter, one of:");
+    for (i, (name, description)) in filter::FILTERS.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --filter-radius <r>    override the radius of the filter, in pixels");
+    println!("  --exposure <stops>     scale the PNG and window output by 2^stops (default 0)");
+    println!("  --tone-map <name>      tone mapper of the PNG and window output, one of:");
+    for (i, (name, description)) in display::TONE_MAPPERS.iter().enumerate() {
+        let default = if i == 0 { " (default)" } else { "" };
+        println!("      {:<15}{}{}", name, description, default);
+    }
+    println!("  --dither               add one step of noise before quantizing to 8 bits");
+    println!("  -h, --help             print this message");
+    println!();
+    println!("The rgb2spec command optimizes the RGB to spectrum upsampling table of a color");
+    println!("space, like data/srgb.coeff. The tables are read from data/ in the working directory,");
+    println!("or from $IRIS_DATA if set, and are generated there on first use if missing.");
+    println!();
+    println!("The merge command adds the sums saved with --save-sums by renders of the same scene");
+    println!("and settings with different seeds, and saves the image to results/<output name>.");
+    println!("It takes the display options --exposure, --tone-map and --dither.");
+}
+
+// `iris merge [display options] <output name> <sums files...>`
+fn merge_renders(mut args: Vec<String>) {
+    let display = take_display_options(&mut args);
+    if let Some(option) = args.iter().find(|arg| arg.starts_with("--")) {
+        eprintln!("unknown option `{}`, iris merge takes --exposure, --tone-map and --dither", option);
+        std::process::exit(1);
+    }
+    if args.len() < 2 {
+        eprintln!("usage: iris merge [display options] <output name> <sums files...>");
+        std::process::exit(1);
+    }
+    let read = |path: &String| match merge::PartialRender::read(path) {
+        Ok(partial) => partial,
+        Err(err) => {
+            eprintln!("error: failed to read {}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
+    let mut merged = read(&args[1]);
+    for path in &args[2..] {
+        if let Err(err) = merged.add(&read(path)) {
+            eprintln!("error: cannot merge {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+
+    // the extension of the output name picks the image format, PNG if it has none
+    let (image_format, path) = match ImageFormat::from_path(&args[0]) {
+        Some(format) => (format, format!("results/{}", args[0])),
+        None => (ImageFormat::Png, format!("results/{}.png", args[0])),
+    };
+    let pixels = merged.to_rgb();
+    let image = output::Image {
+        width: merged.width,
+        height: merged.height,
+        pixels: &pixels,
+        color_space: merged.color_space,
+        display,
+    };
+    if let Err(err) = output::write(&path, image_format, &image, ExrOptions::default()) {
+        eprintln!("error: failed to write {}: {}", path, err);
+        std::process::exit(1);
+    }
+
+    let samples = merged.moments.iter().map(|moments| moments.count as usize).sum::<usize>();
+    println!(
+        "Merged {} renders, {:.2}spp on average, into {}",
+        merged.seeds.len(),
+        samples as f32 / (merged.width * merged.height) as f32,
+        path,
+    );
+    println!("Mean relative variance {:.6e}", variance::mean_relative_variance(&merged.moments));
+}
+
+// `iris rgb2spec <color space> <resolution> <output file>`
+fn generate_upsample_table(args: &[String]) {
+    if args.len() != 3 {
+        eprintln!("usage: iris rgb2spec <color space> <resolution> <output file>");
+        std::process::exit(1);
+    }
+    let color_space = match ColorSpace::from_name(&args[0]) {
+        Some(color_space) => color_space,
+        None => {
+            eprintln!("unknown color space `{}`, see --help for the available ones", args[0]);
+            std::process::exit(1);
+        }
+    };
+    let resolution = match args[1].parse::<usize>() {
+        Ok(resolution) if resolution >= 3 => resolution,
+        _ => {
+            eprintln!("the resolution must be a number of at least 3");
+            std::process::exit(1);
+        }
+    };
+
+    let start = Instant::now();
+    let table = spectrum::rgb2spec::optimize(color_space, resolution, num_cpus::get());
+    let result = std::fs::File::create(&args[2])
+        .and_then(|file| table.write(std::io::BufWriter::new(file)));
+    if let Err(err) = result {
+        eprintln!("error: failed to write {}: {}", args[2], err);
+        std::process::exit(1);
+    }
+    println!("Optimized a {}^3 table in {:.2}s", resolution, start.elapsed().as_secs_f32());
+}
+
+// Removes `name` and the value following it from the arguments
+fn take_option(args: &mut Vec<String>, name: &str, what: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == name)?;
+    if i + 1 >= args.len() {
+        eprintln!("{} requires {}", name, what);
+        std::process::exit(1);
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Some(value)
+}
+
+// `--exposure`, `--tone-map` and `--dither`, shared by renders and `iris merge`
+fn take_display_options(args: &mut Vec<String>) -> DisplayPipeline {
+    let mut display = DisplayPipeline::default();
+    if let Some(exposure) = take_option(args, "--exposure", "a number of stops") {
+        display.exposure = match exposure.parse::<f32>() {
+            Ok(exposure) if exposure.is_finite() => exposure,
+            _ => {
+                eprintln!("--exposure requires a number of stops");
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(name) = take_option(args, "--tone-map", "a name") {
+        display.tone_mapper = match ToneMapper::from_name(&name) {
+            Some(tone_mapper) => tone_mapper,
+            None => {
+                eprintln!("unknown tone mapper `{}`, see --help for the available ones", name);
+                std::process::exit(1);
+            }
+        };
+    }
+    if let Some(index) = args.iter().position(|arg| arg == "--dither") {
+        args.remove(index);
+        display.dither = true;
+    }
+    display
+}
+
+fn do_render_to_file(
+    render: &Arc<Render>,
     tile_priorities: Arc<Mutex<BinaryHeap<TileData>>>,
     num_threads: usize,
-    output_file_name: String
-) {
+    spectral_mode: SpectralMode,
+    output_file_name: String,
+    image_format: ImageFormat,
+) -> (f32, f32, f64) {
+    // returns the render time, the mean samples per pixel and the mean relative variance
+    // `output_file_name` has no extension, the image is saved in the given format and the
+    // spectral film, if any, next to it
+    if render.spectral_bands > 0 {
+        println!("Keeping a spectral film with {} bands", render.spectral_bands);
+    }
+
+    if let Some(adaptive) = render.adaptive {
+        println!(
+            "Sampling adaptively up to {}spp, to a relative error of {}",
+            adaptive.max_spp, adaptive.threshold
+        );
+    }
+
+    if let Some(time_limit) = render.time_limit {
+        println!("Rendering for {}s in passes of {}spp", time_limit.as_secs_f32(), render.spp);
+    }
+
     println!(
-        "Starting render, {}x{}@{}spp to save as PNG file",
-        render.width, render.height, render.spp
+        "Starting render, {}x{}@{}spp with the {} integrator ({}, {} {} wavelengths) to save as {} file",
+        render.width,
+        render.height,
+        render.spp,
+        render.integrator.name(),
+        spectral_mode.name(),
+        render.wavelengths,
+        render.wavelength_sampling.name(),
+        image_format.extension().to_uppercase(),
     );
 
+    // the tiles are kept once done, the image is assembled from them at the end
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let checkpoint_path = format!("{}.checkpoint", output_file_name);
+    let (settings, scene_hash) = if render.checkpoint_interval.is_some() || render.resume {
+        (checkpoint::settings(render, spectral_mode), checkpoint::scene_hash(&render.scene, &render.camera))
+    } else {
+        (String::new(), 0)
+    };
+    let mut resumed_samples = 0;
+    if render.resume {
+        match checkpoint::read(&checkpoint_path, &settings, scene_hash, render) {
+            Ok(tiles) => {
+                let mut heap = tile_priorities.lock().unwrap();
+                heap.clear();
+                for tile in tiles {
+                    resumed_samples += tile.moments.iter().map(|moments| moments.count as usize).sum::<usize>();
+                    if tile.is_done(render) {
+                        finished.lock().unwrap().push(tile);
+                    } else {
+                        heap.push(tile);
+                    }
+                }
+                println!("Resuming from {} with {} samples taken", checkpoint_path, resumed_samples);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                println!("No checkpoint {}, starting from the beginning", checkpoint_path);
+            }
+            Err(err) => {
+                eprintln!("error: failed to resume from {}: {}", checkpoint_path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
     let start = Instant::now();
 
+    // tiles being rendered, and whether to stop taking new ones to save a checkpoint
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let paused = Arc::new(AtomicBool::new(false));
+
     let threads = (0..num_threads)
         .map(|_| {
             let tile_priorities = tile_priorities.clone();
+            let finished = finished.clone();
+            let in_flight = in_flight.clone();
+            let paused = paused.clone();
             let render = render.clone();
             std::thread::spawn(move || loop {
-                let popped = tile_priorities.lock().unwrap().pop();
+                if paused.load(Ordering::SeqCst) {
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                let popped = {
+                    let mut heap = tile_priorities.lock().unwrap();
+                    let popped = heap.pop();
+                    if popped.is_some() {
+                        in_flight.fetch_add(1, Ordering::SeqCst);
+                    } else if in_flight.load(Ordering::SeqCst) == 0 {
+                        break;
+                    }
+                    popped
+                };
                 match popped {
+                    Some(tile) if !tile.wants_pass(out_of_time(&render, start)) => {
+                        finished.lock().unwrap().push(tile);
+                    }
                     Some(tile) => {
-                        tile.render(&render);
+                        let tile = tile.render(&render);
+                        if tile.is_done(&render) {
+                            finished.lock().unwrap().push(tile);
+                        } else {
+                            tile_priorities.lock().unwrap().push(tile);
+                        }
                     }
+                    // the tiles being rendered may come back
                     None => {
-                        break;
+                        std::thread::sleep(Duration::from_millis(1));
+                        continue;
                     }
                 }
+                in_flight.fetch_sub(1, Ordering::SeqCst);
             })
         })
         .collect::<Vec<_>>();
 
+    if let Some(interval) = render.checkpoint_interval {
+        let mut last_checkpoint = Instant::now();
+        while threads.iter().any(|thread| !thread.is_finished()) {
+            std::thread::sleep(Duration::from_millis(20));
+            if last_checkpoint.elapsed() < interval {
+                continue;
+            }
+
+            // every tile is in the heap or finished while none is being rendered
+            paused.store(true, Ordering::SeqCst);
+            loop {
+                let heap = tile_priorities.lock().unwrap();
+                if in_flight.load(Ordering::SeqCst) == 0 {
+                    let finished = finished.lock().unwrap();
+                    let mut tiles = heap.iter().chain(finished.iter()).collect::<Vec<_>>();
+                    tiles.sort_by_key(|tile| tile.idx);
+                    if let Err(err) = checkpoint::write(&checkpoint_path, &settings, scene_hash, &tiles) {
+                        eprintln!("error: failed to write the checkpoint {}: {}", checkpoint_path, err);
+                    }
+                    break;
+                }
+                drop(heap);
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            paused.store(false, Ordering::SeqCst);
+            last_checkpoint = Instant::now();
+        }
+    }
+
     for thread in threads {
         thread.join().unwrap();
     }
+    TileData::resolve(&mut finished.lock().unwrap(), render);
 
     let elapsed = start.elapsed().as_secs_f32();
+    let moments = render.moments.read().unwrap();
+    let samples = moments.iter().map(|moments| moments.count as usize).sum::<usize>();
+    let mean_spp = samples as f32 / (render.width * render.height) as f32;
     println!(
-        "Done in {}s ({}m ray/s)",
+        "Done in {}s ({}m ray/s, BVH built in {}s)",
         elapsed,
-        ((render.spp * WIDTH * HEIGHT) as f32) / (1_000_000.0 * elapsed),
+        ((samples - resumed_samples) as f32) / (1_000_000.0 * elapsed),
+        render.bvh_build_time.as_secs_f32(),
     );
+    if render.adaptive.is_some() || render.time_limit.is_some() {
+        println!(
+            "Samples per pixel: {:.2} on average, {} to {}",
+            mean_spp,
+            moments.iter().map(|moments| moments.count).min().unwrap_or(0),
+            moments.iter().map(|moments| moments.count).max().unwrap_or(0),
+        );
+    }
+    drop(moments);
+    let relative_variance = variance::mean_relative_variance(&render.moments.read().unwrap());
+    println!("Mean relative variance {:.6e}", relative_variance);
 
     // read finished render buffer
     let lock_result: 
@@ -151,66 +894,143 @@ fn do_render_png(
     let buffer_guarded_vec = lock_result.ok().unwrap();
     let buffer_float = &*buffer_guarded_vec;
 
-    // choose file to save image to
-    use std::fs::OpenOptions;
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(output_file_name)
-        .unwrap();
-    let ref mut w = std::io::BufWriter::new(file);
-
-    // configure the encoder
-    let mut encoder = png::Encoder::new(w, WIDTH as u32, HEIGHT as u32);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    encoder.set_trns(vec!(0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8));
-    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
-    encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
-    let source_chromaticities = png::SourceChromaticities::new(
-        (0.31270, 0.32900),
-        (0.64000, 0.33000),
-        (0.30000, 0.60000),
-        (0.15000, 0.06000)
-    );
-    encoder.set_source_chromaticities(source_chromaticities);
-    let mut writer = encoder.write_header().unwrap();
-
-    // convert the f32 (R,G,B) values to u8 format in order [R,G,B,A]
-    let mut buffer: Vec<u8> = vec![0; WIDTH * HEIGHT * 4];
-    for y in 0..HEIGHT {
-        for x in 0..WIDTH {
-            let index = y * WIDTH + x;
-            let pixel = buffer_float[index];
-
-            // convert from [0,1] to [0,255]
-            let r: f32 = pixel.0.max(0.0).min(1.0) * 255.0;
-            let g: f32 = pixel.1.max(0.0).min(1.0) * 255.0;
-            let b: f32 = pixel.2.max(0.0).min(1.0) * 255.0;
-
-            // convert to u8
-            buffer[4*index + 0] = r as u8;
-            buffer[4*index + 1] = g as u8;
-            buffer[4*index + 2] = b as u8;
-            buffer[4*index + 3] = 255;
-        } 
-    }
-
-    // write to file
-    writer.write_image_data(&buffer);
+    let path = format!("{}.{}", output_file_name, image_format.extension());
+    let image = output::Image {
+        width: render.width,
+        height: render.height,
+        pixels: buffer_float,
+        color_space: render.color_space,
+        display: render.display,
+    };
+    if let Err(err) = output::write(&path, image_format, &image, render.exr_options) {
+        eprintln!("error: failed to write {}: {}", path, err);
+    }
+
+    if render.spectral_bands > 0 {
+        let base = format!("{}-spectral", output_file_name);
+        let spectral_buffer = render.spectral_buffer.read().unwrap();
+        if let Err(err) = film::write_envi(
+            &base,
+            render.width,
+            render.height,
+            render.spectral_bands,
+            &spectral_buffer,
+        ) {
+            eprintln!("error: failed to write the spectral film {}.img: {}", base, err);
+        }
+    }
+
+    // PNG cannot hold the values of the AOVs and variances, they are saved as PFM then
+    let aov_format = match image_format {
+        ImageFormat::Png => ImageFormat::Pfm,
+        format => format,
+    };
+    let aov_buffer = render.aov_buffer.read().unwrap();
+    for (k, &aov) in render.aovs.iter().enumerate() {
+        let pixels = aov_buffer.iter().skip(k).step_by(render.aovs.len()).copied().collect::<Vec<_>>();
+        let path = format!("{}-{}.{}", output_file_name, aov.name(), aov_format.extension());
+        let image = output::Image {
+            width: render.width,
+            height: render.height,
+            pixels: &pixels,
+            color_space: render.color_space,
+            display: render.display,
+        };
+        // indices and depths need more precision than half floats have
+        let exr_options = ExrOptions {
+            half: render.exr_options.half && aov.is_averaged(),
+            ..render.exr_options
+        };
+        if let Err(err) = output::write(&path, aov_format, &image, exr_options) {
+            eprintln!("error: failed to write {}: {}", path, err);
+        }
+    }
+    drop(aov_buffer);
+
+    if render.save_variance {
+        let moments = render.moments.read().unwrap();
+        let images: [(&str, fn(&PixelMoments) -> (f32, f32, f32)); 2] = [
+            ("variance", PixelMoments::variance_rgb),
+            ("stderr", PixelMoments::standard_error_rgb),
+        ];
+        for (name, value) in images.iter() {
+            let pixels = moments.iter().map(value).collect::<Vec<_>>();
+            let path = format!("{}-{}.{}", output_file_name, name, aov_format.extension());
+            let image = output::Image {
+                width: render.width,
+                height: render.height,
+                pixels: &pixels,
+                color_space: render.color_space,
+                display: render.display,
+            };
+            let exr_options = ExrOptions { half: false, ..render.exr_options };
+            if let Err(err) = output::write(&path, aov_format, &image, exr_options) {
+                eprintln!("error: failed to write {}: {}", path, err);
+            }
+        }
+    }
+
+    if render.adaptive.is_some() {
+        let pixels = render
+            .moments
+            .read()
+            .unwrap()
+            .iter()
+            .map(|moments| {
+                let count = moments.count as f32;
+                (count, count, count)
+            })
+            .collect::<Vec<_>>();
+        let path = format!("{}-spp.{}", output_file_name, aov_format.extension());
+        let image = output::Image {
+            width: render.width,
+            height: render.height,
+            pixels: &pixels,
+            color_space: render.color_space,
+            display: render.display,
+        };
+        let exr_options = ExrOptions { half: false, ..render.exr_options };
+        if let Err(err) = output::write(&path, aov_format, &image, exr_options) {
+            eprintln!("error: failed to write {}: {}", path, err);
+        }
+    }
+
+    if render.save_sums {
+        let path = format!("{}.sums", output_file_name);
+        if let Err(err) = merge::PartialRender::from_render(render, spectral_mode).write(&path) {
+            eprintln!("error: failed to write {}: {}", path, err);
+        }
+    }
+
+    if render.checkpoint_interval.is_some() || render.resume {
+        match std::fs::remove_file(&checkpoint_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                eprintln!("error: failed to remove the checkpoint {}: {}", checkpoint_path, err);
+            }
+            _ => {}
+        }
+    }
+
+    (elapsed, mean_spp, relative_variance)
+}
+
+// Whether the time limit of the render, if any, is up
+fn out_of_time(render: &Render, start: Instant) -> bool {
+    render.time_limit.is_some_and(|limit| start.elapsed() >= limit)
 }
 
 fn do_render_progressive(
     render: Arc<Render>,
     tile_priorities: Arc<Mutex<BinaryHeap<TileData>>>,
     num_threads: usize,
+    spectral_mode: SpectralMode,
 ) {
     use minifb::{Key, Window, WindowOptions};
 
     let mut window = Window::new(
         "Iris",
-        WIDTH,
-        HEIGHT,
+        render.width,
+        render.height,
         WindowOptions {
             resize: false,
             ..Default::default()
@@ -222,8 +1042,14 @@ fn do_render_progressive(
     static DONE: AtomicBool = AtomicBool::new(false);
 
     println!(
-        "Starting render, {}x{}@{}spp in progressive mode",
-        render.width, render.height, render.spp
+        "Starting render, {}x{}@{}spp with the {} integrator ({}, {} {} wavelengths) in progressive mode",
+        render.width,
+        render.height,
+        render.spp,
+        render.integrator.name(),
+        spectral_mode.name(),
+        render.wavelengths,
+        render.wavelength_sampling.name(),
     );
 
     let start = Instant::now();
@@ -234,6 +1060,9 @@ fn do_render_progressive(
         std::thread::spawn(move || loop {
             let popped = tile_priorities.lock().unwrap().pop();
             match popped {
+                Some(tile) if !tile.wants_pass(out_of_time(&render, start)) => {
+                    tile_priorities.lock().unwrap().clear();
+                }
                 Some(tile) => {
                     let samples_before = tile.remaining_samples;
                     let tile = tile.render(&render);
@@ -243,17 +1072,19 @@ fn do_render_progressive(
                         Ordering::Relaxed,
                     );
 
-                    if samples_after > 0 {
+                    if !tile.is_done(&render) {
                         tile_priorities.lock().unwrap().push(tile);
                     }
                 }
                 None => {
                     if !DONE.swap(true, Ordering::Relaxed) {
                         let elapsed = start.elapsed().as_secs_f32();
+                        let samples = render.moments.read().unwrap().iter().map(|m| m.count as usize).sum::<usize>();
                         println!(
-                            "Done in {}s ({}m ray/s)",
+                            "Done in {}s ({}m ray/s, BVH built in {}s)",
                             elapsed,
-                            ((render.spp * WIDTH * HEIGHT) as f32) / (1_000_000.0 * elapsed),
+                            (samples as f32) / (1_000_000.0 * elapsed),
+                            render.bvh_build_time.as_secs_f32(),
                         );
                     }
                     break;
@@ -269,8 +1100,10 @@ fn do_render_progressive(
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         if !DONE.load(Ordering::Relaxed) {
-            let progress =
-            SAMPLES_TAKEN.load(Ordering::Relaxed) as f32 / (render.spp * WIDTH * HEIGHT) as f32;
+            let progress = match render.time_limit {
+                Some(time_limit) => start.elapsed().as_secs_f32() / time_limit.as_secs_f32(),
+                None => SAMPLES_TAKEN.load(Ordering::Relaxed) as f32 / (render.spp * render.width * render.height) as f32,
+            };
             print!("Progress: {:>5.2}%\r", 100.0 * progress);
             std::io::stdout().flush().unwrap();
         }
@@ -301,26 +1134,12 @@ fn do_render_progressive(
 
         // convert the f32 RGB values to u32 format 0RGB
         //let mut buffer: [u32; WIDTH*HEIGHT] = [0; WIDTH*HEIGHT]; stack overflow for 512x512
-        let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                let index = y * WIDTH + x;
-                let pixel = buffer_float[index];
-
-                let mut result: u32 = 0;
-                let r: f32 = pixel.0.min(1.0) * 255.0;
-                let g: f32 = pixel.1.min(1.0) * 255.0;
-                let b: f32 = pixel.2.min(1.0) * 255.0;
-                //println!("read pixel ({}, {}, {})", r, g, b);
-
-                result += r as u32;
-                result = result << 8;
-                result += g as u32;
-                result = result << 8;
-                result += b as u32;
-
-                buffer[index] = result;
-            } 
+        let mut buffer: Vec<u32> = vec![0; render.width * render.height];
+        for y in 0..render.height {
+            for x in 0..render.width {
+                let index = y * render.width + x;
+                buffer[index] = render.display.to_u32(buffer_float[index], x, y);
+            }
         }
 
         window
@@ -0,0 +1,285 @@
+#![allow(dead_code)]
+
+use std::arch::x86_64::*;
+
+use super::{Point3, Vec3, WideRay};
+
+// Axis aligned bounding box. The default box is empty, i.e. min > max,
+// so that it can be grown with `union`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds3 {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Default for Bounds3 {
+    fn default() -> Self {
+        Self {
+            min: Point3::splat(f32::INFINITY),
+            max: Point3::splat(f32::NEG_INFINITY),
+        }
+    }
+}
+
+impl Bounds3 {
+    pub fn new(min: Point3, max: Point3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_point(p: Point3) -> Self {
+        Self { min: p, max: p }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn union_point(self, p: Point3) -> Self {
+        self.union(Self::from_point(p))
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn diagonal(self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn centroid(self) -> Point3 {
+        Point3::new(
+            0.5 * (self.min.x + self.max.x),
+            0.5 * (self.min.y + self.max.y),
+            0.5 * (self.min.z + self.max.z),
+        )
+    }
+
+    pub fn surface_area(self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let d = self.diagonal();
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    // Index of the axis with the largest extent
+    pub fn max_extent(self) -> usize {
+        let d = self.diagonal();
+        if d.x() > d.y() && d.x() > d.z() {
+            0
+        } else if d.y() > d.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    // Position of `p` along `axis` relative to the box, 0 at `min` and 1 at `max`
+    pub fn offset(self, p: Point3, axis: usize) -> f32 {
+        let (p, min, max) = match axis {
+            0 => (p.x, self.min.x, self.max.x),
+            1 => (p.y, self.min.y, self.max.y),
+            _ => (p.z, self.min.z, self.max.z),
+        };
+
+        if max > min {
+            (p - min) / (max - min)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Four boxes in structure of arrays layout, tested against a ray at once.
+#[derive(Clone, Copy)]
+pub struct Bounds4 {
+    min: [__m128; 3],
+    max: [__m128; 3],
+}
+
+impl Bounds4 {
+    // Unused lanes should be given empty boxes
+    pub fn new(bounds: [Bounds3; 4]) -> Self {
+        let lanes = |f: fn(&Bounds3) -> f32| unsafe {
+            _mm_set_ps(f(&bounds[3]), f(&bounds[2]), f(&bounds[1]), f(&bounds[0]))
+        };
+
+        Self {
+            min: [lanes(|b| b.min.x), lanes(|b| b.min.y), lanes(|b| b.min.z)],
+            max: [lanes(|b| b.max.x), lanes(|b| b.max.y), lanes(|b| b.max.z)],
+        }
+    }
+
+    // Slab test of all four boxes against the ray. Returns a bit mask of the boxes hit in [0, t_max] and the distances at which the ray
+    // enters them.
+    pub fn intersect(&self, ray: &WideRay, t_max: f32) -> (i32, [f32; 4]) {
+        unsafe {
+            let mut t0 = _mm_setzero_ps();
+            let mut t1 = _mm_set1_ps(t_max);
+            let far_scale = _mm_set1_ps(1.0 + 2.0 * gamma(3));
+
+            for axis in 0..3 {
+                let (near, far) = if ray.dir_is_neg[axis] {
+                    (self.max[axis], self.min[axis])
+                } else {
+                    (self.min[axis], self.max[axis])
+                };
+
+                let t_near = _mm_mul_ps(_mm_sub_ps(near, ray.o[axis]), ray.inv_d[axis]);
+                let t_far = _mm_mul_ps(_mm_sub_ps(far, ray.o[axis]), ray.inv_d[axis]);
+                let t_far = _mm_mul_ps(t_far, far_scale);
+
+                // max and min return the second operand if either is NaN
+                t0 = _mm_max_ps(t_near, t0);
+                t1 = _mm_min_ps(t_far, t1);
+            }
+
+            let mask = _mm_movemask_ps(_mm_cmple_ps(t0, t1));
+            let mut entry = [0.0; 4];
+            _mm_storeu_ps(entry.as_mut_ptr(), t0);
+            (mask, entry)
+        }
+    }
+}
+
+// Bound on the relative error of `n` floating point operations
+fn gamma(n: i32) -> f32 {
+    let eps = f32::EPSILON * 0.5;
+    (n as f32 * eps) / (1.0 - n as f32 * eps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Ray;
+
+    // small deterministic generator so the test does not depend on the sampler
+    fn random(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (*state >> 8) as f32 / (1 << 24) as f32
+    }
+
+    // Slab test of a single box that is not empty, the reference for `Bounds4::intersect`.
+    // Returns the distance at which the ray enters the box in [0, t_max], if it does.
+    fn intersect_one(bounds: Bounds3, ray: &Ray, t_max: f32) -> Option<f32> {
+        let (o, d) = (ray.o(), ray.d());
+        let (mut t0, mut t1) = (0.0f32, t_max);
+
+        for &(o, d, min, max) in &[
+            (o.x(), d.x(), bounds.min.x(), bounds.max.x()),
+            (o.y(), d.y(), bounds.min.y(), bounds.max.y()),
+            (o.z(), d.z(), bounds.min.z(), bounds.max.z()),
+        ] {
+            let inv = 1.0 / d;
+            let t_near = (min - o) * inv;
+            let t_far = (max - o) * inv;
+            let (t_near, t_far) = if t_near > t_far { (t_far, t_near) } else { (t_near, t_far) };
+
+            // make the test conservative against rounding errors, see pbrt 3.9.2
+            let t_far = t_far * (1.0 + 2.0 * gamma(3));
+
+            // max and min ignore NaNs, which occur for rays in the plane of a slab
+            t0 = t0.max(t_near);
+            t1 = t1.min(t_far);
+        }
+
+        if t0 <= t1 {
+            Some(t0)
+        } else {
+            None
+        }
+    }
+
+    fn unit_box(x: f32) -> Bounds3 {
+        Bounds3 {
+            min: Point3::new(x - 0.5, -0.5, -0.5),
+            max: Point3::new(x + 0.5, 0.5, 0.5),
+        }
+    }
+
+    #[test]
+    fn test_bounds4_intersect() {
+        // boxes at x = 2, 4 and 6 and an empty one
+        let bounds = Bounds4::new([unit_box(2.0), unit_box(4.0), unit_box(6.0), Bounds3::default()]);
+
+        let ray = WideRay::new(&Ray::new(Point3::splat(0.0), Vec3::new(1.0, 0.0, 0.0)));
+        let (mask, entry) = bounds.intersect(&ray, f32::INFINITY);
+        assert_eq!(mask, 0b0111);
+        assert_eq!(&entry[..3], &[1.5, 3.5, 5.5]);
+
+        // the boxes beyond t_max are missed
+        assert_eq!(bounds.intersect(&ray, 4.0).0, 0b0011);
+
+        // from the other side, with the near and far planes swapped
+        let ray = WideRay::new(&Ray::new(Point3::new(8.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)));
+        let (mask, entry) = bounds.intersect(&ray, f32::INFINITY);
+        assert_eq!(mask, 0b0111);
+        assert_eq!(&entry[..3], &[5.5, 3.5, 1.5]);
+
+        // parallel to the x slabs with a direction of +0.0 and -0.0, inside and outside them
+        for &zero in &[0.0f32, -0.0] {
+            let ray = WideRay::new(&Ray::new(Point3::new(4.0, 0.0, -2.0), Vec3::new(zero, zero, 1.0)));
+            assert_eq!(bounds.intersect(&ray, f32::INFINITY).0, 0b0010, "{:?}", zero);
+            let ray = WideRay::new(&Ray::new(Point3::new(3.0, 0.0, -2.0), Vec3::new(zero, zero, 1.0)));
+            assert_eq!(bounds.intersect(&ray, f32::INFINITY).0, 0b0000, "{:?}", zero);
+        }
+
+        // a ray starting inside a box enters it at 0
+        let ray = WideRay::new(&Ray::new(Point3::new(4.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)));
+        let (mask, entry) = bounds.intersect(&ray, f32::INFINITY);
+        assert_eq!(mask, 0b0010);
+        assert_eq!(entry[1], 0.0);
+    }
+
+    #[test]
+    fn test_bounds4_matches_bounds3() {
+        let mut state = 5;
+        let random_vec = |state: &mut u32, scale: f32| {
+            Vec3::new(random(state), random(state), random(state)) * (2.0 * scale) - Vec3::splat(scale)
+        };
+
+        for _ in 0..1000 {
+            let mut boxes = [Bounds3::default(); 4];
+            for bounds in boxes.iter_mut().take(3) {
+                let (a, b) = (random_vec(&mut state, 2.0), random_vec(&mut state, 2.0));
+                *bounds = Bounds3::from_point(a.to_point()).union_point(b.to_point());
+            }
+            let bounds = Bounds4::new(boxes);
+
+            let o = random_vec(&mut state, 4.0).to_point();
+            let mut d = random_vec(&mut state, 1.0);
+            // directions with zero components, of either sign
+            let zero = if random(&mut state) < 0.5 { 0.0 } else { -0.0 };
+            match (random(&mut state) * 4.0) as usize {
+                0 => d = Vec3::new(zero, d.y(), d.z()),
+                1 => d = Vec3::new(d.x(), zero, zero),
+                _ => (),
+            }
+            let ray = Ray::new(o, d);
+            let t_max = 8.0 * random(&mut state);
+
+            let (mask, entry) = bounds.intersect(&WideRay::new(&ray), t_max);
+            // the reference swaps the planes of an empty box, so the empty lane is left out
+            assert_eq!(mask & 0b1000, 0);
+            for (i, &bounds) in boxes.iter().enumerate().take(3) {
+                let expected = intersect_one(bounds, &ray, t_max);
+                assert_eq!(mask & (1 << i) != 0, expected.is_some(), "{:?} {:?}", bounds, ray);
+                if let Some(t) = expected {
+                    assert_eq!(entry[i], t);
+                }
+            }
+        }
+    }
+}
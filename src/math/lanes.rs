@@ -0,0 +1,150 @@
+use std::{arch::x86_64::*, fmt::Debug, ops};
+
+use super::{Vec4, VecN};
+
+// Storage for one value per wavelength of a wavelength bundle, the first lane
+// belonging to the hero wavelength. Bundles of four use the SSE `Vec4`, the
+// other sizes fall back to a plain array.
+pub trait Lanes:
+    Copy
+    + Debug
+    + Send
+    + Sync
+    + ops::Add<Output = Self>
+    + ops::Add<f32, Output = Self>
+    + ops::AddAssign
+    + ops::Mul<Output = Self>
+    + ops::Mul<f32, Output = Self>
+    + ops::MulAssign
+    + ops::Div<Output = Self>
+    + ops::Div<f32, Output = Self>
+    + ops::DivAssign
+{
+    // Number of lanes, i.e. wavelengths in the bundle
+    const N: usize;
+
+    fn splat(value: f32) -> Self;
+
+    fn from_fn<F: FnMut(usize) -> f32>(func: F) -> Self;
+
+    fn lane(self, i: usize) -> f32;
+
+    fn hero(self) -> f32 {
+        self.lane(0)
+    }
+
+    fn sum(self) -> f32;
+
+    fn clamp(self, min: f32, max: f32) -> Self;
+
+    fn is_zero(self) -> bool;
+
+    // False if any lane is negative or NaN
+    fn is_non_negative(self) -> bool;
+}
+
+impl Lanes for Vec4 {
+    const N: usize = 4;
+
+    fn splat(value: f32) -> Self {
+        Vec4::splat(value)
+    }
+
+    fn from_fn<F: FnMut(usize) -> f32>(mut func: F) -> Self {
+        let x = func(0);
+        let y = func(1);
+        let z = func(2);
+        let w = func(3);
+        Vec4::new(x, y, z, w)
+    }
+
+    fn lane(self, i: usize) -> f32 {
+        match i {
+            0 => self.x(),
+            1 => self.y(),
+            2 => self.z(),
+            3 => self.w(),
+            _ => panic!("lane {} out of range for Vec4", i),
+        }
+    }
+
+    fn hero(self) -> f32 {
+        self.x()
+    }
+
+    fn sum(self) -> f32 {
+        Vec4::sum(self)
+    }
+
+    fn clamp(self, min: f32, max: f32) -> Self {
+        Vec4::clamp(self, min, max)
+    }
+
+    fn is_zero(self) -> bool {
+        Vec4::is_zero(self)
+    }
+
+    fn is_non_negative(self) -> bool {
+        unsafe { _mm_movemask_ps(_mm_cmpge_ps(self.data, _mm_setzero_ps())) == 0xf }
+    }
+}
+
+impl<const N: usize> Lanes for VecN<N> {
+    const N: usize = N;
+
+    fn splat(value: f32) -> Self {
+        VecN::splat(value)
+    }
+
+    fn from_fn<F: FnMut(usize) -> f32>(func: F) -> Self {
+        VecN::from_fn(func)
+    }
+
+    fn lane(self, i: usize) -> f32 {
+        self.data[i]
+    }
+
+    fn sum(self) -> f32 {
+        self.data.iter().sum()
+    }
+
+    fn clamp(self, min: f32, max: f32) -> Self {
+        VecN::from_fn(|i| self.data[i].max(min).min(max))
+    }
+
+    fn is_zero(self) -> bool {
+        self.data.iter().all(|&value| value == 0.0)
+    }
+
+    fn is_non_negative(self) -> bool {
+        self.data.iter().all(|&value| value >= 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<L: Lanes>() {
+        let a = L::from_fn(|i| i as f32 + 1.0);
+        let b = L::splat(2.0);
+
+        assert_eq!(a.hero(), 1.0);
+        assert_eq!(a.sum(), (L::N * (L::N + 1) / 2) as f32);
+        assert_eq!((a * b).lane(L::N - 1), 2.0 * L::N as f32);
+        assert_eq!((a / b + 1.0).lane(0), 1.5);
+        assert_eq!(a.clamp(0.0, 1.5).sum(), 1.0 + 1.5 * (L::N - 1) as f32);
+        assert!((a * 0.0).is_zero());
+        assert!(a.is_non_negative());
+        assert!(!(a * -1.0).is_non_negative());
+    }
+
+    #[test]
+    fn test_lanes() {
+        check::<VecN<1>>();
+        check::<VecN<2>>();
+        check::<Vec4>();
+        check::<VecN<8>>();
+        check::<VecN<16>>();
+    }
+}
@@ -1,16 +1,22 @@
+mod bounds;
+mod lanes;
 mod matrix;
 mod pdf;
 mod point3;
 mod ray;
 mod vec3;
 mod vec4;
+mod vecn;
 
+pub use bounds::*;
+pub use lanes::*;
 pub use matrix::*;
 pub use pdf::*;
 pub use point3::*;
 pub use ray::*;
 pub use vec3::*;
 pub use vec4::*;
+pub use vecn::*;
 
 pub use ray::offset_origin;
 
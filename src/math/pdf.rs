@@ -1,43 +1,32 @@
-use std::{arch::x86_64::*, mem};
-use crate::math::Vec4;
+use crate::math::{Lanes, Vec4};
 
 #[derive(Copy, Clone)]
-pub struct PdfSet {
-    pub inner: Vec4,
+pub struct PdfSet<L: Lanes = Vec4> {
+    pub inner: L,
 }
 
-impl PdfSet {
-    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+impl<L: Lanes> PdfSet<L> {
+    pub fn splat(xyzw: f32) -> Self {
         Self {
-            inner: Vec4::new(x, y, z, w),
-        }.assert_invariants()
+            inner: L::splat(xyzw),
+        }
+        .assert_invariants()
     }
 
-    pub fn splat(xyzw: f32) -> Self {
+    // Pdfs that are zero for all but the hero wavelength
+    pub fn hero_only(hero: f32) -> Self {
         Self {
-            inner: Vec4::splat(xyzw),
+            inner: L::from_fn(|i| if i == 0 { hero } else { 0.0 }),
         }
         .assert_invariants()
     }
 
     pub fn hero(self) -> f32 {
-        self.inner.x()
-    }
-
-    pub fn x(self) -> f32 {
-        self.inner.x()
-    }
-
-    pub fn y(self) -> f32 {
-        self.inner.y()
-    }
-
-    pub fn z(self) -> f32 {
-        self.inner.z()
+        self.inner.hero()
     }
 
-    pub fn w(self) -> f32 {
-        self.inner.w()
+    pub fn lane(self, i: usize) -> f32 {
+        self.inner.lane(i)
     }
 
     pub fn sum(self) -> f32 {
@@ -47,8 +36,7 @@ impl PdfSet {
     #[inline(always)]
     fn assert_invariants(self) -> Self {
         debug_assert!(
-            unsafe { _mm_test_all_ones(mem::transmute(_mm_cmpge_ps(self.inner.data, _mm_setzero_ps()))) }
-                == 1,
+            self.inner.is_non_negative(),
             "PdfSet contains negative or NaN values: {:?}",
             self
         );
@@ -57,25 +45,22 @@ impl PdfSet {
     }
 }
 
-impl std::fmt::Debug for PdfSet {
+impl<L: Lanes> std::fmt::Debug for PdfSet<L> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_list()
-            .entry(&self.x())
-            .entry(&self.y())
-            .entry(&self.z())
-            .entry(&self.w())
+            .entries((0..L::N).map(|i| self.lane(i)))
             .finish()
     }
 }
 
-impl std::convert::From<Vec4> for PdfSet {
-    fn from(inner: Vec4) -> Self {
+impl<L: Lanes> std::convert::From<L> for PdfSet<L> {
+    fn from(inner: L) -> Self {
         Self { inner }
     }
 }
 
-impl std::ops::Add<PdfSet> for PdfSet {
-    type Output = PdfSet;
+impl<L: Lanes> std::ops::Add<PdfSet<L>> for PdfSet<L> {
+    type Output = PdfSet<L>;
 
     fn add(self, other: Self) -> Self {
         Self {
@@ -85,14 +70,14 @@ impl std::ops::Add<PdfSet> for PdfSet {
     }
 }
 
-impl std::ops::MulAssign<PdfSet> for PdfSet {
-    fn mul_assign(&mut self, other: PdfSet) {
+impl<L: Lanes> std::ops::MulAssign<PdfSet<L>> for PdfSet<L> {
+    fn mul_assign(&mut self, other: PdfSet<L>) {
         self.inner *= other.inner;
         self.assert_invariants();
     }
 }
 
-impl std::ops::Div<f32> for PdfSet {
+impl<L: Lanes> std::ops::Div<f32> for PdfSet<L> {
     type Output = Self;
 
     fn div(self, other: f32) -> Self {
@@ -103,7 +88,7 @@ impl std::ops::Div<f32> for PdfSet {
     }
 }
 
-impl std::ops::Mul<PdfSet> for PdfSet {
+impl<L: Lanes> std::ops::Mul<PdfSet<L>> for PdfSet<L> {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self {
@@ -114,7 +99,7 @@ impl std::ops::Mul<PdfSet> for PdfSet {
     }
 }
 
-impl std::ops::Mul<f32> for PdfSet {
+impl<L: Lanes> std::ops::Mul<f32> for PdfSet<L> {
     type Output = Self;
 
     fn mul(self, other: f32) -> Self {
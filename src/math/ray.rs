@@ -1,8 +1,12 @@
 #![allow(dead_code)]
 
+use std::arch::x86_64::*;
+
 use super::{Point3, Vec3, World};
 
 pub const RAY_EPSILON: f32 = 0.001;
+// Fraction of a shadow segment's length ignored at either end
+pub const SHADOW_EPSILON: f32 = 0.0001;
 
 #[derive(Debug, Clone)]
 pub struct Ray<System = World> {
@@ -83,3 +87,32 @@ pub fn offset_origin<S>(p: Point3<S>, n: Vec3<S>) -> Point3<S> {
         if p.z.abs() < ORIGIN { p.z() + FLOAT_SCALE * n.z() } else { p_i[2] },
     )
 }
+
+// A ray with its components broadcast to all four lanes, for testing it against four
+// boxes or triangles at once.
+#[derive(Clone, Copy)]
+pub struct WideRay {
+    pub o: [__m128; 3],
+    pub d: [__m128; 3],
+    pub inv_d: [__m128; 3],
+    pub dir_is_neg: [bool; 3],
+}
+
+impl WideRay {
+    pub fn new(ray: &Ray) -> Self {
+        let (o, d) = (ray.o(), ray.d());
+        unsafe {
+            Self {
+                o: [_mm_set1_ps(o.x()), _mm_set1_ps(o.y()), _mm_set1_ps(o.z())],
+                d: [_mm_set1_ps(d.x()), _mm_set1_ps(d.y()), _mm_set1_ps(d.z())],
+                inv_d: [
+                    _mm_set1_ps(1.0 / d.x()),
+                    _mm_set1_ps(1.0 / d.y()),
+                    _mm_set1_ps(1.0 / d.z()),
+                ],
+                // from the sign bit, so that -0.0 agrees with its inverse of -inf
+                dir_is_neg: [d.x().is_sign_negative(), d.y().is_sign_negative(), d.z().is_sign_negative()],
+            }
+        }
+    }
+}
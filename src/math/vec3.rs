@@ -74,11 +74,12 @@ impl<S> Vec3<S> {
         Point3::new(self.x(), self.y(), self.z())
     }
 
+    // Two unit vectors completing the unit vector to an orthonormal basis
     pub fn coordinate_system_from_unit(self) -> (Self, Self) {
         let v2 = if self.x().abs() > self.y().abs() {
-            Vec3::new(-self.z(), 0.0, self.x()) / (self.x().powi(2) + self.z().powi(2))
+            Vec3::new(-self.z(), 0.0, self.x()) / (self.x().powi(2) + self.z().powi(2)).sqrt()
         } else {
-            Vec3::new(0.0, self.z(), -self.y()) / (self.y().powi(2) + self.z().powi(2))
+            Vec3::new(0.0, self.z(), -self.y()) / (self.y().powi(2) + self.z().powi(2)).sqrt()
         };
 
         (v2, self.cross(v2))
@@ -353,3 +354,29 @@ impl Vec3<Shading> {
         self.z() * other.z() > 0.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coordinate_system_from_unit() {
+        let directions = [
+            Vec3::<World>::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 2.0, 3.0).normalize(),
+            Vec3::new(-3.0, 0.5, 0.2).normalize(),
+            Vec3::new(0.3, -0.3, -0.9).normalize(),
+        ];
+        for &v1 in &directions {
+            let (v2, v3) = v1.coordinate_system_from_unit();
+            for &(a, b) in &[(v1, v2), (v1, v3), (v2, v3)] {
+                assert!(a.dot(b).abs() < 1e-6, "{:?} {:?}", a, b);
+            }
+            for v in &[v2, v3] {
+                assert!((v.len() - 1.0).abs() < 1e-6, "{:?}", v);
+            }
+        }
+    }
+}
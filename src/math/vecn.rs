@@ -0,0 +1,114 @@
+// Vector of `N` floats for wavelength bundles that do not fit the SSE `Vec4`,
+// the operations are plain loops left to the auto-vectorizer.
+#[derive(Debug, Copy, Clone)]
+pub struct VecN<const N: usize> {
+    pub data: [f32; N],
+}
+
+impl<const N: usize> VecN<N> {
+    pub fn splat(value: f32) -> Self {
+        Self { data: [value; N] }
+    }
+
+    pub fn from_fn<F: FnMut(usize) -> f32>(mut func: F) -> Self {
+        let mut data = [0.0; N];
+        for (i, value) in data.iter_mut().enumerate() {
+            *value = func(i);
+        }
+        Self { data }
+    }
+
+    fn zip<F: Fn(f32, f32) -> f32>(self, other: Self, func: F) -> Self {
+        Self::from_fn(|i| func(self.data[i], other.data[i]))
+    }
+
+    fn map<F: Fn(f32) -> f32>(self, func: F) -> Self {
+        Self::from_fn(|i| func(self.data[i]))
+    }
+}
+
+impl<const N: usize> std::ops::Add<VecN<N>> for VecN<N> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.zip(other, |a, b| a + b)
+    }
+}
+
+impl<const N: usize> std::ops::Add<f32> for VecN<N> {
+    type Output = Self;
+
+    fn add(self, other: f32) -> Self {
+        self.map(|a| a + other)
+    }
+}
+
+impl<const N: usize> std::ops::AddAssign<VecN<N>> for VecN<N> {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl<const N: usize> std::ops::Mul<VecN<N>> for VecN<N> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.zip(other, |a, b| a * b)
+    }
+}
+
+impl<const N: usize> std::ops::Mul<f32> for VecN<N> {
+    type Output = Self;
+
+    fn mul(self, other: f32) -> Self {
+        self.map(|a| a * other)
+    }
+}
+
+impl<const N: usize> std::ops::MulAssign<VecN<N>> for VecN<N> {
+    fn mul_assign(&mut self, other: Self) {
+        *self = *self * other;
+    }
+}
+
+impl<const N: usize> std::ops::Div<VecN<N>> for VecN<N> {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        debug_assert!(
+            other.data.iter().all(|&b| b != 0.0),
+            "division by zero: {:?}",
+            self
+        );
+
+        self.zip(other, |a, b| a / b)
+    }
+}
+
+impl<const N: usize> std::ops::Div<f32> for VecN<N> {
+    type Output = Self;
+
+    fn div(self, other: f32) -> Self {
+        debug_assert!(other != 0.0);
+
+        self.map(|a| a / other)
+    }
+}
+
+impl<const N: usize> std::ops::DivAssign<VecN<N>> for VecN<N> {
+    fn div_assign(&mut self, other: Self) {
+        *self = self.zip(other, |a, b| a / b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn test_div_by_zero_vector() {
+        let _ = VecN::<8>::splat(1.0) / VecN::from_fn(|i| i as f32);
+    }
+}
@@ -0,0 +1,250 @@
+// Partial renders of the same scene with different seeds, e.g. on several machines, add up
+// to one estimate. With `--save-sums` a render also saves the filtered sums and the moments
+// of its pixels as `<output name>.sums`, and `iris merge` adds those of several renders.
+// As a pixel is the weighted sum of its samples over the sum of their weights, adding the
+// sums weights every render by the samples it took, and the moments of the merged pixels
+// are those of all their samples.
+//
+// The file is little endian: a magic number and version, the estimator settings as text, a
+// hash of the scene, the seed, the color space, the size and then the sums and moments of
+// the pixels row by row.
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use crate::{
+    checkpoint::{self, read_f32, read_f64, read_u32, read_u64},
+    color::{ColorSpace, Xyz},
+    integrator::SpectralMode,
+    tile::PixelSum,
+    variance::PixelMoments,
+    Render,
+};
+
+const MAGIC: &[u8; 8] = b"IRISSUMS";
+const VERSION: u32 = 1;
+// Of a file, far beyond any render, so that a corrupt size is an error rather than an
+// attempt at a huge allocation
+const MAX_PIXELS: usize = 1 << 28;
+
+pub struct PartialRender {
+    pub settings: String,
+    pub scene_hash: u64,
+    // of every render added, each has to have its own
+    pub seeds: Vec<u32>,
+    pub color_space: ColorSpace,
+    pub width: usize,
+    pub height: usize,
+    pub sums: Vec<PixelSum>,
+    pub moments: Vec<PixelMoments>,
+}
+
+impl PartialRender {
+    pub fn from_render(render: &Render, spectral_mode: SpectralMode) -> Self {
+        Self {
+            settings: checkpoint::estimator_settings(render, spectral_mode),
+            scene_hash: checkpoint::scene_hash(&render.scene, &render.camera),
+            seeds: vec![render.seed],
+            color_space: render.color_space,
+            width: render.width,
+            height: render.height,
+            sums: render.pixel_sums.read().unwrap().clone(),
+            moments: render.moments.read().unwrap().clone(),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+
+    pub fn read(path: &str) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    // Adds the samples of another render of the same scene with the same settings
+    pub fn add(&mut self, other: &PartialRender) -> Result<(), String> {
+        if other.settings != self.settings {
+            return Err(format!("rendered with other settings: {}", other.settings));
+        }
+        if other.scene_hash != self.scene_hash {
+            return Err(String::from("a render of another scene"));
+        }
+        if let Some(seed) = other.seeds.iter().find(|seed| self.seeds.contains(seed)) {
+            return Err(format!("rendered with seed {} as well, its samples are the same", seed));
+        }
+
+        self.seeds.extend_from_slice(&other.seeds);
+        for (sum, other) in self.sums.iter_mut().zip(&other.sums) {
+            sum.xyz += other.xyz;
+            sum.weight += other.weight;
+        }
+        for (moments, other) in self.moments.iter_mut().zip(&other.moments) {
+            moments.count += other.count;
+            for i in 0..4 {
+                moments.sum[i] += other.sum[i];
+                moments.sum_squares[i] += other.sum_squares[i];
+            }
+        }
+        Ok(())
+    }
+
+    // Linear RGB of the pixels
+    pub fn to_rgb(&self) -> Vec<(f32, f32, f32)> {
+        self.sums.iter().map(|sum| sum.to_rgb(self.color_space)).collect()
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(self.settings.len() as u32).to_le_bytes())?;
+        w.write_all(self.settings.as_bytes())?;
+        w.write_all(&self.scene_hash.to_le_bytes())?;
+        w.write_all(&(self.seeds.len() as u32).to_le_bytes())?;
+        for seed in &self.seeds {
+            w.write_all(&seed.to_le_bytes())?;
+        }
+        let color_space = self.color_space.name();
+        w.write_all(&(color_space.len() as u32).to_le_bytes())?;
+        w.write_all(color_space.as_bytes())?;
+        w.write_all(&(self.width as u32).to_le_bytes())?;
+        w.write_all(&(self.height as u32).to_le_bytes())?;
+
+        for (sum, moments) in self.sums.iter().zip(&self.moments) {
+            for value in &[sum.xyz.x(), sum.xyz.y(), sum.xyz.z(), sum.weight] {
+                w.write_all(&value.to_le_bytes())?;
+            }
+            w.write_all(&moments.count.to_le_bytes())?;
+            for value in moments.sum.iter().chain(&moments.sum_squares) {
+                w.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let read_string = |r: &mut R| -> io::Result<String> {
+            let mut bytes = vec![0; read_u32(r)? as usize];
+            r.read_exact(&mut bytes)?;
+            String::from_utf8(bytes).map_err(|_| invalid(String::from("invalid text")))
+        };
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid(String::from("not a file of render sums")));
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let settings = read_string(r)?;
+        let scene_hash = read_u64(r)?;
+        let seeds = (0..read_u32(r)?).map(|_| read_u32(r)).collect::<io::Result<Vec<_>>>()?;
+        let color_space = read_string(r)?;
+        let color_space = ColorSpace::from_name(&color_space)
+            .ok_or_else(|| invalid(format!("unknown color space `{}`", color_space)))?;
+        let width = read_u32(r)? as usize;
+        let height = read_u32(r)? as usize;
+        let pixels = match width.checked_mul(height) {
+            Some(pixels) if pixels <= MAX_PIXELS => pixels,
+            _ => return Err(invalid(format!("invalid size {}x{}", width, height))),
+        };
+
+        // grown as the pixels are read, a truncated file ends before it gets large
+        let mut sums = Vec::new();
+        let mut moments = Vec::new();
+        for _ in 0..pixels {
+            let (x, y, z) = (read_f32(r)?, read_f32(r)?, read_f32(r)?);
+            sums.push(PixelSum {
+                xyz: Xyz::new(x, y, z),
+                weight: read_f32(r)?,
+            });
+            let mut pixel = PixelMoments::ZERO;
+            pixel.count = read_u32(r)?;
+            for value in pixel.sum.iter_mut().chain(pixel.sum_squares.iter_mut()) {
+                *value = read_f64(r)?;
+            }
+            moments.push(pixel);
+        }
+
+        Ok(Self {
+            settings,
+            scene_hash,
+            seeds,
+            color_space,
+            width,
+            height,
+            sums,
+            moments,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(seed: u32, value: f32, weight: f32) -> PartialRender {
+        let mut moments = PixelMoments::ZERO;
+        moments.add((value, value, value), value);
+        PartialRender {
+            settings: String::from("settings"),
+            scene_hash: 7,
+            seeds: vec![seed],
+            color_space: ColorSpace::Srgb,
+            width: 2,
+            height: 1,
+            sums: vec![
+                PixelSum {
+                    xyz: Xyz::new(value, value, value) * weight,
+                    weight,
+                };
+                2
+            ],
+            moments: vec![moments; 2],
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut data = Vec::new();
+        partial(1, 0.5, 1.0).write_to(&mut data).unwrap();
+        let mut merged = PartialRender::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!((merged.width, merged.height, merged.seeds.clone()), (2, 1, vec![1]));
+        assert_eq!(merged.sums[1].xyz.y(), 0.5);
+
+        // the render with three times the weight counts three times as much
+        merged.add(&partial(2, 1.0, 3.0)).unwrap();
+        assert_eq!(merged.sums[0].weight, 4.0);
+        assert_eq!(merged.sums[0].xyz.y() / merged.sums[0].weight, 0.875);
+        assert_eq!(merged.moments[0].count, 2);
+        assert_eq!(merged.moments[0].mean(3), 0.75);
+
+        assert!(merged.add(&partial(2, 1.0, 1.0)).is_err());
+        let mut other = partial(3, 1.0, 1.0);
+        other.scene_hash = 8;
+        assert!(merged.add(&other).is_err());
+        other = partial(3, 1.0, 1.0);
+        other.settings = String::from("other");
+        assert!(merged.add(&other).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_size() {
+        let mut data = Vec::new();
+        partial(1, 0.5, 1.0).write_to(&mut data).unwrap();
+        // the width and height are right before the 2 pixels of 4 f32s, a count and 8 f64s
+        let size = data.len() - 2 * (4 * 4 + 4 + 8 * 8) - 8;
+        for &(width, height) in &[(u32::MAX, u32::MAX), (1 << 16, 1 << 16), (1 << 20, 1)] {
+            data[size..size + 4].copy_from_slice(&width.to_le_bytes());
+            data[size + 4..size + 8].copy_from_slice(&height.to_le_bytes());
+            assert!(PartialRender::read_from(&mut data.as_slice()).is_err());
+        }
+        data[size..size + 8].copy_from_slice(&[2, 0, 0, 0, 1, 0, 0, 0]);
+        assert!(PartialRender::read_from(&mut data.as_slice()).is_ok());
+    }
+}
@@ -0,0 +1,392 @@
+// Image file writers, chosen by the extension of the output name: 8 bit PNG, or the high
+// dynamic range formats PFM and OpenEXR, which keep the linear float values of the render
+// buffer unclamped.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{color::ColorSpace, display::DisplayPipeline};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    // None for names without one of the known extensions
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        Some(match extension.to_ascii_lowercase().as_str() {
+            "png" => ImageFormat::Png,
+            "pfm" => ImageFormat::Pfm,
+            "exr" => ImageFormat::Exr,
+            _ => return None,
+        })
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Exr => "exr",
+        }
+    }
+}
+
+// Pixel type and compression of OpenEXR files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExrOptions {
+    pub half: bool,
+    pub zip: bool,
+}
+
+impl Default for ExrOptions {
+    fn default() -> Self {
+        Self {
+            half: true,
+            zip: true,
+        }
+    }
+}
+
+pub struct Image<'a> {
+    pub width: usize,
+    pub height: usize,
+    // linear RGB, rows from top to bottom
+    pub pixels: &'a [(f32, f32, f32)],
+    pub color_space: ColorSpace,
+    // how the values are brought to 8 bits for PNG
+    pub display: DisplayPipeline,
+}
+
+pub fn write(path: &str, format: ImageFormat, image: &Image, exr: ExrOptions) -> io::Result<()> {
+    assert_eq!(image.pixels.len(), image.width * image.height);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ImageFormat::Png => write_png(&mut writer, image)?,
+        ImageFormat::Pfm => write_pfm(&mut writer, image)?,
+        ImageFormat::Exr => write_exr(&mut writer, image, exr)?,
+    }
+    writer.flush()
+}
+
+fn write_png<W: Write>(w: W, image: &Image) -> io::Result<()> {
+    // configure the encoder
+    let mut encoder = png::Encoder::new(w, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_trns(vec!(0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8));
+    // the values are encoded with the sRGB curve whatever the primaries. With an sRGB chunk
+    // the gamma and chromaticities below are only written as its fallback.
+    if image.color_space == ColorSpace::Srgb {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    }
+    // 1 / 2.2 as the PNG specification rounds it
+    encoder.set_source_gamma(png::ScaledFloat::from_scaled(45455));
+    let [white_x, white_y] = image.color_space.white_point();
+    let [red, green, blue] = image.color_space.primaries();
+    let source_chromaticities = png::SourceChromaticities::new(
+        (white_x as f32, white_y as f32),
+        (red[0] as f32, red[1] as f32),
+        (green[0] as f32, green[1] as f32),
+        (blue[0] as f32, blue[1] as f32)
+    );
+    encoder.set_source_chromaticities(source_chromaticities);
+    let mut writer = encoder.write_header()?;
+
+    // convert the f32 (R,G,B) values to u8 format in order [R,G,B,A]
+    let mut buffer: Vec<u8> = vec![0; image.width * image.height * 4];
+    for (index, pixel) in image.pixels.iter().enumerate() {
+        let [r, g, b] = image.display.to_8bit(*pixel, index % image.width, index / image.width);
+        buffer[4*index..4*index + 4].copy_from_slice(&[r, g, b, 255]);
+    }
+
+    writer.write_image_data(&buffer)?;
+    Ok(())
+}
+
+// Portable float map: a text header, then little endian RGB floats with the rows from
+// bottom to top
+fn write_pfm<W: Write>(mut w: W, image: &Image) -> io::Result<()> {
+    // a negative scale marks little endian data
+    write!(w, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.pixels.chunks_exact(image.width).rev() {
+        for &(r, g, b) in row {
+            for value in &[r, g, b] {
+                w.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Picks one channel out of an RGB pixel
+type Channel = fn(&(f32, f32, f32)) -> f32;
+
+// Single part scanline OpenEXR file with B, G and R channels, uncompressed or ZIP
+// compressed in blocks of 16 lines. See "The OpenEXR File Layout" for the format.
+fn write_exr<W: Write>(mut w: W, image: &Image, options: ExrOptions) -> io::Result<()> {
+    let (width, height) = (image.width, image.height);
+    let lines_per_block = if options.zip { 16 } else { 1 };
+    let blocks = height.div_ceil(lines_per_block);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    // version 2, single part scanline
+    header.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut channels = Vec::new();
+    // the channels are sorted by name
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        let pixel_type: i32 = if options.half { 1 } else { 2 };
+        channels.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and reserved bytes
+        channels.extend_from_slice(&[0; 4]);
+        // x and y sampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    exr_attribute(&mut header, "channels", "chlist", &channels);
+
+    let [white_x, white_y] = image.color_space.white_point();
+    let [red, green, blue] = image.color_space.primaries();
+    let chromaticities = [red[0], red[1], green[0], green[1], blue[0], blue[1], white_x, white_y]
+        .iter()
+        .flat_map(|&value| (value as f32).to_le_bytes())
+        .collect::<Vec<_>>();
+    exr_attribute(&mut header, "chromaticities", "chromaticities", &chromaticities);
+
+    exr_attribute(&mut header, "compression", "compression", &[if options.zip { 3 } else { 0 }]);
+
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    exr_attribute(&mut header, "dataWindow", "box2i", &window);
+    exr_attribute(&mut header, "displayWindow", "box2i", &window);
+    // increasing y
+    exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    exr_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    exr_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    // each block is its first line, the size of its data and the data
+    let mut chunks = Vec::with_capacity(blocks);
+    for block in 0..blocks {
+        let first_line = block * lines_per_block;
+        let last_line = (first_line + lines_per_block).min(height);
+
+        let mut data = Vec::new();
+        for y in first_line..last_line {
+            let row = &image.pixels[y * width..(y + 1) * width];
+            let channels: [Channel; 3] = [|p| p.2, |p| p.1, |p| p.0];
+            for channel in &channels {
+                for pixel in row {
+                    let value = channel(pixel);
+                    if options.half {
+                        data.extend_from_slice(&f32_to_half(value).to_le_bytes());
+                    } else {
+                        data.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+        }
+
+        if options.zip {
+            let compressed = zip_compress(&data);
+            // blocks that do not get smaller are stored as they are
+            if compressed.len() < data.len() {
+                data = compressed;
+            }
+        }
+
+        let mut chunk = Vec::with_capacity(data.len() + 8);
+        chunk.extend_from_slice(&(first_line as i32).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        chunk.extend_from_slice(&data);
+        chunks.push(chunk);
+    }
+
+    w.write_all(&header)?;
+    let mut offset = (header.len() + 8 * blocks) as u64;
+    for chunk in &chunks {
+        w.write_all(&offset.to_le_bytes())?;
+        offset += chunk.len() as u64;
+    }
+    for chunk in &chunks {
+        w.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// OpenEXR's ZIP compression: the bytes are split into the even and the odd ones, delta
+// encoded and deflated with a zlib header
+fn zip_compress(data: &[u8]) -> Vec<u8> {
+    let mut reordered: Vec<u8> = Vec::with_capacity(data.len());
+    reordered.extend(data.iter().step_by(2));
+    reordered.extend(data.iter().skip(1).step_by(2));
+
+    let mut previous = reordered[0];
+    for value in reordered.iter_mut().skip(1) {
+        let current = *value;
+        *value = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+
+    deflate::deflate_bytes_zlib(&reordered)
+}
+
+// Rounds to the nearest half precision float, values beyond its range become infinite
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity, or NaN with a set mantissa bit
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if exponent <= 0 {
+        // subnormal half, or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let rest = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rest > halfway || (rest == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let rest = mantissa & 0x1fff;
+    let round = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+    // a carry out of the mantissa correctly increments the exponent, up to infinity
+    sign | (half + round as u32) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_half() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65520.0), 0x7c00);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NAN) & 0x7e00, 0x7e00);
+        // smallest subnormal and the rounding around it
+        assert_eq!(f32_to_half(5.960464e-8), 0x0001);
+        assert_eq!(f32_to_half(2.0e-8), 0x0000);
+        // smallest normal
+        assert_eq!(f32_to_half(2f32.powi(-14)), 0x0400);
+        // ties round to even
+        assert_eq!(f32_to_half(1.0 + 1.0 / 2048.0), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 / 2048.0), 0x3c02);
+        assert_eq!(f32_to_half(0.1), 0x2e66);
+    }
+
+    #[test]
+    fn test_pfm() {
+        let pixels = [(1.0, 2.0, 3.0), (4.0, 5.0, 6.0)];
+        let image = Image {
+            width: 1,
+            height: 2,
+            pixels: &pixels,
+            color_space: ColorSpace::Srgb,
+            display: DisplayPipeline::default(),
+        };
+        let mut bytes = Vec::new();
+        write_pfm(&mut bytes, &image).unwrap();
+
+        let header = b"PF\n1 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // the bottom row comes first
+        assert_eq!(&bytes[header.len()..header.len() + 4], &4f32.to_le_bytes());
+        assert_eq!(bytes.len(), header.len() + 2 * 3 * 4);
+    }
+
+    #[test]
+    fn test_exr_layout() {
+        let pixels = vec![(0.25, 0.5, 1.0); 4 * 20];
+        let image = Image {
+            width: 4,
+            height: 20,
+            pixels: &pixels,
+            color_space: ColorSpace::Srgb,
+            display: DisplayPipeline::default(),
+        };
+
+        for &(half, zip) in &[(true, false), (false, false), (true, true), (false, true)] {
+            let mut bytes = Vec::new();
+            write_exr(&mut bytes, &image, ExrOptions { half, zip }).unwrap();
+            assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+            // the offset table follows the header and points at the chunks in order
+            // screenWindowWidth is the last attribute, a float, then the header ends with a 0
+            let last = bytes.windows(18).position(|w| w == b"screenWindowWidth\0").unwrap();
+            let header_end = last + 18 + "float\0".len() + 4 + 4 + 1;
+
+            let blocks = if zip { 2 } else { 20 };
+            let offset = |i: usize| {
+                let start = header_end + 8 * i;
+                u64::from_le_bytes(bytes[start..start + 8].try_into().unwrap()) as usize
+            };
+            assert_eq!(offset(0), header_end + 8 * blocks);
+            for i in 0..blocks {
+                let start = offset(i);
+                let line = i32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+                let size = i32::from_le_bytes(bytes[start + 4..start + 8].try_into().unwrap());
+                assert_eq!(line as usize, i * if zip { 16 } else { 1 });
+                let end = start + 8 + size as usize;
+                assert_eq!(end, if i + 1 < blocks { offset(i + 1) } else { bytes.len() });
+            }
+
+            if !zip {
+                // the first line holds the blue, green and red values in turn
+                let data = offset(0) + 8;
+                if half {
+                    assert_eq!(&bytes[data..data + 2], &f32_to_half(1.0).to_le_bytes());
+                    assert_eq!(&bytes[data + 8..data + 10], &f32_to_half(0.5).to_le_bytes());
+                } else {
+                    assert_eq!(&bytes[data..data + 4], &1f32.to_le_bytes());
+                    assert_eq!(&bytes[data + 32..data + 36], &0.25f32.to_le_bytes());
+                }
+            }
+        }
+    }
+}
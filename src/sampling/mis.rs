@@ -1,10 +1,10 @@
-use crate::math::PdfSet;
+use crate::math::{Lanes, PdfSet};
 
-pub fn balance_heuristic_1(f: PdfSet) -> f32 {
+pub fn balance_heuristic_1<L: Lanes>(f: PdfSet<L>) -> f32 {
     f.hero() / f.sum()
 }
 
 // TODO: Power heuristic
-pub fn balance_heuristic_2(f: PdfSet, g: PdfSet) -> f32 {
+pub fn balance_heuristic_2<L: Lanes>(f: PdfSet<L>, g: PdfSet<L>) -> f32 {
     f.hero() / (f + g).sum()
 }
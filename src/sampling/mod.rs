@@ -30,6 +30,13 @@ pub fn unit_sphere<S>(r1: f32, r2: f32) -> Vec3<S> {
     Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
+// Uniformly distributed barycentric coordinates (b1, b2, b3) over a triangle
+pub fn uniform_triangle(r1: f32, r2: f32) -> (f32, f32, f32) {
+    let su = r1.sqrt();
+    let (b1, b2) = (1.0 - su, r2 * su);
+    (b1, b2, 1.0 - b1 - b2)
+}
+
 fn concentric_disk(r1: f32, r2: f32) -> (f32, f32) {
     let x_off = 2.0 * r1 - 1.0;
     let y_off = 2.0 * r2 - 1.0;
@@ -2,7 +2,9 @@
 #![allow(dead_code)]
 use crate::{
     bsdf::{Bsdf, FresnelBsdf, LambertianBsdf, MicrofacetBsdf, SampleableBsdf, SpecularBsdf},
-    math::{self, PdfSet, Point3, Ray, Shading, Vec3},
+    bvh::Bvh,
+    color::ColorSpace,
+    math::{self, Lanes, PdfSet, Point3, Ray, Shading, Vec3},
     sampling::{self, mis, Sampler},
     shape::{Geometry, Intersection, Primitive, Shape, Sphere, Triangle},
     spectrum::{
@@ -24,13 +26,20 @@ pub struct Scene {
     pub lights: Vec<PrimIndex<Spectrum>>,
     pub materials: Vec<PrimIndex<Bsdf>>,
     pub primitives: Vec<Primitive>,
+    // Index of the material definition of each entry of `materials`, shared by all the
+    // primitives made of the same material
+    pub material_indices: Vec<usize>,
+    material_count: usize,
+    // Given to the primitives added next, a new one for each without it
+    current_material_index: Option<usize>,
+    bvh: Bvh,
     _env_map: Vec<UpsampledHdrSpectrum>,
 }
 
 impl Scene {
     pub fn dispersion() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -162,7 +171,7 @@ impl Scene {
 
     pub fn boxed_light() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -278,7 +287,7 @@ impl Scene {
 
     pub fn glass_on_field() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -338,7 +347,7 @@ impl Scene {
 
     pub fn cornell_box() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -426,7 +435,7 @@ impl Scene {
 
     pub fn cornell_box_spheres() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -480,7 +489,7 @@ impl Scene {
 
     pub fn cornell_box_constant() -> Self {
         let mut scene = Self::default();
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // define color spectra
         let orange = upsample_table.get_spectrum([1.0, 0.4, 0.0]);
@@ -608,7 +617,7 @@ impl Scene {
     pub fn dummy() -> Self {
         let mut scene = Self::default();
 
-        let upsample_table = UpsampleTable::load();
+        let upsample_table = UpsampleTable::load(ColorSpace::Srgb);
 
         // add light
         scene.add_emissive_material(
@@ -630,7 +639,7 @@ impl Scene {
         scene
     }
 
-    fn add_light<G: Into<Geometry>, S: Into<Spectrum>>(&mut self, geom: G, light: S) {
+    pub fn add_light<G: Into<Geometry>, S: Into<Spectrum>>(&mut self, geom: G, light: S) {
         self.lights.push(PrimIndex {
             data: light.into(),
             prim_index: self.primitives.len(),
@@ -639,7 +648,28 @@ impl Scene {
             .push(Primitive::new_light(geom.into(), self.lights.len() - 1));
     }
 
-    fn add_material<G: Into<Geometry>, B: Into<Bsdf>>(&mut self, geom: G, material: B) {
+    // Index for a new material definition, see `set_material_index`
+    pub fn new_material_index(&mut self) -> usize {
+        self.material_count += 1;
+        self.material_count - 1
+    }
+
+    // Material index of the primitives added from now on. Without one, every primitive
+    // with a material gets a new index.
+    pub fn set_material_index(&mut self, index: Option<usize>) {
+        self.current_material_index = index;
+    }
+
+    fn push_material_index(&mut self) {
+        let index = match self.current_material_index {
+            Some(index) => index,
+            None => self.new_material_index(),
+        };
+        self.material_indices.push(index);
+    }
+
+    pub fn add_material<G: Into<Geometry>, B: Into<Bsdf>>(&mut self, geom: G, material: B) {
+        self.push_material_index();
         self.materials.push(PrimIndex {
             data: material.into(),
             prim_index: self.primitives.len(),
@@ -650,12 +680,13 @@ impl Scene {
         ));
     }
 
-    fn add_emissive_material<G: Into<Geometry>, B: Into<Bsdf>, S: Into<Spectrum>>(
+    pub fn add_emissive_material<G: Into<Geometry>, B: Into<Bsdf>, S: Into<Spectrum>>(
         &mut self,
         geom: G,
         material: B,
         light: S,
     ) {
+        self.push_material_index();
         self.materials.push(PrimIndex {
             data: material.into(),
             prim_index: self.primitives.len(),
@@ -712,7 +743,7 @@ impl Scene {
         ];
     }
 
-    fn get_vertices_closed_box(box_center: Point3, box_size: Vec3) -> [Point3; 36] {
+    pub fn get_vertices_closed_box(box_center: Point3, box_size: Vec3) -> [Point3; 36] {
         // build a smaller box around the light
         let bfl = box_center + Vec3::new(-box_size.x(), -box_size.y(), -box_size.z());
         let bfr = box_center + Vec3::new(box_size.x(), -box_size.y(), -box_size.z());
@@ -769,60 +800,67 @@ impl Scene {
         ];
     }
 
-    pub fn background_emission(&self, ray: &Ray, _wavelength: Wavelength) -> SpectralSample {
+    pub fn background_emission<L: Lanes>(&self, ray: &Ray, _wavelength: Wavelength<L>) -> SpectralSample<L> {
         SpectralSample::splat(0.0)
     }
 
+    // Builds the acceleration structure, must be called after the last primitive was added
+    pub fn build_bvh(&mut self) {
+        self.bvh = Bvh::build(&self.primitives);
+    }
+
     pub fn intersection(&self, ray: &Ray) -> Option<(&Primitive, Intersection)> {
-        let mut closest_t = INFINITY;
-        let mut closest_prim_hit = None;
-
-        // Note: for some reason, the equivalent code with iterators is *much* slower
-        for prim in &self.primitives {
-            match prim.intersect(ray) {
-                Some((hit, t)) if t < closest_t && t > 0.0 => {
-                    closest_t = t;
-                    closest_prim_hit = Some((prim, hit));
-                }
-                _ => continue,
-            }
-        }
+        debug_assert_eq!(self.bvh.primitive_count(), self.primitives.len(), "BVH is out of date");
 
-        closest_prim_hit
+        self.bvh.intersect(&self.primitives, ray)
     }
 
-    pub fn ray_hits_point(&self, ray: &Ray, pos: Point3) -> bool {
-        let mut closest_t = INFINITY;
-
-        for prim in &self.primitives {
-            match prim.intersect(ray) {
-                Some((_, t)) if t < closest_t && t > 0.0 => {
-                    closest_t = t;
-                }
-                _ => continue,
-            }
-        }
+    // Position of a primitive returned by `intersection` in `primitives`
+    pub fn primitive_index(&self, prim: &Primitive) -> usize {
+        let offset = prim as *const Primitive as usize - self.primitives.as_ptr() as usize;
+        let index = offset / std::mem::size_of::<Primitive>();
+        debug_assert!(std::ptr::eq(prim, &self.primitives[index]));
+        index
+    }
 
-        // TODO: Is this good enough?
-        let target_t = (pos - ray.o()).len() / ray.d().len();
-        closest_t > target_t - math::RAY_EPSILON
+    // Index of the material definition of a primitive, if it has a material
+    pub fn material_index(&self, prim: &Primitive) -> Option<usize> {
+        prim.material_index.map(|i| self.material_indices[i])
     }
 
-    pub fn ray_hits_object(&self, ray: &Ray, light: &Primitive) -> bool {
-        let mut closest_t = INFINITY;
-        let mut closest_hit_is_obj = false;
-
-        for prim in &self.primitives {
-            match prim.intersect(ray) {
-                Some((_, t)) if t < closest_t && t > 0.0 => {
-                    closest_t = t;
-                    closest_hit_is_obj = std::ptr::eq(prim, light);
-                }
-                _ => continue,
-            }
-        }
+    // Whether anything blocks the segment from `origin`, a point on a surface with the given
+    // normal, to `target`, a point sampled on `light`. Stops at the first blocker found. Hits
+    // on the light itself do not count, so that it does not shadow the very point it was
+    // sampled at.
+    pub fn light_occluded(
+        &self,
+        origin: Point3,
+        normal: Vec3,
+        target: Point3,
+        light: &Primitive,
+    ) -> bool {
+        self.segment_blocked(origin, normal, target, |prim| {
+            prim.light_index.is_some() && prim.light_index == light.light_index
+        })
+    }
 
-        closest_hit_is_obj
+    fn segment_blocked(
+        &self,
+        origin: Point3,
+        normal: Vec3,
+        target: Point3,
+        ignore: impl Fn(&Primitive) -> bool,
+    ) -> bool {
+        debug_assert_eq!(self.bvh.primitive_count(), self.primitives.len(), "BVH is out of date");
+
+        let ray = Ray::spawn_to(origin, target, normal);
+        let distance = ray.o().distance(target);
+
+        // only blockers within [epsilon, distance - epsilon) count, which leaves out the
+        // surfaces at both ends of the segment
+        let epsilon = math::SHADOW_EPSILON * distance;
+        self.bvh
+            .intersect_any(&self.primitives, &ray, epsilon, distance - epsilon, ignore)
     }
 
     pub fn pick_one_light(&self, sampler: &mut Sampler) -> (&Spectrum, &Primitive, f32) {
@@ -831,3 +869,39 @@ impl Scene {
         (&light.data, &self.primitives[light.prim_index], self.lights.len() as f32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocker(z: f32) -> Triangle {
+        Triangle::new(Point3::new(-1.0, -1.0, z), Point3::new(1.0, -1.0, z), Point3::new(0.0, 1.0, z))
+    }
+
+    #[test]
+    fn test_light_occluded() {
+        // a spherical light around (0, 0, 2) seen from the origin, the target on its far side
+        let origin = Point3::splat(0.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let target = Point3::new(0.0, 0.0, 2.5);
+
+        let mut scene = Scene::default();
+        scene.add_light(Sphere::new(Point3::new(0.0, 0.0, 2.0), 0.5), ConstantSpectrum::new(1.0));
+        scene.build_bvh();
+        let light = &scene.primitives[0];
+        // its own near side does not shadow it, but does block any other segment
+        assert!(!scene.light_occluded(origin, normal, target, light));
+        assert!(scene.segment_blocked(origin, normal, target, |_| false));
+
+        // a blocker beyond the target does not count
+        scene.add_material(blocker(3.0), LambertianBsdf::new(ConstantSpectrum::new(0.5)));
+        scene.build_bvh();
+        assert!(!scene.light_occluded(origin, normal, target, &scene.primitives[0]));
+
+        // one inside the segment does, other lights included
+        scene.add_light(blocker(1.0), ConstantSpectrum::new(1.0));
+        scene.build_bvh();
+        assert!(scene.light_occluded(origin, normal, target, &scene.primitives[0]));
+        assert!(!scene.light_occluded(origin, normal, Point3::new(0.0, 0.0, 1.0), &scene.primitives[2]));
+    }
+}
@@ -2,7 +2,7 @@ use enum_dispatch::enum_dispatch;
 
 use crate::{
     bsdf::Bsdf,
-    math::{Point3, Ray, Shading, Vec3, World},
+    math::{Bounds3, Point3, Ray, Shading, Vec3, World},
     sampling::Sampler,
     spectrum::Spectrum,
     types::PrimIndex,
@@ -12,7 +12,7 @@ mod sphere;
 pub use sphere::Sphere;
 
 mod triangle;
-pub use triangle::Triangle;
+pub use triangle::{Triangle, Triangle4};
 
 #[derive(Debug)]
 pub struct Intersection {
@@ -47,6 +47,8 @@ pub trait Shape {
     fn sample(&self, hit: &Intersection, sampler: &mut Sampler) -> (Point3, f32);
 
     fn pdf(&self, hit: &Intersection, wi: Vec3) -> f32;
+
+    fn bounds(&self) -> Bounds3;
 }
 
 #[enum_dispatch(Shape)]
@@ -75,6 +77,10 @@ impl Shape for Primitive {
     fn pdf(&self, hit: &Intersection, wi: Vec3) -> f32 {
         self.geometry.pdf(hit, wi)
     }
+
+    fn bounds(&self) -> Bounds3 {
+        self.geometry.bounds()
+    }
 }
 
 impl Primitive {
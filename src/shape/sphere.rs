@@ -1,5 +1,5 @@
 use crate::{
-    math::{self, Local, Point3, Ray, Vec3},
+    math::{self, Bounds3, Local, Point3, Ray, Vec3},
     sampling::{self, Sampler},
     shape::{Intersection, Shape},
 };
@@ -160,4 +160,9 @@ impl Shape for Sphere {
         let cos_theta_max = (1.0 - sin_theta_max_2).max(0.0).sqrt();
         sampling::pdf_cone(cos_theta_max)
     }
+
+    fn bounds(&self) -> Bounds3 {
+        let r = Vec3::splat(self.radius);
+        Bounds3::new(self.position - r, self.position + r)
+    }
 }
@@ -1,5 +1,7 @@
+use std::arch::x86_64::*;
+
 use crate::{
-    math::{self, Local, Point3, Ray, Vec3},
+    math::{self, Bounds3, Local, Point3, Ray, Vec3, WideRay},
     sampling::{self, Sampler},
     shape::{Intersection, Shape},
 };
@@ -15,6 +17,7 @@ pub struct Triangle {
     normal: Vec3,
     dpdu: Vec3,
     dpdv: Vec3,
+    normals: Option<[Vec3; 3]>,
 }
 
 impl Triangle {
@@ -44,7 +47,27 @@ impl Triangle {
         let inormal = (v3 - v1).cross(v2 - v1).normalize();
         let (dpdu, dpdv) = inormal.coordinate_system_from_unit();
 
-        Self { v1, v2, v3, center, normal, dpdu, dpdv }
+        Self { v1, v2, v3, center, normal, dpdu, dpdv, normals: None }
+    }
+
+    // Creates a triangle with per-vertex normals which are interpolated for shading.
+    // The geometric normal is flipped if necessary to lie on the side of the vertex normals.
+    pub fn with_normals(v1: Point3, v2: Point3, v3: Point3, normals: [Vec3; 3]) -> Self {
+        let mut triangle = Self::new(v1, v2, v3);
+
+        if (normals[0] + normals[1] + normals[2]).dot(triangle.normal) < 0.0 {
+            triangle.normal = -triangle.normal;
+            let (dpdu, dpdv) = (-triangle.normal).coordinate_system_from_unit();
+            triangle.dpdu = dpdu;
+            triangle.dpdv = dpdv;
+        }
+
+        triangle.normals = Some(normals);
+        triangle
+    }
+
+    pub fn vertices(&self) -> [Point3; 3] {
+        [self.v1, self.v2, self.v3]
     }
 
     fn local_to_world(&self, vec: Vec3<Local>) -> Vec3 {
@@ -94,10 +117,24 @@ impl Shape for Triangle {
         }
 
         let hit_point = ray.point_at(distance);
-        let hit_normal = self.normal;
-        let tangeant = self.dpdu;
-        let bitangeant = self.dpdv;
-        let back_face = hit_normal.dot(ray.d()) >= 0.0;
+        let back_face = self.normal.dot(ray.d()) >= 0.0;
+
+        // interpolate the vertex normals if there are any, u and v are the weights of v2 and v3
+        let shading_normal = self.normals.and_then(|n| {
+            let interpolated = (1.0 - u - v) * n[0] + u * n[1] + v * n[2];
+            if interpolated.len_squared() > 0.0 {
+                Some(interpolated.normalize().face_forward(self.normal))
+            } else {
+                None
+            }
+        });
+        let (hit_normal, tangeant, bitangeant) = match shading_normal {
+            Some(n) => {
+                let (tangeant, bitangeant) = (-n).coordinate_system_from_unit();
+                (n, tangeant, bitangeant)
+            }
+            None => (self.normal, self.dpdu, self.dpdv),
+        };
 
         Some((
             Intersection {
@@ -113,7 +150,7 @@ impl Shape for Triangle {
 
     fn sample(&self, hit: &Intersection, sampler: &mut Sampler) -> (Point3, f32) {
         // sample a point on the triangle
-        let (b1, b2, b3) = (sampler.gen_0_1(), sampler.gen_0_1(), sampler.gen_0_1());
+        let (b1, b2, b3) = sampling::uniform_triangle(sampler.gen_0_1(), sampler.gen_0_1());
         let triangle_point = (b1 * self.v1.to_vec() + b2 * self.v2.to_vec() + b3 * self.v3.to_vec()).to_point();
 
         // offset so that the point of intersection lies on the right side of the triangle plane
@@ -128,7 +165,7 @@ impl Shape for Triangle {
         if let Some((light_hit, _)) = self.intersect(&ray) {
             // ray hits surface
             let pdf = light_hit.point.distance_squared(hit_point) / (
-                light_hit.normal.dot((light_hit.point - hit_point).normalize()) * self.surface_area()
+                light_hit.normal.dot((light_hit.point - hit_point).normalize()).abs() * self.surface_area()
             );
             return (triangle_point, pdf.max(0.001)); // prevent artifacts from tiny pdf
         } else {
@@ -150,7 +187,7 @@ impl Shape for Triangle {
         if let Some((light_hit, _)) = self.intersect(&ray) {
             // ray hits surface
             let pdf = light_hit.point.distance_squared(hit_point) / (
-                light_hit.normal.dot((light_hit.point - hit_point).normalize()) * self.surface_area()
+                light_hit.normal.dot((light_hit.point - hit_point).normalize()).abs() * self.surface_area()
             );
             return pdf.max(0.001); // prevent artifacts from tiny pdf
         } else {
@@ -158,4 +195,143 @@ impl Shape for Triangle {
             return 0.0;
         }
     }
+
+    fn bounds(&self) -> Bounds3 {
+        Bounds3::from_point(self.v1).union_point(self.v2).union_point(self.v3)
+    }
+}
+
+// Four triangles in structure of arrays layout for intersecting them with a ray at once.
+// The arithmetic mirrors `Triangle::intersect` operation for operation, so both report
+// bitwise identical distances.
+#[derive(Clone, Copy)]
+pub struct Triangle4 {
+    v1: [__m128; 3],
+    // v3 - v1 and v2 - v1
+    a: [__m128; 3],
+    b: [__m128; 3],
+}
+
+impl Triangle4 {
+    // Lanes without a triangle get zero edges, which the determinant test always rejects
+    pub fn new(triangles: &[&Triangle]) -> Self {
+        debug_assert!(triangles.len() <= 4);
+
+        let mut v1 = [[0.0f32; 4]; 3];
+        let mut a = [[0.0f32; 4]; 3];
+        let mut b = [[0.0f32; 4]; 3];
+        for (lane, triangle) in triangles.iter().enumerate() {
+            let (ta, tb) = (triangle.v3 - triangle.v1, triangle.v2 - triangle.v1);
+            let components = [
+                (triangle.v1.x(), ta.x(), tb.x()),
+                (triangle.v1.y(), ta.y(), tb.y()),
+                (triangle.v1.z(), ta.z(), tb.z()),
+            ];
+            for (axis, &(v, ea, eb)) in components.iter().enumerate() {
+                v1[axis][lane] = v;
+                a[axis][lane] = ea;
+                b[axis][lane] = eb;
+            }
+        }
+
+        let load = |x: [[f32; 4]; 3]| unsafe {
+            [_mm_loadu_ps(x[0].as_ptr()), _mm_loadu_ps(x[1].as_ptr()), _mm_loadu_ps(x[2].as_ptr())]
+        };
+        Self { v1: load(v1), a: load(a), b: load(b) }
+    }
+
+    // Möller-Trumbore for all four lanes. Returns the distance of each hit below t_max,
+    // infinity for lanes that are missed.
+    pub fn intersect(&self, ray: &WideRay, t_max: f32) -> [f32; 4] {
+        unsafe {
+            let dot = |x: [__m128; 3], y: [__m128; 3]| {
+                _mm_add_ps(
+                    _mm_add_ps(_mm_mul_ps(x[0], y[0]), _mm_mul_ps(x[1], y[1])),
+                    _mm_mul_ps(x[2], y[2]),
+                )
+            };
+            let cross = |x: [__m128; 3], y: [__m128; 3]| {
+                [
+                    _mm_sub_ps(_mm_mul_ps(x[1], y[2]), _mm_mul_ps(x[2], y[1])),
+                    _mm_sub_ps(_mm_mul_ps(x[2], y[0]), _mm_mul_ps(x[0], y[2])),
+                    _mm_sub_ps(_mm_mul_ps(x[0], y[1]), _mm_mul_ps(x[1], y[0])),
+                ]
+            };
+
+            let zero = _mm_setzero_ps();
+            let one = _mm_set1_ps(1.0);
+            let epsilon = _mm_set1_ps(EPSILON);
+
+            let p = cross(ray.d, self.a);
+            let det = dot(self.b, p);
+            // |det| >= EPSILON, clearing the sign bit
+            let abs_det = _mm_andnot_ps(_mm_set1_ps(-0.0), det);
+            let mut valid = _mm_cmpge_ps(abs_det, epsilon);
+
+            let t = [
+                _mm_sub_ps(ray.o[0], self.v1[0]),
+                _mm_sub_ps(ray.o[1], self.v1[1]),
+                _mm_sub_ps(ray.o[2], self.v1[2]),
+            ];
+            let q = cross(t, self.b);
+
+            let idet = _mm_div_ps(one, det);
+            let u = _mm_mul_ps(dot(t, p), idet);
+            let v = _mm_mul_ps(dot(ray.d, q), idet);
+            valid = _mm_and_ps(valid, _mm_cmpge_ps(u, zero));
+            valid = _mm_and_ps(valid, _mm_cmple_ps(u, one));
+            valid = _mm_and_ps(valid, _mm_cmpge_ps(v, zero));
+            valid = _mm_and_ps(valid, _mm_cmple_ps(_mm_add_ps(u, v), one));
+
+            let distance = _mm_mul_ps(dot(self.a, q), idet);
+            valid = _mm_and_ps(valid, _mm_cmpge_ps(distance, epsilon));
+            valid = _mm_and_ps(valid, _mm_cmplt_ps(distance, _mm_set1_ps(t_max)));
+
+            let distance = _mm_or_ps(
+                _mm_and_ps(valid, distance),
+                _mm_andnot_ps(valid, _mm_set1_ps(f32::INFINITY)),
+            );
+            let mut result = [0.0; 4];
+            _mm_storeu_ps(result.as_mut_ptr(), distance);
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triangle4_matches_triangle() {
+        let triangles = [
+            Triangle::new(Point3::new(-1.0, -1.0, 2.0), Point3::new(1.0, -1.0, 2.0), Point3::new(0.0, 1.0, 2.0)),
+            Triangle::new(Point3::new(-1.0, -1.0, 3.0), Point3::new(0.0, 1.0, 3.0), Point3::new(1.0, -1.0, 3.0)),
+            Triangle::new(Point3::new(0.5, 0.0, 1.0), Point3::new(2.0, 0.0, 1.0), Point3::new(0.5, 1.5, 1.0)),
+        ];
+        let wide = Triangle4::new(&triangles.iter().collect::<Vec<_>>());
+
+        // the distances are bitwise those of the scalar test, infinite for the empty lane
+        let mut state = 1u32;
+        let mut random = || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        for _ in 0..1000 {
+            let ray = Ray::new(Point3::new(random(), random(), 0.0), Vec3::new(random(), random(), 1.0));
+            let distances = wide.intersect(&WideRay::new(&ray), f32::INFINITY);
+            for (triangle, &distance) in triangles.iter().zip(&distances) {
+                let expected = triangle.intersect(&ray).map_or(f32::INFINITY, |(_, t)| t);
+                assert_eq!(distance.to_bits(), expected.to_bits());
+            }
+            assert_eq!(distances[3], f32::INFINITY);
+        }
+
+        // both faces are hit, hits beyond t_max and behind the origin are not
+        let ray = WideRay::new(&Ray::new(Point3::new(0.0, -0.5, 0.0), Vec3::new(0.0, 0.0, 1.0)));
+        assert_eq!(wide.intersect(&ray, f32::INFINITY)[..2], [2.0, 3.0]);
+        assert_eq!(wide.intersect(&ray, 2.5)[..2], [2.0, f32::INFINITY]);
+        let ray = WideRay::new(&Ray::new(Point3::new(0.0, -0.5, 2.5), Vec3::new(0.0, 0.0, 1.0)));
+        assert_eq!(wide.intersect(&ray, f32::INFINITY)[..2], [f32::INFINITY, 0.5]);
+    }
 }
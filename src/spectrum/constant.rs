@@ -1,4 +1,7 @@
-use crate::spectrum::{SampleableSpectrum, SpectralSample, Wavelength};
+use crate::{
+    math::Lanes,
+    spectrum::{SampleableSpectrum, SpectralSample, Wavelength},
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct ConstantSpectrum {
@@ -17,7 +20,7 @@ impl SampleableSpectrum for ConstantSpectrum {
         self.value
     }
 
-    fn evaluate(&self, _: Wavelength) -> SpectralSample {
+    fn evaluate<L: Lanes>(&self, _: Wavelength<L>) -> SpectralSample<L> {
         SpectralSample::splat(self.value)
     }
 }
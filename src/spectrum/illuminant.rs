@@ -0,0 +1,376 @@
+// Emission spectra of physical light sources: Planckian blackbodies and the CIE standard
+// illuminants. The CIE illuminants are relative, normalized to 100 at 560nm; scale them to
+// the radiance of the light.
+#![allow(clippy::excessive_precision, clippy::approx_constant)]
+
+use crate::spectrum::{
+    tabulated::{Extrapolation, TabulatedSpectrum},
+    SampleableSpectrum,
+};
+
+// Planck's constant, the speed of light and Boltzmann's constant
+const H: f64 = 6.62607015e-34;
+const C: f64 = 299792458.0;
+const K_B: f64 = 1.380649e-23;
+// Wien's displacement constant in m K
+const WIEN: f64 = 2.897771955e-3;
+
+// Spectral radiance of a blackbody at `temperature` kelvin, either absolute in
+// W / (sr m^2 nm) or normalized so that its peak is one.
+#[derive(Debug, Clone, Copy)]
+pub struct BlackbodySpectrum {
+    temperature: f64,
+    scale: f64,
+}
+
+impl BlackbodySpectrum {
+    pub fn new(temperature: f32) -> Self {
+        assert!(temperature > 0.0, "blackbody temperature must be positive");
+        Self {
+            temperature: temperature as f64,
+            scale: 1.0,
+        }
+    }
+
+    pub fn normalized(temperature: f32) -> Self {
+        let spectrum = Self::new(temperature);
+        let peak = planck(WIEN / spectrum.temperature * 1e9, spectrum.temperature);
+        Self {
+            scale: 1.0 / peak,
+            ..spectrum
+        }
+    }
+
+    pub fn scaled(self, scale: f32) -> Self {
+        Self {
+            scale: self.scale * scale as f64,
+            ..self
+        }
+    }
+}
+
+impl SampleableSpectrum for BlackbodySpectrum {
+    fn evaluate_single(&self, lambda: f32) -> f32 {
+        (planck(lambda as f64, self.temperature) * self.scale) as f32
+    }
+}
+
+// Planck's law for a wavelength in nm, per nm
+fn planck(lambda_nm: f64, temperature: f64) -> f64 {
+    let lambda = lambda_nm * 1e-9;
+    let radiance = 2.0 * H * C * C / (lambda.powi(5) * ((H * C / (lambda * K_B * temperature)).exp() - 1.0));
+    radiance * 1e-9
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CieIlluminant {
+    // incandescent light, a blackbody at 2856K
+    A,
+    // daylight of the given correlated color temperature in kelvin
+    D(f32),
+    // fluorescent lamps F1 to F12
+    F(usize),
+}
+
+// Scene file names of the illuminants
+pub const CIE_ILLUMINANTS: [&str; 17] = [
+    "a", "d50", "d55", "d65", "d75", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10",
+    "f11", "f12",
+];
+
+impl CieIlluminant {
+    pub fn from_name(name: &str) -> Option<Self> {
+        // the nominal temperatures of the standard daylight illuminants predate a revision of
+        // the second radiation constant, from 1.4380e-2 to 1.4388e-2 m K
+        let daylight = |nominal: f32| CieIlluminant::D(nominal * 1.4388 / 1.4380);
+
+        Some(match name {
+            "a" => CieIlluminant::A,
+            "d50" => daylight(5000.0),
+            "d55" => daylight(5500.0),
+            "d65" => daylight(6500.0),
+            "d75" => daylight(7500.0),
+            _ => {
+                let n = name.strip_prefix('f')?.parse::<usize>().ok()?;
+                if !(1..=12).contains(&n) {
+                    return None;
+                }
+                CieIlluminant::F(n)
+            }
+        })
+    }
+
+    pub fn spectrum(self) -> TabulatedSpectrum {
+        match self {
+            CieIlluminant::A => {
+                // normalized to 100 at 560nm with the constants of its definition
+                let a = |lambda: f64| {
+                    100.0 * (560.0 / lambda).powi(5) * ((1.435e7f64 / (2848.0 * 560.0)).exp() - 1.0)
+                        / ((1.435e7 / (2848.0 * lambda)).exp() - 1.0)
+                };
+                let samples = (360..=830)
+                    .map(|lambda| (lambda as f32, a(lambda as f64) as f32))
+                    .collect::<Vec<_>>();
+                TabulatedSpectrum::new(&samples, Extrapolation::Clamp)
+            }
+            CieIlluminant::D(temperature) => {
+                assert!(
+                    (4000.0..=25000.0).contains(&temperature),
+                    "CIE daylight is defined from 4000K to 25000K"
+                );
+                let (m1, m2) = daylight_coefficients(temperature as f64);
+                let samples = (0..CIE_D_S0.len())
+                    .map(|i| {
+                        let lambda = 360.0 + 10.0 * i as f32;
+                        (lambda, CIE_D_S0[i] + m1 * CIE_D_S1[i] + m2 * CIE_D_S2[i])
+                    })
+                    .collect::<Vec<_>>();
+                TabulatedSpectrum::new(&samples, Extrapolation::Clamp)
+            }
+            CieIlluminant::F(n) => {
+                let samples = CIE_F[n - 1]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &value)| (380.0 + 5.0 * i as f32, value))
+                    .collect::<Vec<_>>();
+                TabulatedSpectrum::new(&samples, Extrapolation::Zero)
+            }
+        }
+    }
+}
+
+// Weights of the second and third basis function of the daylight at a correlated color
+// temperature, rounded to three decimals like the standard tables
+fn daylight_coefficients(temperature: f64) -> (f32, f32) {
+    let t = temperature;
+    let x = if t <= 7000.0 {
+        0.244063 + 0.09911e3 / t + 2.9678e6 / (t * t) - 4.6070e9 / (t * t * t)
+    } else {
+        0.237040 + 0.24748e3 / t + 1.9018e6 / (t * t) - 2.0064e9 / (t * t * t)
+    };
+    let y = -3.0 * x * x + 2.870 * x - 0.275;
+
+    let m = 0.0241 + 0.2562 * x - 0.7341 * y;
+    let m1 = (-1.3515 - 1.7703 * x + 5.9114 * y) / m;
+    let m2 = (0.0300 - 31.4424 * x + 30.0717 * y) / m;
+    let round = |value: f64| ((value * 1000.0).round() / 1000.0) as f32;
+    (round(m1), round(m2))
+}
+
+// Basis functions of CIE daylight from 360nm to 830nm in 10nm steps
+const CIE_D_S0: [f32; 48] = [
+    61.5, 68.8, 63.4, 65.8, 94.8, 104.8, 105.9, 96.8, 113.9, 125.6,
+    125.5, 121.3, 121.3, 113.5, 113.1, 110.8, 106.5, 108.8, 105.3, 104.4,
+    100.0, 96.0, 95.1, 89.1, 90.5, 90.3, 88.4, 84.0, 85.1, 81.9,
+    82.6, 84.9, 81.3, 71.9, 74.3, 76.4, 63.3, 71.7, 77.0, 65.2,
+    47.7, 68.6, 65.0, 66.0, 61.0, 53.3, 58.9, 61.9,
+];
+
+const CIE_D_S1: [f32; 48] = [
+    38.0, 42.4, 38.5, 35.0, 43.4, 46.3, 43.9, 37.1, 36.7, 35.9,
+    32.6, 27.9, 24.3, 20.1, 16.2, 13.2, 8.6, 6.1, 4.2, 1.9,
+    0.0, -1.6, -3.5, -3.5, -5.8, -7.2, -8.6, -9.5, -10.9, -10.7,
+    -12.0, -14.0, -13.6, -12.0, -13.3, -12.9, -10.6, -11.6, -12.2, -10.2,
+    -7.8, -11.2, -10.4, -10.6, -9.7, -8.3, -9.3, -9.8,
+];
+
+const CIE_D_S2: [f32; 48] = [
+    5.3, 6.1, 3.0, 1.2, -1.1, -0.5, -0.7, -1.2, -2.6, -2.9,
+    -2.8, -2.6, -2.6, -1.8, -1.5, -1.3, -1.2, -1.0, -0.5, -0.3,
+    0.0, 0.2, 0.5, 2.1, 3.2, 4.1, 4.7, 5.1, 6.7, 7.3,
+    8.6, 9.8, 10.2, 8.3, 9.6, 8.5, 7.0, 7.6, 8.0, 6.7,
+    5.2, 7.4, 6.8, 7.0, 6.4, 5.5, 6.1, 6.5,
+];
+
+// Relative spectral power of the fluorescent illuminants F1 to F12 from 380nm to 780nm in 5nm steps
+const CIE_F: [[f32; 81]; 12] = [
+    [
+        1.87, 2.36, 2.94, 3.47, 5.17, 19.49, 6.13, 6.24, 7.01, 7.79,
+        8.56, 43.67, 16.94, 10.72, 11.35, 11.89, 12.37, 12.75, 13.0, 13.15,
+        13.23, 13.17, 13.13, 12.85, 12.52, 12.2, 11.83, 11.5, 11.22, 11.05,
+        11.03, 11.18, 11.53, 27.74, 17.05, 13.55, 14.33, 15.01, 15.52, 18.29,
+        19.55, 15.48, 14.91, 14.15, 13.22, 12.19, 11.12, 10.03, 8.95, 7.96,
+        7.02, 6.2, 5.42, 4.73, 4.15, 3.64, 3.2, 2.81, 2.47, 2.18,
+        1.93, 1.72, 1.67, 1.43, 1.29, 1.19, 1.08, 0.96, 0.88, 0.81,
+        0.77, 0.75, 0.73, 0.68, 0.69, 0.64, 0.68, 0.69, 0.61, 0.52,
+        0.43,
+    ],
+    [
+        1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62,
+        5.06, 34.98, 11.81, 6.27, 6.63, 6.93, 7.19, 7.4, 7.54, 7.62,
+        7.65, 7.62, 7.62, 7.45, 7.28, 7.15, 7.05, 7.04, 7.16, 7.47,
+        8.04, 8.88, 10.01, 24.88, 16.64, 14.59, 16.16, 17.56, 18.62, 21.47,
+        22.79, 19.29, 18.66, 17.73, 16.54, 15.21, 13.8, 12.36, 10.95, 9.65,
+        8.4, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96, 2.55, 2.19,
+        1.89, 1.64, 1.53, 1.27, 1.1, 0.99, 0.88, 0.76, 0.68, 0.61,
+        0.56, 0.54, 0.51, 0.47, 0.47, 0.43, 0.46, 0.47, 0.4, 0.33,
+        0.27,
+    ],
+    [
+        0.82, 1.02, 1.26, 1.44, 2.57, 14.36, 2.7, 2.45, 2.73, 3.0,
+        3.28, 31.85, 9.47, 4.02, 4.25, 4.44, 4.59, 4.72, 4.8, 4.86,
+        4.87, 4.85, 4.88, 4.77, 4.67, 4.62, 4.62, 4.73, 4.99, 5.48,
+        6.25, 7.34, 8.78, 23.82, 16.14, 14.59, 16.63, 18.49, 19.95, 23.11,
+        24.69, 21.41, 20.85, 19.93, 18.67, 17.22, 15.65, 14.04, 12.45, 10.95,
+        9.51, 8.27, 7.11, 6.09, 5.22, 4.45, 3.8, 3.23, 2.75, 2.33,
+        1.99, 1.7, 1.55, 1.27, 1.09, 0.96, 0.83, 0.71, 0.62, 0.54,
+        0.49, 0.46, 0.43, 0.39, 0.39, 0.35, 0.38, 0.39, 0.33, 0.28,
+        0.21,
+    ],
+    [
+        0.57, 0.7, 0.87, 0.98, 2.01, 13.75, 1.95, 1.59, 1.76, 1.93,
+        2.1, 30.28, 8.03, 2.55, 2.7, 2.82, 2.91, 2.99, 3.04, 3.08,
+        3.09, 3.09, 3.14, 3.06, 3.0, 2.98, 3.01, 3.14, 3.41, 3.9,
+        4.69, 5.81, 7.32, 22.59, 15.11, 13.88, 16.33, 18.68, 20.64, 24.28,
+        26.26, 23.28, 22.94, 22.14, 20.91, 19.43, 17.74, 16.0, 14.42, 12.56,
+        10.93, 9.52, 8.18, 7.01, 6.0, 5.11, 4.36, 3.69, 3.13, 2.64,
+        2.24, 1.91, 1.7, 1.39, 1.18, 1.03, 0.88, 0.74, 0.64, 0.54,
+        0.49, 0.46, 0.42, 0.37, 0.37, 0.33, 0.35, 0.36, 0.31, 0.26,
+        0.19,
+    ],
+    [
+        1.87, 2.35, 2.92, 3.45, 5.1, 18.91, 6.0, 6.11, 6.85, 7.58,
+        8.31, 40.76, 16.06, 10.32, 10.91, 11.4, 11.83, 12.17, 12.4, 12.54,
+        12.58, 12.52, 12.47, 12.2, 11.89, 11.61, 11.33, 11.1, 10.96, 10.97,
+        11.16, 11.54, 12.12, 27.78, 17.73, 14.47, 15.2, 15.77, 16.1, 18.54,
+        19.5, 15.39, 14.64, 13.72, 12.69, 11.57, 10.45, 9.35, 8.29, 7.32,
+        6.41, 5.63, 4.9, 4.26, 3.72, 3.25, 2.83, 2.49, 2.19, 1.93,
+        1.71, 1.52, 1.43, 1.26, 1.13, 1.05, 0.96, 0.85, 0.78, 0.72,
+        0.68, 0.67, 0.65, 0.61, 0.62, 0.59, 0.62, 0.64, 0.55, 0.47,
+        0.4,
+    ],
+    [
+        1.05, 1.31, 1.63, 1.9, 3.11, 14.8, 3.43, 3.3, 3.68, 4.07,
+        4.45, 32.61, 10.74, 5.48, 5.78, 6.03, 6.25, 6.41, 6.52, 6.58,
+        6.59, 6.56, 6.56, 6.42, 6.28, 6.2, 6.19, 6.3, 6.6, 7.12,
+        7.94, 9.07, 10.49, 25.22, 17.46, 15.63, 17.22, 18.53, 19.43, 21.97,
+        23.01, 19.41, 18.56, 17.42, 16.09, 14.64, 13.15, 11.68, 10.25, 8.96,
+        7.74, 6.69, 5.71, 4.87, 4.16, 3.55, 3.02, 2.57, 2.2, 1.87,
+        1.6, 1.37, 1.29, 1.08, 0.88, 0.78, 0.68, 0.56, 0.48, 0.42,
+        0.38, 0.35, 0.33, 0.3, 0.29, 0.27, 0.29, 0.29, 0.25, 0.2,
+        0.16,
+    ],
+    [
+        2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41,
+        9.15, 44.14, 17.52, 11.35, 12.0, 12.58, 13.08, 13.45, 13.71, 13.88,
+        13.95, 13.93, 13.82, 13.64, 13.43, 13.25, 13.08, 12.93, 12.78, 12.6,
+        12.44, 12.33, 12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46,
+        16.75, 12.83, 12.67, 12.45, 12.19, 11.89, 11.6, 11.35, 11.12, 10.95,
+        10.76, 10.42, 10.11, 10.04, 10.02, 10.11, 9.87, 8.65, 7.27, 6.44,
+        5.83, 5.41, 5.04, 4.57, 4.12, 3.77, 3.46, 3.08, 2.73, 2.47,
+        2.25, 2.06, 1.9, 1.75, 1.62, 1.54, 1.45, 1.32, 1.17, 0.99,
+        0.81,
+    ],
+    [
+        1.21, 1.5, 1.81, 2.13, 3.17, 13.08, 3.83, 3.45, 3.86, 4.42,
+        5.09, 34.1, 12.42, 7.68, 8.6, 9.46, 10.24, 10.84, 11.33, 11.71,
+        11.98, 12.17, 12.28, 12.32, 12.35, 12.44, 12.55, 12.68, 12.77, 12.72,
+        12.6, 12.43, 12.22, 28.96, 16.51, 11.79, 11.76, 11.77, 11.84, 14.61,
+        16.11, 12.34, 12.53, 12.72, 12.92, 13.12, 13.34, 13.61, 13.87, 14.07,
+        14.2, 14.16, 14.13, 14.34, 14.5, 14.46, 14.0, 12.58, 10.99, 9.98,
+        9.22, 8.62, 8.07, 7.39, 6.71, 6.16, 5.63, 5.03, 4.46, 4.02,
+        3.66, 3.36, 3.09, 2.85, 2.65, 2.51, 2.37, 2.15, 1.89, 1.61,
+        1.32,
+    ],
+    [
+        0.9, 1.12, 1.36, 1.6, 2.59, 12.8, 3.05, 2.56, 2.86, 3.3,
+        3.82, 32.62, 10.77, 5.84, 6.57, 7.25, 7.86, 8.35, 8.75, 9.06,
+        9.31, 9.48, 9.61, 9.68, 9.74, 9.88, 10.04, 10.26, 10.48, 10.63,
+        10.78, 10.96, 11.18, 27.71, 16.29, 12.28, 12.74, 13.21, 13.65, 16.57,
+        18.14, 14.55, 14.65, 14.66, 14.61, 14.5, 14.39, 14.4, 14.47, 14.62,
+        14.72, 14.55, 14.4, 14.58, 14.88, 15.51, 15.47, 13.2, 10.57, 9.18,
+        8.25, 7.57, 7.03, 6.35, 5.72, 5.25, 4.8, 4.29, 3.8, 3.43,
+        3.12, 2.86, 2.64, 2.43, 2.26, 2.14, 2.02, 1.83, 1.61, 1.38,
+        1.12,
+    ],
+    [
+        1.11, 0.63, 0.62, 0.57, 1.48, 12.16, 2.12, 2.7, 3.74, 5.14,
+        6.75, 34.39, 14.86, 10.4, 10.76, 10.67, 10.11, 9.27, 8.29, 7.29,
+        7.91, 16.64, 16.73, 10.44, 5.94, 3.34, 2.35, 1.88, 1.59, 1.47,
+        1.8, 5.71, 40.98, 73.69, 33.61, 8.24, 3.38, 2.47, 2.14, 4.86,
+        11.45, 14.79, 12.16, 8.97, 6.52, 8.31, 44.12, 34.55, 12.09, 12.15,
+        10.52, 4.43, 1.95, 2.19, 3.19, 2.77, 2.29, 2.0, 1.52, 1.35,
+        1.47, 1.79, 1.74, 1.02, 1.14, 3.32, 4.49, 2.05, 0.49, 0.24,
+        0.21, 0.21, 0.24, 0.24, 0.21, 0.17, 0.21, 0.22, 0.17, 0.12,
+        0.09,
+    ],
+    [
+        0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33,
+        4.49, 33.94, 12.13, 6.95, 7.19, 7.12, 6.72, 6.13, 5.46, 4.79,
+        5.66, 14.29, 14.96, 8.97, 4.72, 2.33, 1.47, 1.1, 0.89, 0.83,
+        1.18, 4.9, 39.59, 72.84, 32.61, 7.52, 2.83, 1.96, 1.67, 4.43,
+        11.28, 14.76, 12.73, 9.74, 7.33, 9.72, 55.27, 42.58, 13.18, 13.16,
+        12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14, 1.54, 1.33,
+        1.46, 1.94, 2.0, 1.2, 1.35, 4.1, 5.58, 2.51, 0.57, 0.27,
+        0.23, 0.21, 0.24, 0.24, 0.2, 0.24, 0.32, 0.26, 0.16, 0.12,
+        0.09,
+    ],
+    [
+        0.96, 0.64, 0.4, 0.33, 1.19, 12.48, 1.12, 0.94, 1.08, 1.37,
+        1.78, 29.05, 7.9, 2.65, 2.71, 2.65, 2.49, 2.33, 2.1, 1.91,
+        3.01, 10.83, 11.88, 6.88, 3.43, 1.49, 0.92, 0.71, 0.6, 0.63,
+        1.1, 4.56, 34.4, 65.4, 29.48, 7.16, 3.08, 2.47, 2.27, 5.09,
+        11.96, 15.32, 14.27, 11.86, 9.28, 12.31, 68.53, 53.02, 14.67, 14.38,
+        14.71, 6.46, 2.57, 2.75, 4.18, 3.44, 2.81, 2.42, 1.64, 1.36,
+        1.49, 2.14, 2.34, 1.42, 1.61, 5.04, 6.98, 3.19, 0.71, 0.3,
+        0.26, 0.23, 0.28, 0.28, 0.21, 0.17, 0.21, 0.19, 0.15, 0.1,
+        0.05,
+    ],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color;
+
+    #[test]
+    fn test_blackbody() {
+        // the peak of a normalized blackbody is one, at Wien's wavelength
+        let spectrum = BlackbodySpectrum::normalized(5000.0);
+        let peak = (WIEN / 5000.0 * 1e9) as f32;
+        assert!((spectrum.evaluate_single(peak) - 1.0).abs() < 1e-5);
+        assert!(spectrum.evaluate_single(peak - 20.0) < 1.0);
+        assert!(spectrum.evaluate_single(peak + 20.0) < 1.0);
+
+        // the sun's surface at 5778K, about 2.6e4 W / (sr m^2 nm) at its peak
+        let spectrum = BlackbodySpectrum::new(5778.0);
+        let radiance = spectrum.evaluate_single(501.5);
+        assert!((radiance / 2.63e4 - 1.0).abs() < 0.01, "{}", radiance);
+    }
+
+    #[test]
+    fn test_illuminants() {
+        // D65 matches the published values
+        let d65 = CieIlluminant::from_name("d65").unwrap().spectrum();
+        for &(lambda, value) in &[(400.0, 82.7549), (460.0, 117.812), (560.0, 100.0), (705.0, 72.979)] {
+            assert!((d65.evaluate_single(lambda) - value).abs() < 1e-3, "{}", lambda);
+        }
+
+        // illuminant A is normalized at 560nm
+        let a = CieIlluminant::A.spectrum();
+        assert!((a.evaluate_single(560.0) - 100.0).abs() < 1e-3);
+        assert!((a.evaluate_single(780.0) - 241.675).abs() < 1e-2);
+
+        // the chromaticities of F2 and F11 for the 10 degree observer
+        let xy = |spectrum: &TabulatedSpectrum| {
+            let mut xyz = [0.0; 3];
+            for lambda in 360..=830 {
+                let value = spectrum.evaluate_single(lambda as f32);
+                for (sum, cmf) in xyz.iter_mut().zip(&color::cie_xyz(lambda as f32)) {
+                    *sum += cmf * value;
+                }
+            }
+            let [x, y, z] = xyz;
+            (x / (x + y + z), y / (x + y + z))
+        };
+        let (x, y) = xy(&CieIlluminant::F(2).spectrum());
+        assert!((x - 0.37928).abs() < 1e-3 && (y - 0.36723).abs() < 1e-3, "{} {}", x, y);
+        let (x, y) = xy(&CieIlluminant::F(11).spectrum());
+        assert!((x - 0.38541).abs() < 1e-3 && (y - 0.37123).abs() < 1e-3, "{} {}", x, y);
+
+        assert_eq!(CieIlluminant::from_name("f13"), None);
+        for name in CIE_ILLUMINANTS.iter() {
+            assert!(CieIlluminant::from_name(name).is_some());
+        }
+    }
+}
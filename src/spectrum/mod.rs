@@ -1,21 +1,27 @@
+use crate::math::Lanes;
 use enum_dispatch::enum_dispatch;
 
 pub mod constant;
+pub mod illuminant;
+pub mod rgb2spec;
 pub mod sample;
+pub mod tabulated;
 pub mod upsample;
 pub mod wavelength;
 
 pub use sample::SpectralSample;
-pub use wavelength::Wavelength;
+pub use wavelength::{Wavelength, WavelengthSampling};
 
 pub use constant::ConstantSpectrum;
+pub use illuminant::{BlackbodySpectrum, CieIlluminant};
+pub use tabulated::{Extrapolation, TabulatedSpectrum};
 pub use upsample::{UpsampledHdrSpectrum, UpsampledSpectrum};
 
 #[enum_dispatch]
 pub trait SampleableSpectrum {
     fn evaluate_single(&self, wavelength_nm: f32) -> f32;
 
-    fn evaluate(&self, wavelength: Wavelength) -> SpectralSample {
+    fn evaluate<L: Lanes>(&self, wavelength: Wavelength<L>) -> SpectralSample<L> {
         SpectralSample::from_function(wavelength, |lambda| self.evaluate_single(lambda))
     }
 }
@@ -25,6 +31,8 @@ pub trait SampleableSpectrum {
 pub enum Spectrum {
     UpsampledSpectrum,
     ConstantSpectrum,
+    TabulatedSpectrum,
+    BlackbodySpectrum,
 }
 
 impl Default for Spectrum {
@@ -0,0 +1,352 @@
+// Optimizer for the coefficient tables of the spectral upsampling, following
+// rgb2spec_opt from http://rgl.epfl.ch/publications/Jakob2019Spectral.
+// For every RGB value on a grid, Gauss-Newton finds the three coefficients of the
+// sigmoid-polynomial spectrum whose color under D65 matches it in CIELAB. The color
+// space matrices are adapted to D65, see `ColorSpace`.
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    color::{self, ColorSpace},
+    spectrum::{
+        illuminant::CieIlluminant,
+        upsample::UpsampleTable,
+        wavelength::{LAMBDA_MAX_NM, LAMBDA_MIN_NM},
+        SampleableSpectrum,
+    },
+};
+
+// Resolution used when the table has to be generated at startup
+pub const DEFAULT_RESOLUTION: usize = 64;
+
+// The integrals use Simpson's 3/8 rule over this many samples
+const FINE_SAMPLES: usize = 94 * 3 + 1;
+const EPSILON: f64 = 1e-4;
+const ITERATIONS: usize = 15;
+
+// Precomputed integration weights of a color space
+struct Tables {
+    lambda: [f64; FINE_SAMPLES],
+    // color matching functions in RGB, times the illuminant and quadrature weight
+    rgb: [[f64; FINE_SAMPLES]; 3],
+    rgb_to_xyz: [[f64; 3]; 3],
+    white_point: [f64; 3],
+}
+
+impl Tables {
+    fn new(color_space: ColorSpace) -> Self {
+        let xyz_to_rgb = color_space.xyz_to_rgb();
+        let h = (LAMBDA_MAX_NM - LAMBDA_MIN_NM) as f64 / (FINE_SAMPLES - 1) as f64;
+
+        let d65 = CieIlluminant::from_name("d65").unwrap().spectrum();
+
+        let mut tables = Self {
+            lambda: [0.0; FINE_SAMPLES],
+            rgb: [[0.0; FINE_SAMPLES]; 3],
+            rgb_to_xyz: color_space.rgb_to_xyz(),
+            white_point: [0.0; 3],
+        };
+
+        for i in 0..FINE_SAMPLES {
+            let lambda = LAMBDA_MIN_NM as f64 + i as f64 * h;
+            let xyz = color::cie_xyz(lambda as f32);
+            let illuminant = d65.evaluate_single(lambda as f32) as f64;
+
+            let mut weight = 3.0 / 8.0 * h;
+            if i == 0 || i == FINE_SAMPLES - 1 {
+            } else if (i - 1) % 3 == 2 {
+                weight *= 2.0;
+            } else {
+                weight *= 3.0;
+            }
+
+            tables.lambda[i] = lambda;
+            for k in 0..3 {
+                for j in 0..3 {
+                    tables.rgb[k][i] += xyz_to_rgb[k][j] * xyz[j] as f64 * illuminant * weight;
+                }
+                tables.white_point[k] += xyz[k] as f64 * illuminant * weight;
+            }
+        }
+
+        // normalize the illuminant to a luminance of one
+        let norm = tables.white_point[1];
+        for k in 0..3 {
+            tables.white_point[k] /= norm;
+            for value in tables.rgb[k].iter_mut() {
+                *value /= norm;
+            }
+        }
+
+        tables
+    }
+
+    // Color of the spectrum with the given coefficients, over wavelengths scaled to [0, 1]
+    fn rgb(&self, coefficients: [f64; 3]) -> [f64; 3] {
+        let mut out = [0.0; 3];
+        for i in 0..FINE_SAMPLES {
+            let lambda = (self.lambda[i] - LAMBDA_MIN_NM as f64) / (LAMBDA_MAX_NM - LAMBDA_MIN_NM) as f64;
+            let x = (coefficients[0] * lambda + coefficients[1]) * lambda + coefficients[2];
+            let s = sigmoid(x);
+            for (out, rgb) in out.iter_mut().zip(&self.rgb) {
+                *out += rgb[i] * s;
+            }
+        }
+        out
+    }
+
+    fn cie_lab(&self, rgb: [f64; 3]) -> [f64; 3] {
+        let mut xyz = [0.0; 3];
+        for (xyz, row) in xyz.iter_mut().zip(&self.rgb_to_xyz) {
+            *xyz = rgb.iter().zip(row).map(|(c, m)| c * m).sum();
+        }
+
+        let f = |t: f64| {
+            let delta: f64 = 6.0 / 29.0;
+            if t > delta.powi(3) {
+                t.cbrt()
+            } else {
+                t / (delta * delta * 3.0) + 4.0 / 29.0
+            }
+        };
+
+        let fx = f(xyz[0] / self.white_point[0]);
+        let fy = f(xyz[1] / self.white_point[1]);
+        let fz = f(xyz[2] / self.white_point[2]);
+        [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+    }
+
+    fn residual(&self, coefficients: [f64; 3], target: [f64; 3]) -> [f64; 3] {
+        let out = self.cie_lab(self.rgb(coefficients));
+        let target = self.cie_lab(target);
+        [target[0] - out[0], target[1] - out[1], target[2] - out[2]]
+    }
+
+    // Central differences of the residual, jacobian[j][i] = d residual[j] / d coefficients[i]
+    fn jacobian(&self, coefficients: [f64; 3], target: [f64; 3]) -> [[f64; 3]; 3] {
+        let mut jacobian = [[0.0; 3]; 3];
+        for i in 0..3 {
+            let mut lower = coefficients;
+            lower[i] -= EPSILON;
+            let mut upper = coefficients;
+            upper[i] += EPSILON;
+
+            let r0 = self.residual(lower, target);
+            let r1 = self.residual(upper, target);
+            for j in 0..3 {
+                jacobian[j][i] = (r1[j] - r0[j]) / (2.0 * EPSILON);
+            }
+        }
+        jacobian
+    }
+
+    // Refines the coefficients in place, returns the remaining error
+    fn gauss_newton(&self, target: [f64; 3], coefficients: &mut [f64; 3]) -> f64 {
+        let mut r = 0.0;
+        for _ in 0..ITERATIONS {
+            let residual = self.residual(*coefficients, target);
+            let jacobian = self.jacobian(*coefficients, target);
+            let step = match solve(jacobian, residual) {
+                Some(step) => step,
+                None => break,
+            };
+
+            r = 0.0;
+            for j in 0..3 {
+                coefficients[j] -= step[j];
+                r += residual[j] * residual[j];
+            }
+
+            let max = coefficients[0].max(coefficients[1]).max(coefficients[2]);
+            if max > 200.0 {
+                for c in coefficients.iter_mut() {
+                    *c *= 200.0 / max;
+                }
+            }
+
+            if r < 1e-6 {
+                break;
+            }
+        }
+        r.sqrt()
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    0.5 * x / (1.0 + x * x).sqrt() + 0.5
+}
+
+fn smoothstep(x: f64) -> f64 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+// Solves a * x = b with Gaussian elimination and partial pivoting
+fn solve(mut a: [[f64; 3]; 3], mut b: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3)
+            .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+            .unwrap();
+        // A NaN sorts above every number, so it is picked and treated as singular
+        if a[pivot][col].is_nan() || a[pivot][col].abs() <= 1e-15 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in (col + 1)..3 {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col];
+            for (a, p) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *a -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; 3];
+    for row in (0..3).rev() {
+        let sum: f64 = ((row + 1)..3).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+// Builds the table in the layout `UpsampleTable::read` expects: for each largest component
+// and its scale, a grid over the other two components relative to it. The coefficients are
+// converted from wavelengths scaled to [0, 1] to nanometers.
+pub fn optimize(color_space: ColorSpace, resolution: usize, num_threads: usize) -> UpsampleTable {
+    assert!(resolution >= 3, "upsampling table resolution must be at least 3");
+
+    let tables = Tables::new(color_space);
+    let res = resolution;
+    let scale = (0..res)
+        .map(|k| smoothstep(smoothstep(k as f64 / (res - 1) as f64)) as f32)
+        .collect::<Vec<f32>>();
+
+    // each (largest component, y) row is optimized as a whole by one thread,
+    // walking from a medium brightness up and down so that each solve starts
+    // from its neighbour's result
+    let next_row = AtomicUsize::new(0);
+    let mut rows = std::thread::scope(|s| {
+        let workers = (0..num_threads.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let row = next_row.fetch_add(1, Ordering::Relaxed);
+                        if row >= 3 * res {
+                            break done;
+                        }
+                        done.push((row, optimize_row(&tables, &scale, res, row / res, row % res)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    rows.sort_by_key(|(row, _)| *row);
+
+    let mut coefficients = vec![0.0; 9 * res * res * res];
+    for (row, values) in rows {
+        let (l, j) = (row / res, row % res);
+        for (k, i, c) in values {
+            let idx = ((l * res + k) * res + j) * res + i;
+            coefficients[3 * idx..3 * idx + 3].copy_from_slice(&c);
+        }
+    }
+
+    UpsampleTable::new(coefficients, scale, resolution)
+}
+
+fn optimize_row(tables: &Tables, scale: &[f32], res: usize, l: usize, j: usize) -> Vec<(usize, usize, [f32; 3])> {
+    let mut out = Vec::with_capacity(res * res);
+    let y = j as f64 / (res - 1) as f64;
+    let start = res / 5;
+
+    for i in 0..res {
+        let x = i as f64 / (res - 1) as f64;
+
+        let mut solve_range = |ks: &mut dyn Iterator<Item = usize>| {
+            let mut coefficients = [0.0; 3];
+            for k in ks {
+                let b = scale[k] as f64;
+                let mut rgb = [0.0; 3];
+                rgb[l] = b;
+                rgb[(l + 1) % 3] = x * b;
+                rgb[(l + 2) % 3] = y * b;
+
+                tables.gauss_newton(rgb, &mut coefficients);
+                out.push((k, i, to_nanometers(coefficients)));
+            }
+        };
+
+        solve_range(&mut (start..res));
+        solve_range(&mut (0..=start).rev());
+    }
+
+    out
+}
+
+// Coefficients of the polynomial over wavelengths in [0, 1] rewritten for nanometers
+fn to_nanometers(coefficients: [f64; 3]) -> [f32; 3] {
+    let c0 = LAMBDA_MIN_NM as f64;
+    let c1 = 1.0 / (LAMBDA_MAX_NM - LAMBDA_MIN_NM) as f64;
+    let [a, b, c] = coefficients;
+    [
+        (a * c1 * c1) as f32,
+        (b * c1 - 2.0 * a * c0 * c1 * c1) as f32,
+        (c - b * c0 * c1 + a * (c0 * c1) * (c0 * c1)) as f32,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solve() {
+        let a = [[2.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 3.0, 1.0]];
+        let x = solve(a, [4.0, 3.0, 10.0]).unwrap();
+        for (x, expected) in x.iter().zip(&[1.0, 2.0, 3.0]) {
+            assert!((x - expected).abs() < 1e-12);
+        }
+        assert!(solve([[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]], [0.0; 3]).is_none());
+        assert!(solve([[1.0, 0.0, 0.0], [f64::NAN, 1.0, 0.0], [0.0, 0.0, 1.0]], [1.0; 3]).is_none());
+    }
+
+    #[test]
+    fn test_white_point() {
+        // a constant spectrum of one reproduces white, up to the rounding of the
+        // published matrices
+        let tables = Tables::new(ColorSpace::Srgb);
+        let rgb = tables.rgb([0.0, 0.0, 1e6]);
+        for &c in &rgb {
+            assert!((c - 1.0).abs() < 0.02, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // spectra looked up from a coarse table reproduce the color they were fitted to
+        let tables = Tables::new(ColorSpace::Srgb);
+        let table = optimize(ColorSpace::Srgb, 16, 4);
+
+        for &rgb in &[[0.8, 0.3, 0.1], [0.2, 0.5, 0.9], [0.5, 0.5, 0.5], [0.05, 0.6, 0.3]] {
+            let spectrum = table.get_spectrum(rgb);
+            let mut out = [0.0; 3];
+            for i in 0..FINE_SAMPLES {
+                let s = spectrum.evaluate_single(tables.lambda[i] as f32) as f64;
+                for (out, rgb) in out.iter_mut().zip(&tables.rgb) {
+                    *out += rgb[i] * s;
+                }
+            }
+
+            for j in 0..3 {
+                assert!((out[j] - rgb[j] as f64).abs() < 0.02, "{:?} -> {:?}", rgb, out);
+            }
+        }
+    }
+}
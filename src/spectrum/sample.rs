@@ -1,62 +1,65 @@
-use std::{arch::x86_64::*, mem};
-
-use crate::{color::Xyz, spectrum::Wavelength, math::{Vec4, PdfSet}};
+use crate::{color::Xyz, film, spectrum::Wavelength, math::{Lanes, Vec4, PdfSet}};
 
 #[derive(Copy, Clone)]
-pub struct SpectralSample {
-    pub inner: Vec4,
+pub struct SpectralSample<L: Lanes = Vec4> {
+    pub inner: L,
 }
 
-impl SpectralSample {
+impl SpectralSample<Vec4> {
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
         Self {
             inner: Vec4::new(x, y, z, w),
         }.assert_invariants()
     }
+}
 
+impl<L: Lanes> SpectralSample<L> {
     pub fn splat(xyzw: f32) -> Self {
         Self {
-            inner: Vec4::splat(xyzw),
+            inner: L::splat(xyzw),
         }
         .assert_invariants()
     }
 
-    pub fn hero(self) -> f32 {
-        self.inner.x()
-    }
-
-    pub fn x(self) -> f32 {
-        self.inner.x()
+    // Sample with only the hero wavelength set, for single wavelength sampling
+    pub fn hero_only(hero: f32) -> Self {
+        Self {
+            inner: L::from_fn(|i| if i == 0 { hero } else { 0.0 }),
+        }
+        .assert_invariants()
     }
 
-    pub fn y(self) -> f32 {
-        self.inner.y()
+    pub fn hero(self) -> f32 {
+        self.inner.hero()
     }
 
-    pub fn z(self) -> f32 {
-        self.inner.z()
+    pub fn lane(self, i: usize) -> f32 {
+        self.inner.lane(i)
     }
 
-    pub fn w(self) -> f32 {
-        self.inner.w()
+    // Each lane is divided by the pdf of its wavelength, the integrators weight the
+    // lanes so that their sum estimates the whole spectrum
+    pub fn to_xyz(self, wavelength: Wavelength<L>) -> Xyz {
+        // TODO: Simd
+        let values = self.inner / wavelength.pdfs;
+        (0..L::N)
+            .map(|i| Xyz::from_wavelength(wavelength.lane(i), values.lane(i)))
+            .fold(Xyz::new(0.0, 0.0, 0.0), |sum, xyz| sum + xyz)
+    }
+
+    // Adds each lane, divided by its pdf like in `to_xyz` and times `weight`, to the band of
+    // the spectral film its wavelength falls into
+    pub fn add_to_bands(self, wavelength: Wavelength<L>, weight: f32, bands: &mut [f32]) {
+        let values = self.inner / wavelength.pdfs;
+        for i in 0..L::N {
+            bands[film::band(wavelength.lane(i), bands.len())] += values.lane(i) * weight;
+        }
     }
 
-    pub fn to_xyz(self, wavelength: Wavelength) -> Xyz {
-        // TODO: Simd
-        let a = Xyz::from_wavelength(wavelength.x(), self.x());
-        let b = Xyz::from_wavelength(wavelength.y(), self.y());
-        let c = Xyz::from_wavelength(wavelength.z(), self.z());
-        let d = Xyz::from_wavelength(wavelength.w(), self.w());
-        a + b + c + d
-    }
-
-    pub fn from_function<F: Fn(f32) -> f32>(wavelength: Wavelength, func: F) -> Self {
-        SpectralSample::new(
-            func(wavelength.x()),
-            func(wavelength.y()),
-            func(wavelength.z()),
-            func(wavelength.w()),
-        )
+    pub fn from_function<F: Fn(f32) -> f32>(wavelength: Wavelength<L>, func: F) -> Self {
+        Self {
+            inner: L::from_fn(|i| func(wavelength.lane(i))),
+        }
         .assert_invariants()
     }
 
@@ -64,8 +67,7 @@ impl SpectralSample {
     fn assert_invariants(self) -> Self {
         // Check that self.data >= 0
         debug_assert!(
-            unsafe { _mm_test_all_ones(mem::transmute(_mm_cmpge_ps(self.inner.data, _mm_setzero_ps()))) }
-                == 1,
+            self.inner.is_non_negative(),
             "SpectralSample contains negative or NaN values: {:?}",
             self
         );
@@ -88,38 +90,35 @@ impl SpectralSample {
     }
 }
 
-impl std::fmt::Debug for SpectralSample {
+impl<L: Lanes> std::fmt::Debug for SpectralSample<L> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("SpectralSample")
-            .field("x", &self.x())
-            .field("y", &self.y())
-            .field("z", &self.z())
-            .field("w", &self.w())
+        f.debug_tuple("SpectralSample")
+            .field(&self.inner)
             .finish()
     }
 }
 
-impl std::convert::From<Vec4> for SpectralSample {
-    fn from(inner: Vec4) -> Self {
+impl<L: Lanes> std::convert::From<L> for SpectralSample<L> {
+    fn from(inner: L) -> Self {
         Self { inner }
     }
 }
 
-impl std::ops::Mul<SpectralSample> for f32 {
-    type Output = SpectralSample;
+impl<L: Lanes> std::ops::Mul<SpectralSample<L>> for f32 {
+    type Output = SpectralSample<L>;
 
-    fn mul(self, other: SpectralSample) -> SpectralSample {
+    fn mul(self, other: SpectralSample<L>) -> SpectralSample<L> {
         SpectralSample {
-            inner: self * other.inner,
+            inner: other.inner * self,
         }
         .assert_invariants()
     }
 }
 
-impl std::ops::Mul<SpectralSample> for PdfSet {
-    type Output = SpectralSample;
+impl<L: Lanes> std::ops::Mul<SpectralSample<L>> for PdfSet<L> {
+    type Output = SpectralSample<L>;
 
-    fn mul(self, other: SpectralSample) -> SpectralSample {
+    fn mul(self, other: SpectralSample<L>) -> SpectralSample<L> {
         SpectralSample {
             inner: self.inner * other.inner,
         }
@@ -127,7 +126,7 @@ impl std::ops::Mul<SpectralSample> for PdfSet {
     }
 }
 
-impl std::ops::Mul<f32> for SpectralSample {
+impl<L: Lanes> std::ops::Mul<f32> for SpectralSample<L> {
     type Output = Self;
 
     fn mul(self, other: f32) -> Self {
@@ -138,7 +137,7 @@ impl std::ops::Mul<f32> for SpectralSample {
     }
 }
 
-impl std::ops::Mul<SpectralSample> for SpectralSample {
+impl<L: Lanes> std::ops::Mul<SpectralSample<L>> for SpectralSample<L> {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self {
@@ -149,7 +148,7 @@ impl std::ops::Mul<SpectralSample> for SpectralSample {
     }
 }
 
-impl std::ops::Add<SpectralSample> for SpectralSample {
+impl<L: Lanes> std::ops::Add<SpectralSample<L>> for SpectralSample<L> {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
@@ -160,32 +159,32 @@ impl std::ops::Add<SpectralSample> for SpectralSample {
     }
 }
 
-impl std::ops::AddAssign<SpectralSample> for SpectralSample {
-    fn add_assign(&mut self, other: SpectralSample) {
+impl<L: Lanes> std::ops::AddAssign<SpectralSample<L>> for SpectralSample<L> {
+    fn add_assign(&mut self, other: SpectralSample<L>) {
         self.inner += other.inner;
         self.assert_invariants();
     }
 }
 
-impl std::ops::MulAssign<SpectralSample> for SpectralSample {
-    fn mul_assign(&mut self, other: SpectralSample) {
+impl<L: Lanes> std::ops::MulAssign<SpectralSample<L>> for SpectralSample<L> {
+    fn mul_assign(&mut self, other: SpectralSample<L>) {
         self.inner *= other.inner;
         self.assert_invariants();
     }
 }
 
-impl std::ops::Div<SpectralSample> for f32 {
-    type Output = SpectralSample;
+impl<L: Lanes> std::ops::Div<SpectralSample<L>> for f32 {
+    type Output = SpectralSample<L>;
 
-    fn div(self, other: SpectralSample) -> SpectralSample {
+    fn div(self, other: SpectralSample<L>) -> SpectralSample<L> {
         SpectralSample {
-            inner: self / other.inner
+            inner: L::splat(self) / other.inner
         }
         .assert_invariants()
     }
 }
 
-impl std::ops::Div<f32> for SpectralSample {
+impl<L: Lanes> std::ops::Div<f32> for SpectralSample<L> {
     type Output = Self;
 
     fn div(self, other: f32) -> Self {
@@ -196,10 +195,10 @@ impl std::ops::Div<f32> for SpectralSample {
     }
 }
 
-impl std::ops::Div<PdfSet> for SpectralSample {
+impl<L: Lanes> std::ops::Div<PdfSet<L>> for SpectralSample<L> {
     type Output = Self;
 
-    fn div(self, other: PdfSet) -> Self {
+    fn div(self, other: PdfSet<L>) -> Self {
         Self {
             inner: self.inner / other.inner,
         }
@@ -207,8 +206,8 @@ impl std::ops::Div<PdfSet> for SpectralSample {
     }
 }
 
-impl std::ops::DivAssign<SpectralSample> for SpectralSample {
-    fn div_assign(&mut self, other: SpectralSample) {
+impl<L: Lanes> std::ops::DivAssign<SpectralSample<L>> for SpectralSample<L> {
+    fn div_assign(&mut self, other: SpectralSample<L>) {
         self.inner /= other.inner;
         self.assert_invariants();
     }
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use crate::spectrum::{
+    wavelength::{LAMBDA_MAX_NM, LAMBDA_MIN_NM, LAMBDA_RANGE_NM},
+    SampleableSpectrum,
+};
+
+const GRID_SAMPLES: usize = LAMBDA_RANGE_NM as usize + 1;
+
+// Value of a tabulated spectrum outside of its measured range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Extrapolation {
+    // the value of the nearest sample
+    Clamp,
+    Zero,
+}
+
+// Measured spectrum, linear between its samples. The samples are resampled once onto a 1nm
+// grid over the visible range, so that evaluating it is a single interpolation.
+#[derive(Debug, Clone)]
+pub struct TabulatedSpectrum {
+    values: Arc<[f32]>,
+}
+
+impl TabulatedSpectrum {
+    // `samples` are pairs of a wavelength in nm and a value, with increasing wavelengths
+    pub fn new(samples: &[(f32, f32)], extrapolation: Extrapolation) -> Self {
+        assert!(!samples.is_empty(), "tabulated spectrum without samples");
+        debug_assert!(samples.windows(2).all(|w| w[0].0 < w[1].0));
+
+        let (first, last) = (samples[0], samples[samples.len() - 1]);
+        let values = (0..GRID_SAMPLES)
+            .map(|i| {
+                let lambda = LAMBDA_MIN_NM + i as f32;
+                if lambda < first.0 || lambda > last.0 {
+                    return match extrapolation {
+                        Extrapolation::Clamp if lambda < first.0 => first.1,
+                        Extrapolation::Clamp => last.1,
+                        Extrapolation::Zero => 0.0,
+                    };
+                }
+
+                // first sample at or above lambda, there is one below it unless it is the first
+                let upper = samples.partition_point(|&(l, _)| l < lambda);
+                if upper == 0 {
+                    return first.1;
+                }
+                let (l0, v0) = samples[upper - 1];
+                let (l1, v1) = samples[upper];
+                let t = (lambda - l0) / (l1 - l0);
+                v0 * (1.0 - t) + v1 * t
+            })
+            .collect();
+
+        Self { values }
+    }
+
+    pub fn scaled(&self, scale: f32) -> Self {
+        Self {
+            values: self.values.iter().map(|value| value * scale).collect(),
+        }
+    }
+}
+
+impl SampleableSpectrum for TabulatedSpectrum {
+    fn evaluate_single(&self, lambda: f32) -> f32 {
+        debug_assert!(LAMBDA_MIN_NM <= lambda && lambda <= LAMBDA_MAX_NM);
+
+        let x = lambda - LAMBDA_MIN_NM;
+        let i = (x as usize).min(GRID_SAMPLES - 2);
+        let t = x - i as f32;
+        self.values[i] * (1.0 - t) + self.values[i + 1] * t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tabulated() {
+        let samples = [(400.0, 1.0), (500.0, 3.0), (500.5, 0.0), (700.0, 2.0)];
+        let spectrum = TabulatedSpectrum::new(&samples, Extrapolation::Clamp);
+
+        assert_eq!(spectrum.evaluate_single(400.0), 1.0);
+        assert_eq!(spectrum.evaluate_single(450.0), 2.0);
+        assert!((spectrum.evaluate_single(475.5) - 2.51).abs() < 1e-5);
+        assert!((spectrum.evaluate_single(600.0) - 2.0 * 99.5 / 199.5).abs() < 1e-5);
+        assert_eq!(spectrum.evaluate_single(LAMBDA_MIN_NM), 1.0);
+        assert_eq!(spectrum.evaluate_single(LAMBDA_MAX_NM), 2.0);
+
+        let spectrum = TabulatedSpectrum::new(&samples, Extrapolation::Zero);
+        assert_eq!(spectrum.evaluate_single(399.0), 0.0);
+        assert_eq!(spectrum.evaluate_single(701.0), 0.0);
+        assert_eq!(spectrum.evaluate_single(700.0), 2.0);
+
+        // a single sample is constant
+        let spectrum = TabulatedSpectrum::new(&[(550.0, 0.5)], Extrapolation::Clamp);
+        assert_eq!(spectrum.evaluate_single(LAMBDA_MIN_NM), 0.5);
+        assert_eq!(spectrum.evaluate_single(LAMBDA_MAX_NM), 0.5);
+    }
+}
@@ -1,6 +1,15 @@
 // Implementation of http://rgl.epfl.ch/publications/Jakob2019Spectral
-use crate::spectrum::SampleableSpectrum;
-use std::{convert::TryInto, fs::File, io::Read};
+use crate::{
+    color::ColorSpace,
+    spectrum::{rgb2spec, SampleableSpectrum},
+};
+use std::{
+    convert::TryInto,
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+};
 
 pub struct UpsampleTable {
     coefficients: Vec<f32>,
@@ -118,23 +127,70 @@ impl UpsampleTable {
         UpsampledSpectrum { coefficients, scale }
     }
 
-    pub fn load() -> Self {
-        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/data/srgb.coeff");
+    pub(crate) fn new(coefficients: Vec<f32>, scale: Vec<f32>, resolution: usize) -> Self {
+        debug_assert_eq!(scale.len(), resolution);
+        debug_assert_eq!(coefficients.len(), 9 * resolution.pow(3));
 
-        let mut file = File::open(path).expect("failed to load srgb coefficients");
+        Self {
+            coefficients,
+            scale,
+            resolution,
+        }
+    }
+
+    // Loads the table of the color space from <name>.coeff in the data directory, generating
+    // and saving it first if it is missing
+    pub fn load(color_space: ColorSpace) -> Self {
+        let dir = data_dir();
+        let path = dir.join(format!("{}.coeff", color_space.name()));
+
+        match File::open(&path) {
+            Ok(file) => Self::read(BufReader::new(file)).unwrap_or_else(|err| {
+                panic!("failed to load upscaling coefficients from {}: {}", path.display(), err)
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                eprintln!(
+                    "{} is missing, generating it at resolution {}, this takes a while \
+                     (`iris rgb2spec {} <resolution> {}` creates it ahead of time)",
+                    path.display(),
+                    rgb2spec::DEFAULT_RESOLUTION,
+                    color_space.name(),
+                    path.display()
+                );
+                let table = rgb2spec::optimize(color_space, rgb2spec::DEFAULT_RESOLUTION, num_cpus::get());
+
+                let saved = fs::create_dir_all(&dir)
+                    .and_then(|_| File::create(&path))
+                    .and_then(|file| table.write(BufWriter::new(file)));
+                if let Err(err) = saved {
+                    eprintln!("warning: failed to save {}: {}", path.display(), err);
+                }
+                table
+            }
+            Err(err) => panic!("failed to load upscaling coefficients from {}: {}", path.display(), err),
+        }
+    }
+
+    // Format: "SPEC", the resolution as a little endian u32, then the scales and coefficients
+    // as little endian f32s
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
 
         let mut signature = [0u8; 4];
-        file.read_exact(&mut signature).unwrap();
+        reader.read_exact(&mut signature)?;
         if &signature != b"SPEC" {
-            panic!("sRGB upscaling coefficients file: incorrect header");
+            return Err(invalid("incorrect header"));
         }
 
         let mut resolution = [0u8; 4];
-        file.read_exact(&mut resolution).unwrap();
+        reader.read_exact(&mut resolution)?;
         let resolution = u32::from_le_bytes(resolution) as usize;
+        if resolution < 3 {
+            return Err(invalid("resolution must be at least 3"));
+        }
 
         let mut scale = vec![0; resolution * std::mem::size_of::<f32>()];
-        file.read_exact(&mut scale).unwrap();
+        reader.read_exact(&mut scale)?;
 
         let scale = scale
             .chunks_exact(4)
@@ -142,18 +198,30 @@ impl UpsampleTable {
             .collect::<Vec<f32>>();
 
         let mut coefficients = Vec::new();
-        file.read_to_end(&mut coefficients).unwrap();
+        reader.read_to_end(&mut coefficients)?;
 
         let coefficients = coefficients
             .chunks_exact(4)
             .map(|slice| f32::from_le_bytes(slice[0..4].try_into().unwrap()))
             .collect::<Vec<f32>>();
+        if coefficients.len() != 9 * resolution.pow(3) {
+            return Err(invalid("wrong number of coefficients for the resolution"));
+        }
 
-        Self {
+        Ok(Self {
             coefficients,
             scale,
             resolution,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(b"SPEC")?;
+        writer.write_all(&(self.resolution as u32).to_le_bytes())?;
+        for value in self.scale.iter().chain(&self.coefficients) {
+            writer.write_all(&value.to_le_bytes())?;
         }
+        writer.flush()
     }
 }
 
@@ -177,11 +245,33 @@ fn find_interval(values: &[f32], size: usize, x: f32) -> usize {
     left.min(last_interval)
 }
 
+// The directory of the upsampling tables, $IRIS_DATA or else data/ in the working directory
+// like results/
+fn data_dir() -> PathBuf {
+    env::var_os("IRIS_DATA").map_or_else(|| PathBuf::from("data"), PathBuf::from)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::spectrum::Wavelength;
 
+    #[test]
+    fn test_read_write() {
+        let table = rgb2spec::optimize(ColorSpace::Srgb, 4, 2);
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 4 * (4 + 9 * 4 * 4 * 4));
+
+        let read = UpsampleTable::read(&bytes[..]).unwrap();
+        assert_eq!(read.resolution, 4);
+        assert_eq!(read.scale, table.scale);
+        assert_eq!(read.coefficients, table.coefficients);
+
+        assert!(UpsampleTable::read(&bytes[..bytes.len() - 4]).is_err());
+        assert!(UpsampleTable::read(&b"SPEX"[..]).is_err());
+    }
+
     //#[test]
     //fn test_upsample() {
         //let table = UpsampleTable::load();
@@ -1,27 +1,32 @@
+use std::sync::OnceLock;
+
+use crate::color;
 use crate::sampling::Sampler;
-use crate::math::Vec4;
+use crate::math::{Lanes, PdfSet, Vec4};
 
 pub const LAMBDA_MIN_NM: f32 = 360.0;
 pub const LAMBDA_MAX_NM: f32 = 830.0;
 pub const LAMBDA_RANGE_NM: f32 = LAMBDA_MAX_NM - LAMBDA_MIN_NM;
 
+// Bundle of `L::N` wavelengths, the hero wavelength followed by its companions
+// spaced evenly over the visible range, along with the pdf of drawing each of them
+// as the hero.
 #[derive(Copy, Clone)]
-pub struct Wavelength {
-    pub inner: Vec4,
+pub struct Wavelength<L: Lanes = Vec4> {
+    pub inner: L,
+    pub pdfs: L,
 }
 
-impl Wavelength {
-    pub fn new(hero: f32) -> Self {
-        debug_assert!(LAMBDA_MIN_NM <= hero && hero <= LAMBDA_MAX_NM);
-
-        let y = rotate_n(hero, 1);
-        let z = rotate_n(hero, 2);
-        let w = rotate_n(hero, 3);
+impl<L: Lanes> Wavelength<L> {
+    pub fn new(hero: f32, sampling: WavelengthSampling) -> Self {
+        debug_assert!((LAMBDA_MIN_NM..=LAMBDA_MAX_NM).contains(&hero));
 
         //println!("sampled wavelength set: hero={}, y={}, z={}, w={}", hero, y, z, w);
 
+        let inner = L::from_fn(|n| rotate_n(hero, n, L::N));
         Self {
-            inner: Vec4::new(hero, y, z, w),
+            inner,
+            pdfs: L::from_fn(|n| sampling.pdf(inner.lane(n))),
         }
     }
 
@@ -37,39 +42,32 @@ impl Wavelength {
         }
     }*/
 
-    pub fn sample(sampler: &mut Sampler) -> Self {
+    pub fn sample(sampler: &mut Sampler, sampling: WavelengthSampling) -> Self {
         //Self::new(sampler.gen_golden_ratio() * LAMBDA_RANGE_NM + LAMBDA_MIN_NM)
-        Self::new(sampler.gen_range(LAMBDA_MIN_NM, LAMBDA_MAX_NM))
+        Self::new(sampling.sample(sampler.gen_0_1()), sampling)
         //Self::new_sampled(sampler)
     }
 
-    pub fn pdf(self) -> f32 {
-        1.0 / LAMBDA_RANGE_NM
-    }
-
-    pub fn hero(self) -> f32 {
-        self.inner.x()
+    // Pdfs of the wavelengths, each as if it had been sampled as the hero
+    pub fn pdfs(self) -> PdfSet<L> {
+        PdfSet::from(self.pdfs)
     }
 
-    pub fn x(self) -> f32 {
-        self.inner.x()
+    pub fn hero_pdf(self) -> f32 {
+        self.pdfs.hero()
     }
 
-    pub fn y(self) -> f32 {
-        self.inner.y()
-    }
-
-    pub fn z(self) -> f32 {
-        self.inner.z()
+    pub fn hero(self) -> f32 {
+        self.inner.hero()
     }
 
-    pub fn w(self) -> f32 {
-        self.inner.w()
+    pub fn lane(self, i: usize) -> f32 {
+        self.inner.lane(i)
     }
 }
 
-fn rotate_n(hero: f32, n: usize) -> f32 {
-    let lambda = hero + (LAMBDA_RANGE_NM / 4.0) * (n as f32);
+fn rotate_n(hero: f32, n: usize, count: usize) -> f32 {
+    let lambda = hero + (LAMBDA_RANGE_NM / count as f32) * (n as f32);
 
     // Perform modulo operation (so that lambda is always in range)
     if lambda >= LAMBDA_MAX_NM {
@@ -79,32 +77,160 @@ fn rotate_n(hero: f32, n: usize) -> f32 {
     }
 }
 
-// Importance sample the wavelength with pdf(lambda) = sech^2(0.0072(lambda -
-// 538)) See https://www.researchgate.net/publication/228938842_An_Improved_Technique_for_Full_Spectral_Rendering
+// Distribution the hero wavelength is drawn from, chosen with `--wavelength-sampling`.
 // Even though we stratify the spectrum for HWSS, it's important that the hero
 // wavelength is importance sampled too because we use it for path
 // generation.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WavelengthSampling {
+    #[default]
+    Uniform,
+    // pdf(lambda) ~ sech^2(0.0072(lambda - 538)), see
+    // https://www.researchgate.net/publication/228938842_An_Improved_Technique_for_Full_Spectral_Rendering
+    Sech2,
+    // pdf(lambda) ~ CIE Y matching function, piecewise constant over its 1nm bins
+    CieY,
+}
 
-// TODO: Is the error on these curves reasonable?
-// fn pdf(lambda: f32) -> f32 {
-// if lambda < 380.0 || lambda > 700.0 {
-// 0.0
-//} else {
-// let x1 = lambda;
-// let x2 = lambda * lambda;
-// let x3 = lambda * x2;
-// let x4 = x2 * x2;
-// let x5 = lambda * x4;
-//-8.19329974e-16 
-//-8.19329974e-16 * x5 + 5.58900125e-12 * x4 - 9.63692860e-09 * x3 +
-//-8.19329974e-16   6.92631892e-06 * x2
-//- 2.22283548e-03 * x1
-//+ 2.64835297e-01
-//}
-
-// fn inverse_cdf(unif: f32) -> f32 {
-// let val = 377.92772964 * unif.powi(3) - 562.7179108 * unif.powi(2)
-//+ 495.09783553 * unif
-//+ 384.47036553;
-// val.clamp(LAMBDA_MIN_NM, LAMBDA_MAX_NM)
-//}
+// Command line names of the strategies with a short description, the first is the default
+pub const WAVELENGTH_SAMPLINGS: [(&str, &str); 3] = [
+    ("uniform", "uniform over the whole range"),
+    ("sech2", "visible importance sampling after Radziszewski et al. (2009)"),
+    ("cie-y", "proportional to the CIE Y matching function"),
+];
+
+const SECH2_CENTER_NM: f32 = 538.0;
+const SECH2_SCALE: f32 = 0.0072;
+
+impl WavelengthSampling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "uniform" => WavelengthSampling::Uniform,
+            "sech2" => WavelengthSampling::Sech2,
+            "cie-y" => WavelengthSampling::CieY,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WavelengthSampling::Uniform => "uniform",
+            WavelengthSampling::Sech2 => "sech2",
+            WavelengthSampling::CieY => "cie-y",
+        }
+    }
+
+    // Maps a uniform random number in [0, 1) to a wavelength
+    pub fn sample(self, u: f32) -> f32 {
+        let lambda = match self {
+            WavelengthSampling::Uniform => u * LAMBDA_RANGE_NM + LAMBDA_MIN_NM,
+            WavelengthSampling::Sech2 => {
+                let (a, b) = sech2_bounds();
+                SECH2_CENTER_NM + (a + u * (b - a)).atanh() / SECH2_SCALE
+            }
+            WavelengthSampling::CieY => {
+                let table = cie_y_table();
+                let target = u * table.cdf[CIE_Y_BINS];
+                let bin = table.cdf[1..CIE_Y_BINS].partition_point(|&c| c <= target);
+                LAMBDA_MIN_NM + bin as f32 + (target - table.cdf[bin]) / table.values[bin]
+            }
+        };
+
+        lambda.clamp(LAMBDA_MIN_NM, LAMBDA_MAX_NM)
+    }
+
+    pub fn pdf(self, lambda: f32) -> f32 {
+        match self {
+            WavelengthSampling::Uniform => 1.0 / LAMBDA_RANGE_NM,
+            WavelengthSampling::Sech2 => {
+                let (a, b) = sech2_bounds();
+                let cosh = (SECH2_SCALE * (lambda - SECH2_CENTER_NM)).cosh();
+                SECH2_SCALE / ((b - a) * cosh * cosh)
+            }
+            WavelengthSampling::CieY => {
+                let table = cie_y_table();
+                let bin = ((lambda - LAMBDA_MIN_NM) as usize).min(CIE_Y_BINS - 1);
+                table.values[bin] / table.cdf[CIE_Y_BINS]
+            }
+        }
+    }
+}
+
+// tanh of the scaled range ends, the inverse cdf maps [0, 1) linearly between them
+fn sech2_bounds() -> (f32, f32) {
+    (
+        (SECH2_SCALE * (LAMBDA_MIN_NM - SECH2_CENTER_NM)).tanh(),
+        (SECH2_SCALE * (LAMBDA_MAX_NM - SECH2_CENTER_NM)).tanh(),
+    )
+}
+
+const CIE_Y_BINS: usize = LAMBDA_RANGE_NM as usize;
+
+struct CieYTable {
+    values: [f32; CIE_Y_BINS],
+    // cdf[i] is the integral up to the start of bin i, cdf[CIE_Y_BINS] the total
+    cdf: [f32; CIE_Y_BINS + 1],
+}
+
+fn cie_y_table() -> &'static CieYTable {
+    static TABLE: OnceLock<CieYTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = CieYTable {
+            values: [0.0; CIE_Y_BINS],
+            cdf: [0.0; CIE_Y_BINS + 1],
+        };
+        for bin in 0..CIE_Y_BINS {
+            table.values[bin] = color::cie_y(LAMBDA_MIN_NM + bin as f32);
+            table.cdf[bin + 1] = table.cdf[bin] + table.values[bin];
+        }
+        table
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::VecN;
+
+    #[test]
+    fn test_rotation() {
+        let wavelength = Wavelength::<VecN<8>>::new(800.0, WavelengthSampling::Uniform);
+        assert_eq!(wavelength.hero(), 800.0);
+        for i in 1..8 {
+            let lambda = wavelength.lane(i);
+            assert!((LAMBDA_MIN_NM..LAMBDA_MAX_NM).contains(&lambda));
+        }
+        assert_eq!(wavelength.lane(1), 800.0 + LAMBDA_RANGE_NM / 8.0 - LAMBDA_RANGE_NM);
+
+        // the four wavelength bundle is unchanged
+        let wavelength = Wavelength::<Vec4>::new(400.0, WavelengthSampling::Uniform);
+        assert_eq!(wavelength.lane(2), 400.0 + LAMBDA_RANGE_NM / 2.0);
+    }
+
+    #[test]
+    fn test_sampling() {
+        for &(name, _) in WAVELENGTH_SAMPLINGS.iter() {
+            let sampling = WavelengthSampling::from_name(name).unwrap();
+
+            // the pdf integrates to one
+            let steps = 47_000;
+            let step = LAMBDA_RANGE_NM / steps as f32;
+            let integral: f32 = (0..steps)
+                .map(|i| sampling.pdf(LAMBDA_MIN_NM + (i as f32 + 0.5) * step) * step)
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "{}: {}", name, integral);
+
+            // sampling inverts the cdf, so the density of the samples matches the pdf
+            for i in 1..100 {
+                let u = i as f32 / 100.0;
+                let lambda = sampling.sample(u);
+                assert!((LAMBDA_MIN_NM..=LAMBDA_MAX_NM).contains(&lambda));
+
+                let du = 1e-4;
+                let density = du / (sampling.sample(u + du) - lambda);
+                let pdf = sampling.pdf(lambda);
+                assert!((density - pdf).abs() < 0.05 * pdf, "{} at {}: {} {}", name, lambda, density, pdf);
+            }
+        }
+    }
+}
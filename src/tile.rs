@@ -1,18 +1,45 @@
 use std::cmp::{Ord, Ordering};
 
 use crate::{
-    color::Xyz,
+    aov::FirstHit,
+    color::{ColorSpace, Xyz},
     integrator::Integrator,
-    math::{Point3, Ray, Vec3},
+    math::{Lanes, Point3, Ray, Vec4, VecN},
     sampling::Sampler,
-    spectrum::Wavelength,
+    spectrum::{wavelength::LAMBDA_RANGE_NM, Wavelength},
+    variance::PixelMoments,
     Render,
 };
 
 const MAX_TILE_WIDTH: usize = 64;
 const MAX_TILE_HEIGHT: usize = 64;
-// const SAMPLE_CHUNK_SIZE: usize = 5000;
-const SEED: u32 = 123_456_789;
+// The base samples are taken in passes doubling the samples so far up to this many
+const MAX_PASS_SPP: usize = 64;
+// Of the sample scrambling, renders with different seeds take independent samples
+pub const DEFAULT_SEED: u32 = 123_456_789;
+
+// Filter weighted sum of the samples splatted onto a pixel, the pixel is `xyz / weight`
+#[derive(Debug, Clone, Copy)]
+pub struct PixelSum {
+    pub xyz: Xyz,
+    pub weight: f32,
+}
+
+impl PixelSum {
+    pub const ZERO: PixelSum = PixelSum {
+        xyz: Xyz::new(0.0, 0.0, 0.0),
+        weight: 0.0,
+    };
+
+    // Linear RGB, black while no sample has reached the pixel
+    pub fn to_rgb(self, color_space: ColorSpace) -> (f32, f32, f32) {
+        if self.weight <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let rgb = (self.xyz / self.weight).to_rgb(color_space);
+        (rgb.0.max(0.0), rgb.1.max(0.0), rgb.2.max(0.0))
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct TileData {
@@ -22,17 +49,32 @@ pub struct TileData {
     pub pixel_x: usize,
     pub pixel_y: usize,
     pub distance_from_center: f32,
+    // base samples per pixel still to take
     pub remaining_samples: usize,
-    pub accum_buffer: Vec<Xyz>,
-    pub temp_buffer: Vec<(f32, f32, f32)>,
+    // with adaptive sampling, the pixels that need more samples and the sum of their
+    // relative variances, which orders the tiles
+    pub active_pixels: usize,
+    pub error: f64,
+    // passes rendered so far, with a time limit the tiles behind get theirs first
+    pub passes: usize,
+    // Pixels around the tile reached by the filter of its samples, on each side
+    pub margin: usize,
+    // sums of all passes over the tile and its margin, `width + 2 * margin` wide
+    pub sums: Vec<PixelSum>,
+    // `render.spectral_bands` sums per pixel, empty without a spectral film
+    pub spectral_buffer: Vec<f32>,
+    // one value per pixel for each of `render.aovs`, a sum for the averaged ones
+    pub aov_buffer: Vec<(f32, f32, f32)>,
+    // of the samples taken in each pixel
+    pub moments: Vec<PixelMoments>,
 }
 
 // TODO: This code is very messy and I am not particularly happy with it
 
 impl TileData {
     pub fn new(render: &Render, idx: usize) -> Option<Self> {
-        let tile_width = (render.width / 4).min(MAX_TILE_WIDTH);
-        let tile_height = (render.height / 4).min(MAX_TILE_HEIGHT);
+        let tile_width = (render.width / 4).clamp(1, MAX_TILE_WIDTH);
+        let tile_height = (render.height / 4).clamp(1, MAX_TILE_HEIGHT);
 
         let num_horiz_tiles = (render.width + tile_width - 1) / tile_width;
         let num_vert_tiles = (render.height + tile_height - 1) / tile_height;
@@ -42,16 +84,17 @@ impl TileData {
             return None;
         }
 
-        let this_tile_width = render.width - ((num_horiz_tiles.max(1) - 1) * tile_width);
-        let this_tile_height = render.height - ((num_vert_tiles.max(1) - 1) * tile_height);
-        let this_tile_pixels = this_tile_height * this_tile_width;
-
         let tile_x = idx % num_horiz_tiles;
         let tile_y = idx / num_horiz_tiles;
 
         let pixel_start_x = tile_x * tile_width;
         let pixel_start_y = tile_y * tile_height;
 
+        // the last tiles of a row or column may be cut off by the image border
+        let this_tile_width = tile_width.min(render.width - pixel_start_x);
+        let this_tile_height = tile_height.min(render.height - pixel_start_y);
+        let this_tile_pixels = this_tile_height * this_tile_width;
+
         let pixel_center_x = (pixel_start_x + this_tile_width / 2) as i32;
         let pixel_center_y = (pixel_start_y + this_tile_height / 2) as i32;
         let distance_from_center_x = (pixel_center_x - render.width as i32 / 2) as f32;
@@ -59,6 +102,8 @@ impl TileData {
         let distance_from_center =
             (distance_from_center_x.powi(2) + distance_from_center_y.powi(2)).sqrt();
 
+        let margin = render.filter.margin();
+
         Some(TileData {
             idx,
             distance_from_center,
@@ -67,102 +112,283 @@ impl TileData {
             pixel_x: pixel_start_x,
             pixel_y: pixel_start_y,
             remaining_samples: render.spp,
-            accum_buffer: vec![Xyz::new(0.0, 0.0, 0.0); this_tile_pixels],
-            temp_buffer: vec![(0.0, 0.0, 0.0); this_tile_pixels],
+            active_pixels: 0,
+            error: 0.0,
+            passes: 0,
+            margin,
+            sums: vec![PixelSum::ZERO; (this_tile_width + 2 * margin) * (this_tile_height + 2 * margin)],
+            spectral_buffer: vec![0.0; this_tile_pixels * render.spectral_bands],
+            aov_buffer: vec![(0.0, 0.0, 0.0); this_tile_pixels * render.aovs.len()],
+            moments: vec![PixelMoments::ZERO; this_tile_pixels],
         })
     }
 
+    // Whether the tile needs to be rendered again. With a time limit and no adaptive sampling
+    // it takes passes until the time is up.
+    pub fn is_done(&self, render: &Render) -> bool {
+        self.remaining_samples == 0
+            && match render.adaptive {
+                Some(_) => self.active_pixels == 0,
+                None => render.time_limit.is_none(),
+            }
+    }
+
+    // Whether the tile takes another pass once it comes up, given whether the time limit is
+    // up. Past it the tiles still take their base samples so that none is left black, but
+    // no more passes.
+    pub fn wants_pass(&self, out_of_time: bool) -> bool {
+        self.remaining_samples > 0 || !out_of_time
+    }
+
+    // Renders one pass. The base samples are split into passes doubling the samples taken so
+    // far, 1, 2, 4, ... spp in total, so that the whole image sharpens at once and a pass
+    // stays short. Then, with adaptive sampling or a time limit, every pass takes up to that
+    // many more in the pixels that have not converged. The passes only depend on what the
+    // tile rendered before, so that a resumed render takes the same ones.
     pub fn render(mut self, render: &Render) -> Self {
-        // use time::Instant;
-        // let start_time = Instant::now();
-
-        // The below is needed for progressive rendering
-        while self.remaining_samples > 0
-        //&& Instant::now()
-        //.saturating_duration_since(start_time)
-        //.as_secs_f32()
-        //<= 0.1
-        {
-            // let new_remaining_samples =
-            // self.remaining_samples.saturating_sub(SAMPLE_CHUNK_SIZE);
-            let new_remaining_samples = 0;
-            let samples_this_iter = self.remaining_samples - new_remaining_samples;
-            let weight = render.spp as f32 / ((render.spp - new_remaining_samples) as f32);
-
-            for (i, (accumulator, pixel)) in self
-                .accum_buffer
-                .iter_mut()
-                .zip(self.temp_buffer.iter_mut())
-                .enumerate()
-            {
-                let xyz = get_pixel_color(
-                    self.pixel_x + i % self.width,
-                    self.pixel_y + i / self.width,
-                    samples_this_iter,
-                    render.spp - self.remaining_samples,
-                    render,
-                );
-
-                *accumulator += xyz;
-
-                let rgb = (*accumulator * weight).to_rgb_hdr();
-                *pixel = (rgb.0.max(0.0), rgb.1.max(0.0), rgb.2.max(0.0));
+        let adaptive_pass = self.remaining_samples == 0;
+        let samples_this_pass = if adaptive_pass {
+            render.spp
+        } else {
+            (render.spp - self.remaining_samples)
+                .clamp(1, MAX_PASS_SPP)
+                .min(self.remaining_samples)
+        };
+
+        let bands = render.spectral_bands;
+        let aovs = render.aovs.len();
+        let mut pass_sums = vec![PixelSum::ZERO; self.sums.len()];
+        let (sums, margin) = (&mut pass_sums, self.margin as i64);
+        let (sums_x, sums_y) = (self.pixel_x as i64 - margin, self.pixel_y as i64 - margin);
+        let sums_width = self.width as i64 + 2 * margin;
+        let sums_height = self.height as i64 + 2 * margin;
+        let filter = render.filter;
+
+        // adds a sample at (x, y) in pixel units to every pixel in reach of the filter
+        let mut splat = |x: f32, y: f32, xyz: Xyz| {
+            let (x_min, x_max) = filter.pixel_range(x);
+            let (y_min, y_max) = filter.pixel_range(y);
+            for py in y_min.max(sums_y)..=y_max.min(sums_y + sums_height - 1) {
+                let dy = py as f32 + 0.5 - y;
+                for px in x_min.max(sums_x)..=x_max.min(sums_x + sums_width - 1) {
+                    let weight = filter.evaluate(px as f32 + 0.5 - x, dy);
+                    let sum = &mut sums[((py - sums_y) * sums_width + px - sums_x) as usize];
+                    sum.xyz += xyz * weight;
+                    sum.weight += weight;
+                }
             }
+        };
+
+        for i in 0..self.width * self.height {
+            let samples_so_far = self.moments[i].count as usize;
+            let samples = match render.adaptive {
+                Some(adaptive) if adaptive_pass => {
+                    if adaptive.is_converged(&self.moments[i]) {
+                        continue;
+                    }
+                    samples_this_pass.min(adaptive.max_spp - samples_so_far)
+                }
+                _ => samples_this_pass,
+            };
+
+            let pixel = PixelOutput {
+                bands: &mut self.spectral_buffer[i * bands..(i + 1) * bands],
+                aovs: &mut self.aov_buffer[i * aovs..(i + 1) * aovs],
+                moments: &mut self.moments[i],
+            };
+            render_pixel(
+                self.pixel_x + i % self.width,
+                self.pixel_y + i / self.width,
+                samples,
+                samples_so_far,
+                render,
+                &mut splat,
+                pixel,
+            );
+        }
+        if !adaptive_pass {
+            self.remaining_samples -= samples_this_pass;
+        }
+        self.passes += 1;
 
-            self.remaining_samples = new_remaining_samples;
+        if let Some(adaptive) = render.adaptive {
+            let active = self.moments.iter().filter(|moments| !adaptive.is_converged(moments));
+            self.active_pixels = active.clone().count();
+            self.error = active.map(PixelMoments::relative_variance).sum();
         }
 
+        for (sum, pass_sum) in self.sums.iter_mut().zip(&pass_sums) {
+            sum.xyz += pass_sum.xyz;
+            sum.weight += pass_sum.weight;
+        }
+        self.add_to_render(&pass_sums, render);
+        self.copy_to_render(render);
+        self
+    }
+
+    // Rebuilds the sums and buffers of the render from the given tiles, all of them, in the
+    // order of their index. As each tile adds its passes in a fixed order the image does
+    // not depend on the order the tiles were rendered in, nor on the number of threads.
+    pub fn resolve(tiles: &mut [TileData], render: &Render) {
+        tiles.sort_by_key(|tile| tile.idx);
+        for sum in render.pixel_sums.write().unwrap().iter_mut() {
+            *sum = PixelSum::ZERO;
+        }
+        for tile in tiles.iter() {
+            tile.add_to_render(&tile.sums, render);
+            tile.copy_to_render(render);
+        }
+    }
+
+    // Adds sums over the tile and its margin to those of the render and updates the
+    // affected pixels of the render buffer. Pixels near tiles that are not done yet are
+    // incomplete until they are.
+    fn add_to_render(&self, sums: &[PixelSum], render: &Render) {
+        let margin = self.margin;
+        let sums_width = self.width + 2 * margin;
+        let x_start = self.pixel_x.saturating_sub(margin);
+        let x_end = (self.pixel_x + self.width + margin).min(render.width);
+        let y_start = self.pixel_y.saturating_sub(margin);
+        let y_end = (self.pixel_y + self.height + margin).min(render.height);
+
+        let mut pixel_sums = render.pixel_sums.write().unwrap();
         let mut render_buffer = render.buffer.write().unwrap();
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                let tile_sum = sums[(y + margin - self.pixel_y) * sums_width + x + margin - self.pixel_x];
+                let sum = &mut pixel_sums[y * render.width + x];
+                sum.xyz += tile_sum.xyz;
+                sum.weight += tile_sum.weight;
+                render_buffer[y * render.width + x] = sum.to_rgb(render.color_space);
+            }
+        }
+    }
+
+    // The other buffers of the tile hold everything rendered in its pixels so far and
+    // replace those of the render
+    fn copy_to_render(&self, render: &Render) {
+        // the spectral film and the averaged AOVs are divided by the sample count of the pixel
+        let pixel_index = |i: usize| (self.pixel_y + i / self.width) * render.width + self.pixel_x + i % self.width;
+        let counts = self.moments.iter().map(|moments| moments.count.max(1) as f32);
+
+        if render.spectral_bands > 0 {
+            let bands = render.spectral_bands;
+            let mut spectral_buffer = render.spectral_buffer.write().unwrap();
+            for (i, count) in counts.clone().enumerate() {
+                let abs = pixel_index(i) * bands;
+                for band in 0..bands {
+                    spectral_buffer[abs + band] = self.spectral_buffer[i * bands + band] / count;
+                }
+            }
+        }
 
+        let mut moments = render.moments.write().unwrap();
         for i in 0..self.height {
             let abs = (self.pixel_y + i) * render.width + self.pixel_x;
-            render_buffer[abs..(abs + self.width)]
-                .copy_from_slice(&self.temp_buffer[(i * self.width)..((i + 1) * self.width)]);
+            moments[abs..(abs + self.width)]
+                .copy_from_slice(&self.moments[(i * self.width)..((i + 1) * self.width)]);
+        }
+        drop(moments);
+
+        if !render.aovs.is_empty() {
+            let aovs = render.aovs.len();
+            let mut aov_buffer = render.aov_buffer.write().unwrap();
+            for (i, count) in counts.enumerate() {
+                let abs = pixel_index(i) * aovs;
+                for (k, &aov) in render.aovs.iter().enumerate() {
+                    let value = self.aov_buffer[i * aovs + k];
+                    aov_buffer[abs + k] = if aov.is_averaged() {
+                        (value.0 / count, value.1 / count, value.2 / count)
+                    } else {
+                        value
+                    };
+                }
+            }
         }
-
-        self
     }
 }
 
-pub fn get_pixel_color(
+// What the samples of a pixel add to besides the image: its spectral film, AOVs and moments
+pub struct PixelOutput<'a> {
+    pub bands: &'a mut [f32],
+    pub aovs: &'a mut [(f32, f32, f32)],
+    pub moments: &'a mut PixelMoments,
+}
+
+// Traces the samples of a pixel and splats them with `splat(x, y, xyz)`, where (x, y) is
+// the position of the sample in pixel units. The rest is added to `pixel`.
+pub fn render_pixel<F: FnMut(f32, f32, Xyz)>(
     x_abs: usize,
     y_abs: usize,
     samples_this_iter: usize,
     samples_so_far: usize,
     render: &Render,
-) -> Xyz {
-    let pixel_center_clip = Point3::new(
-        ((x_abs as f32 + 0.5) / (render.width as f32) - 0.5) * 2.0,
-        ((y_abs as f32 + 0.5) / (render.height as f32) - 0.5) * -2.0,
-        0.0,
-    );
-
-    let weight = 1.0 / render.spp as f32;
-
-    let mut xyz_sum = Xyz::new(0.0, 0.0, 0.0);
+    splat: &mut F,
+    pixel: PixelOutput,
+) {
+    // the bundle size is only known at runtime, pick the matching instantiation
+    match render.wavelengths {
+        1 => render_pixel_lanes::<VecN<1>, F>(x_abs, y_abs, samples_this_iter, samples_so_far, render, splat, pixel),
+        2 => render_pixel_lanes::<VecN<2>, F>(x_abs, y_abs, samples_this_iter, samples_so_far, render, splat, pixel),
+        4 => render_pixel_lanes::<Vec4, F>(x_abs, y_abs, samples_this_iter, samples_so_far, render, splat, pixel),
+        8 => render_pixel_lanes::<VecN<8>, F>(x_abs, y_abs, samples_this_iter, samples_so_far, render, splat, pixel),
+        16 => render_pixel_lanes::<VecN<16>, F>(x_abs, y_abs, samples_this_iter, samples_so_far, render, splat, pixel),
+        n => panic!("unsupported wavelength bundle size {}", n),
+    }
+}
 
+fn render_pixel_lanes<L: Lanes, F: FnMut(f32, f32, Xyz)>(
+    x_abs: usize,
+    y_abs: usize,
+    samples_this_iter: usize,
+    samples_so_far: usize,
+    render: &Render,
+    splat: &mut F,
+    pixel: PixelOutput,
+) {
+    let PixelOutput { bands, aovs, moments } = pixel;
     for i in 0..samples_this_iter {
-        let mut sampler = Sampler::new(x_abs, y_abs, i + samples_so_far, SEED);
+        let mut sampler = Sampler::new(x_abs, y_abs, i + samples_so_far, render.seed);
 
-        let hero_wavelength = Wavelength::sample(&mut sampler);
+        let hero_wavelength = Wavelength::<L>::sample(&mut sampler, render.wavelength_sampling);
 
-        let jitter_clip = Vec3::new(
-            0.5 * sampler.gen_0_1() / render.width as f32,
-            0.5 * sampler.gen_0_1() / render.height as f32,
+        // anywhere in the pixel, in pixel units
+        let x = x_abs as f32 + sampler.gen_0_1();
+        let y = y_abs as f32 + sampler.gen_0_1();
+        let target_clip = Point3::new(
+            (x / render.width as f32 - 0.5) * 2.0,
+            (y / render.height as f32 - 0.5) * -2.0,
             0.0,
         );
 
-        let target_world = &render.camera.clip_to_world * (pixel_center_clip + jitter_clip);
+        let target_world = &render.camera.clip_to_world * target_clip;
         let origin_world = render.camera.position;
         let ray = Ray::new(origin_world, target_world - origin_world);
 
-        xyz_sum += render
+        let mut first_hit = FirstHit::miss();
+        let first_hit_aovs = if aovs.is_empty() { None } else { Some(&mut first_hit) };
+        let radiance = render
             .integrator
-            .radiance(&render.scene, ray, hero_wavelength, &mut sampler)
-            .to_xyz(hero_wavelength);
-    }
+            .radiance(&render.scene, ray, hero_wavelength, &mut sampler, first_hit_aovs);
+        let xyz = radiance.to_xyz(hero_wavelength);
+        splat(x, y, xyz);
+        moments.add(xyz.to_rgb(render.color_space), xyz.y());
+
+        for (&aov, pixel) in render.aovs.iter().zip(aovs.iter_mut()) {
+            let value = first_hit.value(aov, render.color_space);
+            if aov.is_averaged() {
+                *pixel = (pixel.0 + value.0, pixel.1 + value.1, pixel.2 + value.2);
+            } else if i + samples_so_far == 0 {
+                *pixel = value;
+            }
+        }
 
-    xyz_sum * weight
+        if !bands.is_empty() {
+            // the film holds the mean over each band, per nm, and is not filtered
+            let band_width = LAMBDA_RANGE_NM / bands.len() as f32;
+            radiance.add_to_bands(hero_wavelength, 1.0 / band_width, bands);
+        }
+    }
 }
 
 impl PartialEq for TileData {
@@ -181,12 +407,211 @@ impl PartialOrd for TileData {
 
 impl Ord for TileData {
     fn cmp(&self, other: &Self) -> Ordering {
-        // Render from the inside out
-        self.remaining_samples.cmp(&other.remaining_samples).then(
-            self.distance_from_center
-                .partial_cmp(&other.distance_from_center)
-                .unwrap()
-                .reverse(),
-        )
+        // Render from the inside out, the adaptive passes from the largest error down and
+        // the others from the fewest passes up
+        self.remaining_samples
+            .cmp(&other.remaining_samples)
+            // a NaN sample makes the error NaN, which must not panic with the heap locked
+            .then(self.error.total_cmp(&other.error))
+            .then(self.passes.cmp(&other.passes).reverse())
+            .then(
+                self.distance_from_center
+                    .total_cmp(&other.distance_from_center)
+                    .reverse(),
+            )
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{
+        bsdf::LambertianBsdf,
+        camera::Camera,
+        filter::{Filter, FilterKind},
+        integrator::AnyIntegrator,
+        scene::Scene,
+        shape::Sphere,
+        spectrum::{ConstantSpectrum, WavelengthSampling},
+    };
+    use std::{collections::BinaryHeap, sync::RwLock, time::Duration};
+
+    // A light over a floor in a 12x8 image of 16 tiles, with a filter reaching into the
+    // tiles around
+    pub fn small_render(spp: usize) -> Render {
+        let mut scene = Scene::default();
+        scene.add_light(Sphere::new(Point3::new(0.0, 0.5, 3.0), 0.5), ConstantSpectrum::new(4.0));
+        scene.add_material(
+            Sphere::new(Point3::new(0.0, -101.0, 3.0), 100.0),
+            LambertianBsdf::new(ConstantSpectrum::new(0.5)),
+        );
+        scene.build_bvh();
+
+        let (width, height) = (12, 8);
+        Render {
+            width,
+            height,
+            spp,
+            scene,
+            camera: Camera::new(Point3::new(0.0, 0.0, 0.0), width as f32 / height as f32),
+            buffer: RwLock::new(vec![(0.0, 0.0, 0.0); width * height]),
+            pixel_sums: RwLock::new(vec![PixelSum::ZERO; width * height]),
+            filter: Filter::new(FilterKind::Gaussian),
+            spectral_bands: 2,
+            spectral_buffer: RwLock::new(vec![0.0; width * height * 2]),
+            aovs: Vec::new(),
+            aov_buffer: RwLock::new(Vec::new()),
+            moments: RwLock::new(vec![PixelMoments::ZERO; width * height]),
+            save_variance: false,
+            adaptive: None,
+            time_limit: None,
+            checkpoint_interval: None,
+            resume: false,
+            seed: DEFAULT_SEED,
+            save_sums: false,
+            integrator: AnyIntegrator::default(),
+            wavelengths: 4,
+            wavelength_sampling: WavelengthSampling::default(),
+            color_space: ColorSpace::Srgb,
+            display: Default::default(),
+            exr_options: Default::default(),
+            bvh_build_time: Duration::from_secs(0),
+        }
+    }
+
+    // Samples per pixel taken by each pass of a tile until it is done
+    fn pass_sizes(render: &Render) -> Vec<u32> {
+        let mut tile = TileData::new(render, 0).unwrap();
+        let mut sizes = Vec::new();
+        while !tile.is_done(render) {
+            let before = tile.moments[0].count;
+            tile = tile.render(render);
+            sizes.push(tile.moments[0].count - before);
+        }
+        sizes
+    }
+
+    fn empty_tile(idx: usize) -> TileData {
+        TileData {
+            idx,
+            width: 2,
+            height: 1,
+            pixel_x: 2 * idx,
+            pixel_y: 0,
+            distance_from_center: idx as f32,
+            remaining_samples: 0,
+            active_pixels: 1,
+            error: 0.0,
+            passes: 1,
+            margin: 0,
+            sums: vec![PixelSum::ZERO; 2],
+            spectral_buffer: Vec::new(),
+            aov_buffer: Vec::new(),
+            moments: vec![PixelMoments::ZERO; 2],
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let mut heap = BinaryHeap::new();
+        for (idx, &error) in [0.5, f64::NAN, 2.0, f64::INFINITY].iter().enumerate() {
+            heap.push(TileData { error, ..empty_tile(idx) });
+        }
+        let mut fresh = empty_tile(4);
+        fresh.remaining_samples = 4;
+        heap.push(fresh);
+
+        // the base samples first, then from the largest error down with NaN at the top
+        let order = std::iter::from_fn(|| heap.pop()).map(|tile| tile.idx).collect::<Vec<_>>();
+        assert_eq!(order, [4, 1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn test_pass_schedule() {
+        // doubling the samples so far, up to the largest pass
+        assert_eq!(pass_sizes(&small_render(1)), [1]);
+        assert_eq!(pass_sizes(&small_render(13)), [1, 1, 2, 4, 5]);
+        let sizes = pass_sizes(&small_render(300));
+        assert_eq!(sizes, [1, 1, 2, 4, 8, 16, 32, 64, 64, 64, 44]);
+        assert_eq!(sizes.iter().sum::<u32>(), 300);
+    }
+
+    #[test]
+    fn test_passes_add_up() {
+        // the image of the tiles rendered in passes
+        let render = small_render(24);
+        let mut tiles = (0..).map_while(|idx| TileData::new(&render, idx)).collect::<Vec<_>>();
+        assert_eq!(tiles.len(), 16);
+        for tile in tiles.iter_mut() {
+            while !tile.is_done(&render) {
+                *tile = tile.clone().render(&render);
+            }
+        }
+        TileData::resolve(&mut tiles, &render);
+
+        // and of every pixel taking all its samples at once
+        let (width, height) = (render.width as i64, render.height as i64);
+        let mut sums = vec![PixelSum::ZERO; render.width * render.height];
+        let mut moments = vec![PixelMoments::ZERO; render.width * render.height];
+        for (i, moments) in moments.iter_mut().enumerate() {
+            let mut splat = |x: f32, y: f32, xyz: Xyz| {
+                let (x_min, x_max) = render.filter.pixel_range(x);
+                let (y_min, y_max) = render.filter.pixel_range(y);
+                for py in y_min.max(0)..=y_max.min(height - 1) {
+                    for px in x_min.max(0)..=x_max.min(width - 1) {
+                        let weight = render.filter.evaluate(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                        let sum = &mut sums[(py * width + px) as usize];
+                        sum.xyz += xyz * weight;
+                        sum.weight += weight;
+                    }
+                }
+            };
+            let pixel = PixelOutput {
+                bands: &mut [0.0; 2],
+                aovs: &mut [],
+                moments,
+            };
+            render_pixel(i % render.width, i / render.width, render.spp, 0, &render, &mut splat, pixel);
+        }
+
+        // take the same samples, only added up in another order
+        let pixel_sums = render.pixel_sums.read().unwrap();
+        let buffer = render.buffer.read().unwrap();
+        let close = |a: f32, b: f32| (a - b).abs() <= 1e-4 * a.abs().max(b.abs());
+        for (i, (sum, expected)) in pixel_sums.iter().zip(&sums).enumerate() {
+            assert!(close(sum.weight, expected.weight), "{} {:?} {:?}", i, sum, expected);
+            assert!(close(sum.xyz.y(), expected.xyz.y()), "{} {:?} {:?}", i, sum, expected);
+            assert_eq!(buffer[i], sum.to_rgb(render.color_space));
+        }
+        assert!(sums.iter().filter(|sum| sum.xyz.y() > 0.0).count() > sums.len() / 2);
+        let render_moments = render.moments.read().unwrap();
+        for (pixel, expected) in render_moments.iter().zip(&moments) {
+            assert_eq!(pixel.count, 24);
+            assert_eq!((pixel.sum, pixel.sum_squares), (expected.sum, expected.sum_squares));
+        }
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let mut render = small_render(4);
+        render.time_limit = Some(Duration::from_secs(1));
+
+        // before the deadline the tiles keep taking passes of `spp` after the base samples
+        let mut tile = TileData::new(&render, 5).unwrap();
+        for _ in 0..5 {
+            assert!(tile.wants_pass(false));
+            tile = tile.render(&render);
+            assert!(!tile.is_done(&render));
+        }
+        assert_eq!(tile.remaining_samples, 0);
+        assert!(tile.moments.iter().all(|moments| moments.count == 4 + 2 * 4));
+
+        // after it only the base samples
+        assert!(!tile.wants_pass(true));
+        let mut tile = TileData::new(&render, 5).unwrap();
+        while tile.wants_pass(true) {
+            tile = tile.render(&render);
+        }
+        assert!(tile.moments.iter().all(|moments| moments.count == 4));
     }
 }
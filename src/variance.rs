@@ -0,0 +1,143 @@
+// Per-pixel sample statistics, to compare estimators by their noise rather than by eye.
+// Every pixel keeps the count, sum and sum of squares of the samples taken in it, in the
+// RGB channels of the output color space and in luminance (CIE Y). They describe the mean
+// of the samples in the pixel, i.e. the image with the box filter.
+//
+// The relative variance of a pixel is the variance of its mean luminance divided by the
+// squared mean plus a small constant, as in Rousselle et al. (2011), so that dark pixels do
+// not dominate its mean over the image.
+
+// Added to the squared mean in the relative variance
+const RELATIVE_VARIANCE_EPSILON: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PixelMoments {
+    pub count: u32,
+    // R, G, B and Y
+    pub sum: [f64; 4],
+    pub sum_squares: [f64; 4],
+}
+
+impl PixelMoments {
+    pub const ZERO: PixelMoments = PixelMoments {
+        count: 0,
+        sum: [0.0; 4],
+        sum_squares: [0.0; 4],
+    };
+
+    pub fn add(&mut self, rgb: (f32, f32, f32), luminance: f32) {
+        self.count += 1;
+        for (i, &value) in [rgb.0, rgb.1, rgb.2, luminance].iter().enumerate() {
+            let value = value as f64;
+            self.sum[i] += value;
+            self.sum_squares[i] += value * value;
+        }
+    }
+
+    pub fn mean(&self, channel: usize) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sum[channel] / self.count as f64
+    }
+
+    // Unbiased estimate of the variance of the samples, zero with fewer than two
+    pub fn sample_variance(&self, channel: usize) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        let mean = self.sum[channel] / n;
+        ((self.sum_squares[channel] - n * mean * mean) / (n - 1.0)).max(0.0)
+    }
+
+    // Variance of the mean of the samples, the squared standard error
+    pub fn variance_of_mean(&self, channel: usize) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.sample_variance(channel) / self.count as f64
+    }
+
+    pub fn relative_variance(&self) -> f64 {
+        let mean = self.mean(3);
+        self.variance_of_mean(3) / (mean * mean + RELATIVE_VARIANCE_EPSILON)
+    }
+
+    // Variance of the mean per RGB channel
+    pub fn variance_rgb(&self) -> (f32, f32, f32) {
+        (
+            self.variance_of_mean(0) as f32,
+            self.variance_of_mean(1) as f32,
+            self.variance_of_mean(2) as f32,
+        )
+    }
+
+    // Standard error of the mean per RGB channel
+    pub fn standard_error_rgb(&self) -> (f32, f32, f32) {
+        let (r, g, b) = self.variance_rgb();
+        (r.sqrt(), g.sqrt(), b.sqrt())
+    }
+}
+
+// After the base samples, pixels keep getting more until the relative standard error of
+// their mean luminance is at most `threshold` or they have `max_spp` samples. With few
+// samples the variance estimate is unreliable, so the base should not be too small.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    pub threshold: f64,
+    pub max_spp: usize,
+}
+
+impl AdaptiveSampling {
+    pub fn is_converged(&self, moments: &PixelMoments) -> bool {
+        moments.count as usize >= self.max_spp || moments.relative_variance().sqrt() <= self.threshold
+    }
+}
+
+pub fn mean_relative_variance(moments: &[PixelMoments]) -> f64 {
+    if moments.is_empty() {
+        return 0.0;
+    }
+    moments.iter().map(PixelMoments::relative_variance).sum::<f64>() / moments.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moments() {
+        let mut moments = PixelMoments::ZERO;
+        assert_eq!(moments.variance_of_mean(0), 0.0);
+        moments.add((1.0, 0.0, 2.0), 1.0);
+        assert_eq!(moments.sample_variance(0), 0.0);
+
+        for &value in &[2.0, 3.0, 4.0] {
+            moments.add((value, 0.0, 2.0), value);
+        }
+        assert_eq!(moments.count, 4);
+        assert_eq!(moments.mean(0), 2.5);
+        assert!((moments.sample_variance(0) - 5.0 / 3.0).abs() < 1e-12);
+        assert!((moments.variance_of_mean(0) - 5.0 / 12.0).abs() < 1e-12);
+        assert_eq!(moments.variance_rgb().1, 0.0);
+        assert_eq!(moments.variance_rgb().2, 0.0);
+        assert!((moments.relative_variance() - (5.0 / 12.0) / (6.25 + 0.01)).abs() < 1e-12);
+
+        let constant = {
+            let mut moments = PixelMoments::ZERO;
+            for _ in 0..8 {
+                moments.add((0.5, 0.5, 0.5), 0.5);
+            }
+            moments
+        };
+        assert!(constant.relative_variance() < 1e-12);
+        let mrv = mean_relative_variance(&[moments, constant]);
+        assert!((mrv - moments.relative_variance() / 2.0).abs() < 1e-12);
+
+        let adaptive = AdaptiveSampling { threshold: 0.1, max_spp: 16 };
+        assert!(adaptive.is_converged(&constant));
+        assert!(!adaptive.is_converged(&moments));
+        assert!(AdaptiveSampling { max_spp: 4, ..adaptive }.is_converged(&moments));
+    }
+}